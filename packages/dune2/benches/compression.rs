@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use dune2_assets::prelude::*;
+
+
+const TILE_SIZE: Size = Size { width: 16, height: 16 };
+const TILE_COUNT: usize = 512;
+const TILESET_COUNT: usize = 8;
+const TILEMAP_SHAPE: Shape = Shape { columns: 64, rows: 64 };
+
+const COMPRESSIONS: [AssetsCompression; 4] = [
+    AssetsCompression::None,
+    AssetsCompression::Deflate,
+    AssetsCompression::Zstd,
+    AssetsCompression::Lz4,
+];
+
+fn tileset(
+    tileset_index: usize,
+) -> Tileset {
+    let mut tileset = Tileset::new(&format!("bench{tileset_index}"), TILE_SIZE);
+    let pixel_count = (TILE_SIZE.width*TILE_SIZE.height) as usize;
+
+    // Few colors with runs of pixels, closer to the game graphics than
+    // random data
+    for tile_index in 0..TILE_COUNT {
+        let data = (0..pixel_count)
+            .map(|i| ((i/5 + tile_index*3 + tileset_index)%24) as u8)
+            .collect::<Vec<u8>>();
+
+        tileset.add(Tile::new(&data, TILE_SIZE)).unwrap();
+    }
+    tileset
+}
+
+fn assets() -> Assets {
+    let tile_count = (TILEMAP_SHAPE.columns*TILEMAP_SHAPE.rows) as usize;
+
+    Assets {
+        palette: Palette::new(),
+        tilesets: (0..TILESET_COUNT)
+            .map(|index| {
+                let tileset = tileset(index);
+                (tileset.get_id().into(), tileset)
+            })
+            .collect(),
+        tilemaps: vec![Tilemap {
+            class: "bench".into(),
+            shape: TILEMAP_SHAPE,
+            tiles: (0..tile_count).map(|i| (i*7)%TILE_COUNT).collect(),
+            tileset: "bench0".into(),
+        }],
+        musics: HashMap::new(),
+        units: Vec::new(),
+        structures: Vec::new(),
+        sounds: HashMap::new(),
+        announcements: Vec::new(),
+        remap_tables: default_remap_tables(),
+        palette_cycles: Vec::new(),
+        tile_pool: TilePool::new(),
+    }
+}
+
+fn encode(
+    assets: &Assets,
+    compression: AssetsCompression,
+) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    assets.write_with_compression(&mut data, compression).unwrap();
+    data
+}
+
+/// Write the size of the file for each compression next to the bench
+/// results, criterion only records timings.
+fn write_sizes(
+    sizes: &[(AssetsCompression, usize)],
+) {
+    let filepath = Path::new(env!("CARGO_TARGET_TMPDIR")).join("compression-sizes.csv");
+    let mut csv = String::from("compression,bytes\n");
+
+    for (compression, size) in sizes {
+        csv.push_str(&format!("{compression:?},{size}\n"));
+    }
+    fs::write(filepath, csv).unwrap();
+}
+
+fn compression_benchmarks(c: &mut Criterion) {
+    let assets = assets();
+    let sizes = COMPRESSIONS
+        .map(|compression| (compression, encode(&assets, compression).len()));
+
+    write_sizes(&sizes);
+
+    // Throughput in uncompressed bytes so that the backends compare on the
+    // same amount of assets
+    let mut group = c.benchmark_group("compression");
+
+    group.sample_size(20);
+    group.throughput(Throughput::Bytes(encode(&assets, AssetsCompression::None).len() as u64));
+    for compression in COMPRESSIONS {
+        let data = encode(&assets, compression);
+
+        group.bench_function(format!("{compression:?} encode"), |b| b.iter(|| {
+            encode(black_box(&assets), compression)
+        }));
+        group.bench_function(format!("{compression:?} decode"), |b| b.iter(|| {
+            Assets::read_from(&mut black_box(data.as_slice())).unwrap()
+        }));
+        group.bench_function(format!("{compression:?} decode one tileset"), |b| b.iter(|| {
+            let mut assets = LazyAssets::from_bytes(black_box(data.clone())).unwrap();
+            assets.get_tileset("bench0").unwrap().tile_count()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compression_benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,126 @@
+use std::rc::Rc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use dune2_assets::prelude::*;
+
+
+const TILE_SIZE: Size = Size { width: 16, height: 16 };
+const TILE_COUNT: usize = 512;
+const TILESET_COLUMNS: u32 = 16;
+const TILEMAP_SHAPE: Shape = Shape { columns: 64, rows: 64 };
+
+fn palette() -> Palette {
+    let mut palette = Palette::new();
+
+    for index in 1..PALETTE_SIZE {
+        let color = Color::new(index as u8, (3*index) as u8, (7*index) as u8);
+        palette.set_color(index, &color).unwrap();
+    }
+    palette
+}
+
+fn tileset() -> Tileset {
+    let mut tileset = Tileset::new("bench", TILE_SIZE);
+    let pixel_count = (TILE_SIZE.width*TILE_SIZE.height) as usize;
+
+    for tile_index in 0..TILE_COUNT {
+        let data = (0..pixel_count)
+            .map(|i| (i*31 + tile_index*17) as u8)
+            .collect::<Vec<u8>>();
+
+        tileset.add(Tile::new(&data, TILE_SIZE)).unwrap();
+    }
+    tileset
+}
+
+fn tilemap() -> Tilemap {
+    let tile_count = (TILEMAP_SHAPE.columns*TILEMAP_SHAPE.rows) as usize;
+
+    Tilemap {
+        class: "bench".into(),
+        shape: TILEMAP_SHAPE,
+        tiles: (0..tile_count).map(|i| (i*7)%TILE_COUNT).collect(),
+        tileset: "bench".into(),
+    }
+}
+
+fn render_tileset(
+    tileset: &Tileset,
+    palette: &Rc<FactionPalette>,
+    scale: u32,
+) -> RgbaBitmap {
+    let rows = (TILE_COUNT as u32).div_ceil(TILESET_COLUMNS);
+    let tile_size = scale*TILE_SIZE;
+    let mut dst = RgbaBitmap::new(Size {
+        width: TILESET_COLUMNS*tile_size.width,
+        height: rows*tile_size.height,
+    });
+
+    for (index, tile) in tileset.tile_iter().enumerate() {
+        let index = index as u32;
+        let src = TileBitmap::with_faction_palette(tile, palette.clone());
+        let dst_rect = Rect::from_point_and_size(
+            Point {
+                x: ((index%TILESET_COLUMNS)*tile_size.width) as i32,
+                y: ((index/TILESET_COLUMNS)*tile_size.height) as i32,
+            },
+            tile_size,
+        );
+
+        bitmap_blit(&src, &src.rect(), &mut dst, &dst_rect, BlitSizePolicy::Stretch);
+    }
+    dst
+}
+
+fn render_tilemap(
+    bitmap: &TilemapBitmap,
+    size: Size,
+) -> RgbaBitmap {
+    let mut dst = RgbaBitmap::new(size);
+    let dst_rect = dst.rect();
+
+    bitmap_blit(bitmap, &bitmap.rect(), &mut dst, &dst_rect, BlitSizePolicy::Stretch);
+    dst
+}
+
+fn tileset_benchmarks(c: &mut Criterion) {
+    let palette = Rc::new(FactionPalette::new(&palette(), None));
+    let tileset = tileset();
+    let mut group = c.benchmark_group("tileset");
+
+    group.bench_function("scale 1", |b| b.iter(|| {
+        render_tileset(black_box(&tileset), &palette, 1)
+    }));
+    group.bench_function("scale 3", |b| b.iter(|| {
+        render_tileset(black_box(&tileset), &palette, 3)
+    }));
+    group.finish();
+}
+
+fn tilemap_benchmarks(c: &mut Criterion) {
+    let palette = Rc::new(FactionPalette::new(&palette(), None));
+    let tileset = tileset();
+    let tilemap = tilemap();
+    let bitmap = TilemapBitmap::try_with_tileset(&tilemap, &tileset, palette).unwrap();
+    let size = bitmap.size();
+    let mut group = c.benchmark_group("tilemap");
+
+    group.sample_size(20);
+    group.bench_function("64x64 scale 1", |b| b.iter(|| {
+        render_tilemap(black_box(&bitmap), size)
+    }));
+    group.bench_function("64x64 scale 2", |b| b.iter(|| {
+        render_tilemap(black_box(&bitmap), 2u32*size)
+    }));
+    group.bench_function("64x64 scale 1.5", |b| b.iter(|| {
+        render_tilemap(black_box(&bitmap), Size {
+            width: 3*size.width/2,
+            height: 3*size.height/2,
+        })
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, tileset_benchmarks, tilemap_benchmarks);
+criterion_main!(benches);
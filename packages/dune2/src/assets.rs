@@ -4,30 +4,68 @@ use std::io::{
     Write,
 };
 
-use flate2::Compression;
 use flate2::read::DeflateDecoder;
-use flate2::write::DeflateEncoder;
 
 use serde::{Deserialize, Serialize};
 
 use rmp_serde;
 
+use crate::container::{
+    decode,
+    encode,
+    write_chunks,
+};
 use crate::prelude::{
+    default_remap_tables,
+    AssetsChunk,
+    AssetsCompression,
+    AssetsHeader,
+    voice_prefix,
+    Announcement,
+    Color,
     Dune2Faction,
     Error,
+    FactionPalette,
+    LazyAssets,
+    Music,
     Palette,
+    PaletteCycle,
+    RemapTable,
     Result,
+    Sound,
+    StructureInfo,
     TileBitmap,
+    TilePool,
     Tilemap,
     Tileset,
+    UnitInfo,
 };
+use crate::utils::serialize_sorted_map;
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Assets {
     pub palette: Palette,
+    #[serde(serialize_with = "serialize_sorted_map")]
     pub tilesets: HashMap<String, Tileset>,
     pub tilemaps: Vec<Tilemap>,
+    #[serde(default, serialize_with = "serialize_sorted_map")]
+    pub musics: HashMap<String, Music>,
+    #[serde(default)]
+    pub units: Vec<UnitInfo>,
+    #[serde(default)]
+    pub structures: Vec<StructureInfo>,
+    #[serde(default, serialize_with = "serialize_sorted_map")]
+    pub sounds: HashMap<String, Sound>,
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    #[serde(default = "default_remap_tables", serialize_with = "serialize_sorted_map")]
+    pub remap_tables: HashMap<String, RemapTable>,
+    #[serde(default)]
+    pub palette_cycles: Vec<PaletteCycle>,
+    /// Tiles shared by the tilesets using the pool.
+    #[serde(default)]
+    pub tile_pool: TilePool,
 }
 
 impl Assets {
@@ -40,6 +78,58 @@ impl Assets {
             .ok_or(Error::TilesetInvalidId(tileset_id.into()))
     }
 
+    pub fn get_remap_table(
+        &self,
+        remap_table_id: &str,
+    ) -> Result<&RemapTable> {
+        self.remap_tables
+            .get(remap_table_id)
+            .ok_or(Error::RemapTableInvalidId(remap_table_id.into()))
+    }
+
+    /// Palette remapped with the table of the given faction. Factions without
+    /// a remap table use the palette as is.
+    pub fn faction_palette(
+        &self,
+        faction_id: Option<&str>,
+    ) -> FactionPalette {
+        let remap_table = faction_id.and_then(|id| self.remap_tables.get(id));
+        FactionPalette::new(&self.palette, remap_table)
+    }
+
+    /// Palette animated by the palette cycles at the given tick.
+    pub fn palette_at(
+        &self,
+        tick: u32,
+    ) -> Result<Palette> {
+        let mut palette = self.palette.clone();
+
+        for cycle in self.palette_cycles.iter() {
+            cycle.apply(&self.palette, &mut palette, tick)?;
+        }
+        Ok(palette)
+    }
+
+    /// Same as `faction_palette` with the palette animated at the given
+    /// tick.
+    pub fn faction_palette_at(
+        &self,
+        faction_id: Option<&str>,
+        tick: u32,
+    ) -> Result<FactionPalette> {
+        let remap_table = faction_id.and_then(|id| self.remap_tables.get(id));
+        Ok(FactionPalette::new(&self.palette_at(tick)?, remap_table))
+    }
+
+    /// Palette where the faction colors are replaced by the shades of the
+    /// given team color.
+    pub fn team_color_palette(
+        &self,
+        team_color: &Color,
+    ) -> FactionPalette {
+        FactionPalette::with_team_color(&self.palette, team_color)
+    }
+
     pub fn get_tile_bitmap(
         &self,
         tileset_id: &str,
@@ -51,23 +141,230 @@ impl Assets {
 
         Ok(TileBitmap::with_assets(tile, faction, self))
     }
+
+    pub fn get_music(
+        &self,
+        music_id: &str,
+    ) -> Result<&Music> {
+        self.musics
+            .get(music_id)
+            .ok_or(Error::MusicInvalidId(music_id.into()))
+    }
+
+    pub fn get_sound(
+        &self,
+        sound_id: &str,
+    ) -> Result<&Sound> {
+        self.sounds
+            .get(sound_id)
+            .ok_or(Error::SoundInvalidId(sound_id.into()))
+    }
+
+    pub fn get_announcement(
+        &self,
+        announcement_id: &str,
+    ) -> Result<&Announcement> {
+        self.announcements
+            .iter()
+            .find(|announcement| announcement.id == announcement_id)
+            .ok_or(Error::AnnouncementInvalidId(announcement_id.into()))
+    }
+
+    /// Assemble the voice fragments of a faction into a single sound. Each
+    /// fragment is resampled to `sample_rate` and fragments are separated by
+    /// `gap` seconds of silence.
+    pub fn announce<S: AsRef<str>>(
+        &self,
+        faction: Dune2Faction,
+        fragments: &[S],
+        sample_rate: u32,
+        gap: f32,
+    ) -> Result<Sound> {
+        let prefix = voice_prefix(faction)?;
+        let gap = Sound::silence(sample_rate, gap);
+        let mut samples = Vec::new();
+
+        for (index, fragment) in fragments.iter().enumerate() {
+            let sound_id = format!("{}{}", prefix, fragment.as_ref());
+            let sound = self.get_sound(&sound_id)?.resample(sample_rate);
+
+            if index > 0 {
+                samples.extend_from_slice(gap.samples());
+            }
+            samples.extend_from_slice(sound.samples());
+        }
+
+        Ok(Sound::new(sample_rate, &samples))
+    }
 }
 
 impl Assets {
+    /// Read an assets file of the current format version or of an older
+    /// one. The assets layout did not change with the versions, version 1
+    /// added the header and version 2 the chunks. The fields added over time
+    /// are defaulted and the tiles stored one by one are moved to the
+    /// tileset buffers when deserializing, no conversion step is needed.
     pub fn read_from<R: Read>(
         reader: &mut R,
-    ) -> core::result::Result<Assets, rmp_serde::decode::Error> {
-        let mut inflate_reader = DeflateDecoder::new(reader);
-        rmp_serde::decode::from_read(&mut inflate_reader)
+    ) -> Result<Assets> {
+        let mut head = AssetsHeader::read_bytes(reader)?;
+
+        let mut assets = match AssetsHeader::parse(&head)? {
+            None => Self::read_unversioned(&mut head.as_slice().chain(reader))?,
+            Some(header) if header.version == 1 => {
+                decode(header.compression, &header.read_payload(reader)?)?
+            },
+            Some(header) if header.version == 2 => {
+                reader
+                    .read_to_end(&mut head)
+                    .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))?;
+
+                return LazyAssets::from_bytes(head)?.into_assets();
+            },
+            Some(header) => return Err(Error::AssetsUnsupportedVersion(header.version)),
+        };
+
+        assets.link_tile_pool()?;
+        Ok(assets)
+    }
+
+    /// Files written before the format was versioned are a bare deflate
+    /// compressed payload. The fields added over time are defaulted and the
+    /// tiles stored one by one are moved to the tileset buffers while
+    /// decoding.
+    fn read_unversioned<R: Read>(
+        reader: &mut R,
+    ) -> Result<Assets> {
+        rmp_serde::decode::from_read(DeflateDecoder::new(reader))
+            .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))
+    }
+
+    /// Resolve the pool references of the tilesets, needed when the assets
+    /// are deserialized from another format than the assets file.
+    pub fn link_tile_pool(&mut self) -> Result<()> {
+        for tileset in self.tilesets.values_mut() {
+            tileset.link_tile_pool(&self.tile_pool)?;
+        }
+        Ok(())
     }
 }
 
 impl Assets {
+    /// Write the assets with the current format version and the default
+    /// compression, each tileset being stored in its own chunk.
     pub fn write_to<W: Write>(
         &self,
         writer: &mut W,
-    ) -> core::result::Result<(), rmp_serde::encode::Error> {
-        let mut output = DeflateEncoder::new(writer, Compression::best());
-        rmp_serde::encode::write(&mut output, self)
+    ) -> Result<()> {
+        self.write_with_compression(writer, AssetsCompression::default())
+    }
+
+    pub fn write_with_compression<W: Write>(
+        &self,
+        writer: &mut W,
+        compression: AssetsCompression,
+    ) -> Result<()> {
+        // No rest pattern, so that a new field cannot be left out of the
+        // chunks
+        let Assets {
+            palette,
+            tilesets,
+            tilemaps,
+            musics,
+            units,
+            structures,
+            sounds,
+            announcements,
+            remap_tables,
+            palette_cycles,
+            tile_pool,
+        } = self;
+
+        let mut chunks = vec![
+            (AssetsChunk::Palette, encode(compression, palette)?),
+            (AssetsChunk::Tilemaps, encode(compression, tilemaps)?),
+            (AssetsChunk::TilePool, encode(compression, tile_pool)?),
+        ];
+
+        let mut tileset_ids = tilesets.keys().collect::<Vec<&String>>();
+        tileset_ids.sort();
+
+        for tileset_id in tileset_ids {
+            chunks.push((
+                AssetsChunk::Tileset(tileset_id.clone()),
+                encode(compression, &tilesets[tileset_id])?,
+            ));
+        }
+
+        chunks.extend([
+            (AssetsChunk::Musics, encode(compression, musics)?),
+            (AssetsChunk::Units, encode(compression, units)?),
+            (AssetsChunk::Structures, encode(compression, structures)?),
+            (AssetsChunk::Sounds, encode(compression, sounds)?),
+            (AssetsChunk::Announcements, encode(compression, announcements)?),
+            (AssetsChunk::RemapTables, encode(compression, remap_tables)?),
+            (AssetsChunk::PaletteCycles, encode(compression, palette_cycles)?),
+        ]);
+
+        write_chunks(writer, compression, &chunks)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use crate::prelude::{
+        Shape,
+        Size,
+        Tile,
+    };
+
+    const TILE_SIZE: Size = Size { width: 2, height: 2 };
+
+    /// Small assets with an owned and a pooled tileset.
+    pub(crate) fn assets() -> Assets {
+        let mut owned = Tileset::new("owned", TILE_SIZE);
+        let mut pooled = Tileset::new("pooled", TILE_SIZE);
+        let mut tile_pool = TilePool::new();
+
+        for index in 0..3 {
+            owned.add(Tile::new(&[index, 1, 2, 3], TILE_SIZE)).unwrap();
+            pooled.add(Tile::new(&[index%2, 4, 5, 6], TILE_SIZE)).unwrap();
+        }
+
+        pooled.move_to_pool(&mut tile_pool).unwrap();
+        pooled.link_tile_pool(&tile_pool).unwrap();
+
+        Assets {
+            palette: Palette::new(),
+            tilesets: HashMap::from([
+                (owned.get_id().into(), owned),
+                (pooled.get_id().into(), pooled),
+            ]),
+            tilemaps: vec![Tilemap {
+                class: "map".into(),
+                shape: Shape { rows: 1, columns: 3 },
+                tiles: [0, 2, 1].into(),
+                tileset: "pooled".into(),
+            }],
+            musics: HashMap::new(),
+            units: Vec::new(),
+            structures: Vec::new(),
+            sounds: HashMap::new(),
+            announcements: Vec::new(),
+            remap_tables: default_remap_tables(),
+            palette_cycles: Vec::new(),
+            tile_pool,
+        }
+    }
+
+    /// Assets are compared by their serialization, the maps of which are
+    /// sorted.
+    pub(crate) fn assert_same_assets(
+        a: &Assets,
+        b: &Assets,
+    ) {
+        assert_eq!(rmp_serde::to_vec(a).unwrap(), rmp_serde::to_vec(b).unwrap());
     }
 }
@@ -1,8 +1,21 @@
+use std::rc::Rc;
+
 pub use crate::color::*;
 pub use crate::point::*;
 pub use crate::rect::*;
 pub use crate::size::*;
 
+use crate::prelude::{
+    ColorMatcher,
+    ColorMetric,
+    Dithering,
+    Error,
+    Result,
+    Tile,
+    TileAnchor,
+    TileView,
+};
+
 pub trait Bitmap {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
@@ -21,10 +34,362 @@ pub trait Bitmap {
 
 pub trait BitmapGetPixel {
     fn get_pixel(&self, p: Point) -> Option<Color>;
+
+    /// Palette index of the pixel, only available for indexed bitmaps.
+    fn get_palette_index(&self, _p: Point) -> Option<u8> {
+        None
+    }
+
+    /// Read the pixels of a row starting at the given point as RGBA values,
+    /// 4 bytes per pixel. Transparent and out of bounds pixels are all zeros,
+    /// opaque pixels have a 255 alpha.
+    fn get_rgba_row(&self, p: Point, row: &mut [u8]) {
+        for (i, rgba) in row.chunks_exact_mut(4).enumerate() {
+            let color = self.get_pixel(Point { x: p.x + i as i32, y: p.y });
+            rgba.copy_from_slice(&rgba_from_color(color));
+        }
+    }
 }
 
 pub trait BitmapPutPixel {
     fn put_pixel(&mut self, p: Point, color: Option<Color>) -> &mut Self;
+
+    /// Write a row of RGBA values starting at the given point, 4 bytes per
+    /// pixel, as read by `BitmapGetPixel::get_rgba_row`.
+    fn put_rgba_row(&mut self, p: Point, row: &[u8]) -> &mut Self {
+        for (i, rgba) in row.chunks_exact(4).enumerate() {
+            self.put_pixel(Point { x: p.x + i as i32, y: p.y }, color_from_rgba(rgba));
+        }
+        self
+    }
+}
+
+/// RGBA value of a color, `None` having a zero alpha.
+pub fn rgba_from_color(color: Option<Color>) -> [u8; 4] {
+    match color {
+        Some(color) => [color.red, color.green, color.blue, 255],
+        None => [0, 0, 0, 0],
+    }
+}
+
+/// Color of a RGBA value, `None` if its alpha is zero.
+pub fn color_from_rgba(rgba: &[u8]) -> Option<Color> {
+    if rgba[3] == 0 {
+        None
+    } else {
+        Some(Color::new(rgba[0], rgba[1], rgba[2]))
+    }
+}
+
+/// Bounds of a row of `len` pixels starting at the given point clipped to a
+/// bitmap of the given size: the offset of the first visible pixel in the
+/// row, the offset of the first visible pixel in the bitmap and the count of
+/// visible pixels.
+pub(crate) fn clip_row(
+    p: Point,
+    len: usize,
+    size: Size,
+) -> Option<(usize, usize, usize)> {
+    if p.y < 0 || (p.y as u32) >= size.height {
+        return None;
+    }
+
+    let left = i64::max(p.x as i64, 0);
+    let right = i64::min(p.x as i64 + len as i64, size.width as i64);
+
+    if left >= right {
+        return None;
+    }
+
+    Some((
+        (left - p.x as i64) as usize,
+        (p.y as u32*size.width) as usize + left as usize,
+        (right - left) as usize,
+    ))
+}
+
+fn pixel_offset(
+    p: Point,
+    size: Size,
+) -> Option<usize> {
+    if p.x < 0 || (p.x as u32) >= size.width {
+        return None;
+    }
+    if p.y < 0 || (p.y as u32) >= size.height {
+        return None;
+    }
+    Some((p.y as u32*size.width + p.x as u32) as usize)
+}
+
+/// Owned bitmap of palette indices.
+#[derive(Clone, Debug)]
+pub struct IndexedBitmap {
+    size: Size,
+    data: Vec<u8>,
+    palette: Rc<Palette>,
+    // Built for the first color put which is missing from the palette
+    matcher: Option<ColorMatcher>,
+}
+
+impl IndexedBitmap {
+    /// Create a bitmap filled with the transparent index of the palette.
+    pub fn new(
+        size: Size,
+        palette: Rc<Palette>,
+    ) -> Self {
+        let transparent_index = palette
+            .transparent_index()
+            .unwrap_or(DEFAULT_TRANSPARENT_INDEX) as u8;
+
+        Self {
+            size,
+            data: vec![transparent_index; (size.width*size.height) as usize],
+            palette,
+            matcher: None,
+        }
+    }
+
+    pub fn from_data(
+        size: Size,
+        data: Vec<u8>,
+        palette: Rc<Palette>,
+    ) -> Result<Self> {
+        if data.len() != (size.width*size.height) as usize {
+            return Err(Error::BitmapInvalidDataSize(size, data.len()));
+        }
+
+        Ok(Self {
+            size,
+            data,
+            palette,
+            matcher: None,
+        })
+    }
+
+    pub fn from_tile(
+        tile: TileView,
+        palette: Rc<Palette>,
+    ) -> Self {
+        Self {
+            size: tile.size(),
+            data: tile.untrim().data().to_vec(),
+            palette,
+            matcher: None,
+        }
+    }
+
+    pub fn to_tile(&self) -> Tile {
+        Tile::new(&self.data, self.size)
+    }
+
+    /// Convert the indices to their colors, transparent pixels having a
+    /// zero alpha.
+    pub fn to_rgba(&self) -> RgbaBitmap {
+        let mut bitmap = RgbaBitmap::new(self.size);
+
+        for (offset, index) in self.data.iter().enumerate() {
+            bitmap.put_pixel_at(offset, self.palette.color_at(*index as usize));
+        }
+        bitmap
+    }
+
+    pub fn palette(&self) -> &Rc<Palette> {
+        &self.palette
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn index_at(
+        &self,
+        p: Point,
+    ) -> Option<u8> {
+        pixel_offset(p, self.size).map(|offset| self.data[offset])
+    }
+
+    pub fn put_index(
+        &mut self,
+        p: Point,
+        index: u8,
+    ) -> &mut Self {
+        if let Some(offset) = pixel_offset(p, self.size) {
+            self.data[offset] = index;
+        }
+        self
+    }
+}
+
+impl Bitmap for IndexedBitmap {
+    fn width(&self) -> u32 {
+        self.size.width
+    }
+
+    fn height(&self) -> u32 {
+        self.size.height
+    }
+}
+
+impl BitmapGetPixel for IndexedBitmap {
+    fn get_pixel(&self, p: Point) -> Option<Color> {
+        self.index_at(p).and_then(|index| self.palette.color_at(index as usize))
+    }
+
+    fn get_palette_index(&self, p: Point) -> Option<u8> {
+        self.index_at(p)
+    }
+}
+
+impl BitmapPutPixel for IndexedBitmap {
+    /// Colors missing from the palette are replaced by their nearest
+    /// opaque color, `None` by the transparent index.
+    fn put_pixel(&mut self, p: Point, color: Option<Color>) -> &mut Self {
+        let index = match color {
+            Some(color) => self.palette.color_index(&color).or_else(|| {
+                self.matcher
+                    .get_or_insert_with(|| self.palette.color_matcher(ColorMetric::default()))
+                    .nearest(&color)
+            }),
+            None => self.palette.transparent_index(),
+        };
+
+        if let Some(index) = index {
+            self.put_index(p, index as u8);
+        }
+        self
+    }
+}
+
+/// Owned bitmap of RGBA pixels, 4 bytes per pixel. Fully transparent pixels
+/// read as `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbaBitmap {
+    size: Size,
+    data: Vec<u8>,
+}
+
+impl RgbaBitmap {
+    /// Create a fully transparent bitmap.
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            data: vec![0; 4*(size.width*size.height) as usize],
+        }
+    }
+
+    pub fn from_data(
+        size: Size,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        if data.len() != 4*(size.width*size.height) as usize {
+            return Err(Error::BitmapInvalidDataSize(size, data.len()));
+        }
+        Ok(Self { size, data })
+    }
+
+    /// Copy the pixels of any bitmap.
+    pub fn from_bitmap<T>(
+        bitmap: &T,
+    ) -> Self where T: Bitmap + BitmapGetPixel {
+        let mut rgba = Self::new(bitmap.size());
+
+        bitmap_blit(bitmap, &bitmap.rect(), &mut rgba, &bitmap.rect(), BlitSizePolicy::Clip);
+        rgba
+    }
+
+    /// Quantize the pixels to the given palette, fully transparent pixels
+    /// being mapped to the transparent index.
+    pub fn to_indexed(
+        &self,
+        palette: Rc<Palette>,
+        metric: ColorMetric,
+        dithering: Dithering,
+    ) -> Result<IndexedBitmap> {
+        let mut matcher = palette.color_matcher(metric);
+
+        self.to_indexed_with_matcher(palette, &mut matcher, dithering)
+    }
+
+    /// Same as `to_indexed` with a matcher over the entries of `palette`
+    /// shared between bitmaps, so that its cache outlives a single bitmap.
+    pub fn to_indexed_with_matcher(
+        &self,
+        palette: Rc<Palette>,
+        matcher: &mut ColorMatcher,
+        dithering: Dithering,
+    ) -> Result<IndexedBitmap> {
+        let colors = self.data
+            .chunks_exact(4)
+            .map(|rgba| Color::new(rgba[0], rgba[1], rgba[2]))
+            .collect::<Vec<Color>>();
+        let opaque = self.data
+            .chunks_exact(4)
+            .map(|rgba| rgba[3] != 0)
+            .collect::<Vec<bool>>();
+        let mut data = matcher.dither(&colors, Some(&opaque), self.size, dithering)?;
+        let transparent_index = palette
+            .transparent_index()
+            .unwrap_or(DEFAULT_TRANSPARENT_INDEX) as u8;
+
+        for (index, opaque) in data.iter_mut().zip(opaque) {
+            if !opaque {
+                *index = transparent_index;
+            }
+        }
+
+        IndexedBitmap::from_data(self.size, data, palette)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn put_pixel_at(
+        &mut self,
+        offset: usize,
+        color: Option<Color>,
+    ) {
+        self.data[4*offset..4*offset + 4].copy_from_slice(&rgba_from_color(color));
+    }
+}
+
+impl Bitmap for RgbaBitmap {
+    fn width(&self) -> u32 {
+        self.size.width
+    }
+
+    fn height(&self) -> u32 {
+        self.size.height
+    }
+}
+
+impl BitmapGetPixel for RgbaBitmap {
+    fn get_pixel(&self, p: Point) -> Option<Color> {
+        let offset = 4*pixel_offset(p, self.size)?;
+
+        color_from_rgba(&self.data[offset..offset + 4])
+    }
+}
+
+impl BitmapPutPixel for RgbaBitmap {
+    fn put_pixel(&mut self, p: Point, color: Option<Color>) -> &mut Self {
+        if let Some(offset) = pixel_offset(p, self.size) {
+            self.put_pixel_at(offset, color);
+        }
+        self
+    }
+
+    fn put_rgba_row(&mut self, p: Point, row: &[u8]) -> &mut Self {
+        if let Some((row_offset, offset, count)) = clip_row(p, row.len()/4, self.size) {
+            self.data[4*offset..4*(offset + count)]
+                .copy_from_slice(&row[4*row_offset..4*(row_offset + count)]);
+        }
+        self
+    }
 }
 
 pub fn bitmap_fill_rect<T>(
@@ -48,67 +413,709 @@ pub fn bitmap_clear<T>(
     bitmap_fill_rect(bitmap, &bitmap.rect(), color);
 }
 
+/// How the source pixels of a blit are combined with the destination ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlendMode {
+    /// Source pixels replace destination pixels, transparent ones included.
+    #[default]
+    Replace,
+    /// Transparent source pixels leave the destination untouched.
+    SkipTransparent,
+    /// Source pixels of the given palette index darken the destination by
+    /// the given factor, between 0 and 1. Transparent source pixels leave
+    /// the destination untouched.
+    Shadow {
+        index: u8,
+        factor: f32,
+    },
+    /// Source colors are added to the destination ones. Transparent source
+    /// pixels leave the destination untouched.
+    Additive,
+}
+
+impl BlendMode {
+    fn blend<T, U>(
+        &self,
+        src_bitmap: &T,
+        src: Point,
+        dst_bitmap: &mut U,
+        dst: Point,
+    ) where
+        T: BitmapGetPixel,
+        U: BitmapGetPixel + BitmapPutPixel,
+    {
+        let color = match self {
+            Self::Replace => src_bitmap.get_pixel(src),
+            Self::SkipTransparent => match src_bitmap.get_pixel(src) {
+                Some(color) => Some(color),
+                None => return,
+            },
+            Self::Shadow { index, factor } => {
+                if src_bitmap.get_palette_index(src) == Some(*index) {
+                    match dst_bitmap.get_pixel(dst) {
+                        Some(color) => Some(color.shade(1. - factor)),
+                        None => return,
+                    }
+                } else {
+                    match src_bitmap.get_pixel(src) {
+                        Some(color) => Some(color),
+                        None => return,
+                    }
+                }
+            },
+            Self::Additive => match (src_bitmap.get_pixel(src), dst_bitmap.get_pixel(dst)) {
+                (Some(src_color), Some(dst_color)) => Some(Color {
+                    red: src_color.red.saturating_add(dst_color.red),
+                    green: src_color.green.saturating_add(dst_color.green),
+                    blue: src_color.blue.saturating_add(dst_color.blue),
+                }),
+                (Some(src_color), None) => Some(src_color),
+                (None, _) => return,
+            },
+        };
+
+        dst_bitmap.put_pixel(dst, color);
+    }
+}
+
+/// How the source rect of a blit is fitted in the destination rect.
+#[derive(Clone, Copy, Debug, Default)]
 pub enum BlitSizePolicy {
+    /// The source is copied at its size at the top left corner of the
+    /// destination rect and clipped to it.
     Clip,
+    /// The source is scaled to the destination rect.
+    #[default]
     Stretch,
+    /// The source is scaled to the largest size fitting the destination rect
+    /// keeping its aspect ratio, then placed according to the anchor.
+    Fit(TileAnchor),
+    /// The source is scaled by the largest integer factor fitting the
+    /// destination rect, then placed according to the anchor and clipped.
+    IntegerScale(TileAnchor),
+}
+
+impl BlitSizePolicy {
+    /// Rect the whole source rect is mapped to. It may exceed the
+    /// destination rect.
+    fn target_rect(
+        &self,
+        src_size: Size,
+        dst_rect: &Rect,
+    ) -> Rect {
+        let dst_size = dst_rect.size();
+        let (size, anchor) = match self {
+            Self::Clip => (src_size, TileAnchor::TopLeft),
+            Self::Stretch => (dst_size, TileAnchor::TopLeft),
+            Self::Fit(anchor) => {
+                let src_w = src_size.width as u64;
+                let src_h = src_size.height as u64;
+                let dst_w = dst_size.width as u64;
+                let dst_h = dst_size.height as u64;
+
+                // Compare dst_w/src_w and dst_h/src_h without rounding
+                let size = if dst_w*src_h <= dst_h*src_w {
+                    Size {
+                        width: dst_size.width,
+                        height: ((2*src_h*dst_w + src_w)/(2*src_w)) as u32,
+                    }
+                } else {
+                    Size {
+                        width: ((2*src_w*dst_h + src_h)/(2*src_h)) as u32,
+                        height: dst_size.height,
+                    }
+                };
+                (size, *anchor)
+            },
+            Self::IntegerScale(anchor) => {
+                let scale = u32::min(
+                    dst_size.width/src_size.width,
+                    dst_size.height/src_size.height,
+                );
+                (u32::max(1, scale)*src_size, *anchor)
+            },
+        };
+        let position = anchor.to_position(&size, &dst_size);
+
+        Rect::from_point_and_size(
+            dst_rect.top_left() + Point {
+                x: position.left,
+                y: position.top,
+            },
+            size,
+        )
+    }
 }
 
-fn create_range_mapper(
-    i_min: i32, i_max: i32,
-    o_min: i32, o_max: i32,
-) -> impl Fn(i32) -> i32 {
-    let i_min = i_min as f32;
-    let i_max = i_max as f32;
-    let o_min = o_min as f32;
-    let o_max = o_max as f32;
-    return move |n| {
-        let n = n as f32;
-        ((n - i_min)/(i_max - i_min)*(o_max - o_min) + o_min) as i32
+/// Map a destination coordinate to the source one, sampling the source at
+/// the center of the destination pixel.
+fn map_coordinate(
+    dst: i32,
+    dst_min: i32,
+    dst_len: u32,
+    src_min: i32,
+    src_len: u32,
+) -> i32 {
+    let offset = (2*(dst - dst_min) as i64 + 1)*src_len as i64/(2*dst_len as i64);
+    src_min + offset as i32
+}
+
+/// Rects of a blit: the source rect clipped to the source bitmap bounds, the
+/// rect it is mapped to according to the policy and the visible part of the
+/// latter, clipped to the destination rect and bitmap bounds.
+fn blit_rects(
+    src_bounds: Rect,
+    src_rect: &Rect,
+    dst_bounds: Rect,
+    dst_rect: &Rect,
+    policy: BlitSizePolicy,
+) -> Option<(Rect, Rect, Rect)> {
+    let src_rect = src_rect
+        .intersected(&src_bounds)
+        .filter(|rect| rect.width() > 0 && rect.height() > 0)?;
+
+    let target_rect = policy.target_rect(src_rect.size(), dst_rect);
+    if target_rect.width() == 0 || target_rect.height() == 0 {
+        return None;
+    }
+
+    let visible_rect = target_rect
+        .intersected(dst_rect)
+        .and_then(|rect| rect.intersected(&dst_bounds))?;
+
+    Some((src_rect, target_rect, visible_rect))
+}
+
+/// Call `blit_pixel` with each pair of source and destination points of a
+/// blit.
+fn blit_points<F>(
+    src_bounds: Rect,
+    src_rect: &Rect,
+    dst_bounds: Rect,
+    dst_rect: &Rect,
+    policy: BlitSizePolicy,
+    mut blit_pixel: F,
+) where F: FnMut(Point, Point) {
+    let Some((src_rect, target_rect, visible_rect)) = blit_rects(
+        src_bounds,
+        src_rect,
+        dst_bounds,
+        dst_rect,
+        policy,
+    ) else {
+        return;
     };
+
+    for y in visible_rect.top()..visible_rect.bottom() {
+        for x in visible_rect.left()..visible_rect.right() {
+            let dst = Point { x, y };
+            let src = Point {
+                x: map_coordinate(
+                    x, target_rect.left(), target_rect.width(),
+                    src_rect.left(), src_rect.width(),
+                ),
+                y: map_coordinate(
+                    y, target_rect.top(), target_rect.height(),
+                    src_rect.top(), src_rect.height(),
+                ),
+            };
+
+            blit_pixel(src, dst);
+        }
+    }
 }
 
+/// Copy the rows of a blit whose source is scaled by an integer factor on
+/// both axes. Each source row is read once, then repeated horizontally and
+/// vertically, which gives the same result as `blit_points` since the center
+/// of the destination pixel `i` is then always in the source pixel
+/// `i/scale`.
+fn blit_rows<T, U>(
+    src_bitmap: &T,
+    src_rect: &Rect,
+    dst_bitmap: &mut U,
+    target_rect: &Rect,
+    visible_rect: &Rect,
+) where
+    T: BitmapGetPixel,
+    U: BitmapPutPixel,
+{
+    let scale_x = target_rect.width()/src_rect.width();
+    let scale_y = target_rect.height()/src_rect.height();
+
+    let dx = (visible_rect.left() - target_rect.left()) as u32;
+    let src_left = src_rect.left() + (dx/scale_x) as i32;
+    let src_width = (dx + visible_rect.width() - 1)/scale_x - dx/scale_x + 1;
+
+    let mut src_row = vec![0; 4*src_width as usize];
+    let mut dst_row = vec![0; 4*visible_rect.width() as usize];
+    let mut last_src_y = None;
+
+    for y in visible_rect.top()..visible_rect.bottom() {
+        let src_y = src_rect.top() + ((y - target_rect.top()) as u32/scale_y) as i32;
+
+        if last_src_y != Some(src_y) {
+            src_bitmap.get_rgba_row(Point { x: src_left, y: src_y }, &mut src_row);
+
+            if scale_x == 1 {
+                dst_row.copy_from_slice(&src_row);
+            } else {
+                let (src_pixels, _) = src_row.as_chunks::<4>();
+                let (dst_pixels, _) = dst_row.as_chunks_mut::<4>();
+
+                for (i, rgba) in dst_pixels.iter_mut().enumerate() {
+                    *rgba = src_pixels[((dx + i as u32)/scale_x - dx/scale_x) as usize];
+                }
+            }
+            last_src_y = Some(src_y);
+        }
+
+        dst_bitmap.put_rgba_row(Point { x: visible_rect.left(), y }, &dst_row);
+    }
+}
+
+/// Copy the source rect of a bitmap to the destination rect of another
+/// according to the size policy. Unscaled and integer-scaled blits are done
+/// row by row.
 pub fn bitmap_blit<T, U>(
     src_bitmap: &T,
     src_rect: &Rect,
     dst_bitmap: &mut U,
     dst_rect: &Rect,
+    policy: BlitSizePolicy,
 ) where
     T: Bitmap + BitmapGetPixel,
     U: Bitmap + BitmapPutPixel,
 {
-    let src_rect =
-        if let Some(rect) = src_rect.intersected(&src_bitmap.rect()) {
-            rect
-        } else {
-            Rect::zero()
-        };
+    let src_bounds = src_bitmap.rect();
+    let dst_bounds = dst_bitmap.rect();
 
-    let dst_rect =
-        if let Some(rect) = dst_rect.intersected(&dst_bitmap.rect()) {
-            rect
-        } else {
-            Rect::zero()
-        };
+    if let Some((clipped_src_rect, target_rect, visible_rect)) = blit_rects(
+        src_bounds,
+        src_rect,
+        dst_bounds,
+        dst_rect,
+        policy,
+    ) {
+        if target_rect.width()%clipped_src_rect.width() == 0
+            && target_rect.height()%clipped_src_rect.height() == 0 {
+            blit_rows(src_bitmap, &clipped_src_rect, dst_bitmap, &target_rect, &visible_rect);
+            return;
+        }
+    }
 
-    let x_map = create_range_mapper(
-        dst_rect.left(), dst_rect.right(),
-        src_rect.left(), src_rect.right(),
-    );
+    blit_points(src_bounds, src_rect, dst_bounds, dst_rect, policy, |src, dst| {
+        dst_bitmap.put_pixel(dst, src_bitmap.get_pixel(src));
+    });
+}
 
-    let y_map = create_range_mapper(
-        dst_rect.top(), dst_rect.bottom(),
-        src_rect.top(), src_rect.bottom(),
-    );
+/// Same as `bitmap_blit`, the source pixels being combined with the
+/// destination ones according to the given blend mode.
+pub fn bitmap_blit_blend<T, U>(
+    src_bitmap: &T,
+    src_rect: &Rect,
+    dst_bitmap: &mut U,
+    dst_rect: &Rect,
+    policy: BlitSizePolicy,
+    blend_mode: BlendMode,
+) where
+    T: Bitmap + BitmapGetPixel,
+    U: Bitmap + BitmapGetPixel + BitmapPutPixel,
+{
+    let dst_bounds = dst_bitmap.rect();
 
-    for y in dst_rect.top()..dst_rect.bottom() {
-        for x in dst_rect.left()..dst_rect.right() {
-            let dst = Point { x, y };
-            let src = Point {
-                x: x_map(x),
-                y: y_map(y),
-            };
+    blit_points(src_bitmap.rect(), src_rect, dst_bounds, dst_rect, policy, |src, dst| {
+        blend_mode.blend(src_bitmap, src, dst_bitmap, dst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::TileAnchorPosition;
+
+    use super::*;
 
-            dst_bitmap.put_pixel(dst, src_bitmap.get_pixel(src));
+    fn rgba_bitmap(
+        colors: &[Option<Color>],
+    ) -> RgbaBitmap {
+        let mut bitmap = RgbaBitmap::new(Size { width: colors.len() as u32, height: 1 });
+
+        for (x, color) in colors.iter().enumerate() {
+            bitmap.put_pixel(Point { x: x as i32, y: 0 }, *color);
         }
+        bitmap
+    }
+
+    fn row<T>(
+        bitmap: &T,
+    ) -> Vec<Option<Color>> where T: Bitmap + BitmapGetPixel {
+        (0..bitmap.width() as i32)
+            .map(|x| bitmap.get_pixel(Point { x, y: 0 }))
+            .collect()
+    }
+
+    fn blit_blend<T>(
+        src: &T,
+        dst: &mut RgbaBitmap,
+        blend_mode: BlendMode,
+    ) where T: Bitmap + BitmapGetPixel {
+        let dst_rect = dst.rect();
+
+        bitmap_blit_blend(src, &src.rect(), dst, &dst_rect, BlitSizePolicy::Clip, blend_mode);
+    }
+
+    #[test]
+    fn shadow_blend_darkens_the_destination() {
+        let mut palette = Palette::new();
+
+        palette.set_color(7, &Color::new(10, 20, 30)).unwrap();
+
+        // Shadow, transparent and opaque source pixels
+        let src = IndexedBitmap::from_data(
+            Size { width: 3, height: 1 },
+            vec![12, 0, 7],
+            Rc::new(palette),
+        ).unwrap();
+        let background = Some(Color::new(200, 100, 50));
+        let mut dst = rgba_bitmap(&[background, background, None]);
+
+        blit_blend(&src, &mut dst, BlendMode::Shadow { index: 12, factor: 0.25 });
+
+        assert_eq!(row(&dst), [
+            Some(Color::new(150, 75, 38)),
+            background,
+            Some(Color::new(10, 20, 30)),
+        ]);
+
+        // Shadows over transparent pixels are not drawn
+        let mut dst = rgba_bitmap(&[None, None, None]);
+
+        blit_blend(&src, &mut dst, BlendMode::Shadow { index: 12, factor: 0.25 });
+
+        assert_eq!(row(&dst), [None, None, Some(Color::new(10, 20, 30))]);
+    }
+
+    #[test]
+    fn additive_blend_saturates() {
+        let src = rgba_bitmap(&[
+            Some(Color::new(100, 200, 10)),
+            None,
+            Some(Color::new(1, 2, 3)),
+        ]);
+        let mut dst = rgba_bitmap(&[
+            Some(Color::new(100, 100, 250)),
+            Some(Color::new(4, 5, 6)),
+            None,
+        ]);
+
+        blit_blend(&src, &mut dst, BlendMode::Additive);
+
+        assert_eq!(row(&dst), [
+            Some(Color::new(200, 255, 255)),
+            Some(Color::new(4, 5, 6)),
+            Some(Color::new(1, 2, 3)),
+        ]);
+    }
+
+    #[test]
+    fn skip_transparent_blend_keeps_the_destination() {
+        let src = rgba_bitmap(&[None, Some(Color::new(1, 2, 3))]);
+        let background = Some(Color::new(4, 5, 6));
+
+        let mut dst = rgba_bitmap(&[background, background]);
+        blit_blend(&src, &mut dst, BlendMode::SkipTransparent);
+        assert_eq!(row(&dst), [background, Some(Color::new(1, 2, 3))]);
+
+        let mut dst = rgba_bitmap(&[background, background]);
+        blit_blend(&src, &mut dst, BlendMode::Replace);
+        assert_eq!(row(&dst), [None, Some(Color::new(1, 2, 3))]);
+    }
+
+    fn point(
+        x: i32,
+        y: i32,
+    ) -> Point {
+        Point { x, y }
+    }
+
+    // Destination and source points of a blit of a square source rect at
+    // (1, 1) in its bitmap to the whole destination bitmap, row by row
+    fn sampled_points(
+        policy: BlitSizePolicy,
+        src_len: u32,
+        dst_size: Size,
+    ) -> Vec<(Point, Point)> {
+        let src_size = Size { width: src_len, height: src_len };
+        let src_bounds = Rect::from_point_and_size(Point::zero(), Size {
+            width: src_len + 2,
+            height: src_len + 2,
+        });
+        let src_rect = Rect::from_point_and_size(point(1, 1), src_size);
+        let dst_rect = Rect::from_point_and_size(Point::zero(), dst_size);
+        let mut points = Vec::new();
+
+        blit_points(src_bounds, &src_rect, dst_rect, &dst_rect, policy, |src, dst| {
+            points.push((dst, src));
+        });
+        points
+    }
+
+    // First and last sampled (destination, source) points
+    fn assert_sampled(
+        policy: BlitSizePolicy,
+        src_len: u32,
+        dst_size: Size,
+        first: (Point, Point),
+        last: (Point, Point),
+    ) {
+        let points = sampled_points(policy, src_len, dst_size);
+
+        assert_eq!(points.first(), Some(&first), "{policy:?} {src_len} -> {dst_size}");
+        assert_eq!(points.last(), Some(&last), "{policy:?} {src_len} -> {dst_size}");
+    }
+
+    #[test]
+    fn stretch_samples_the_whole_source() {
+        for (src_len, dst_len) in [(3, 2), (2, 3), (5, 7)] {
+            let dst_size = Size { width: dst_len, height: dst_len };
+            let last = dst_len as i32 - 1;
+            let src_last = src_len as i32;
+
+            assert_sampled(
+                BlitSizePolicy::Stretch,
+                src_len,
+                dst_size,
+                (point(0, 0), point(1, 1)),
+                (point(last, last), point(src_last, src_last)),
+            );
+        }
+
+        // Pixel centers: 5 -> 7 samples 0 1 1 2 3 3 4
+        let columns = sampled_points(BlitSizePolicy::Stretch, 5, Size { width: 7, height: 7 })
+            .iter()
+            .take(7)
+            .map(|(_, src)| src.x - 1)
+            .collect::<Vec<i32>>();
+
+        assert_eq!(columns, [0, 1, 1, 2, 3, 3, 4]);
+    }
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio() {
+        // Taller destination, the square source is centered vertically
+        for (src_len, dst_len) in [(3, 2), (2, 3), (5, 7)] {
+            let dst_size = Size { width: dst_len, height: dst_len + 2 };
+            let last = dst_len as i32 - 1;
+            let src_last = src_len as i32;
+
+            assert_sampled(
+                BlitSizePolicy::Fit(TileAnchor::Center),
+                src_len,
+                dst_size,
+                (point(0, 1), point(1, 1)),
+                (point(last, last + 1), point(src_last, src_last)),
+            );
+        }
+    }
+
+    #[test]
+    fn fit_follows_the_anchor() {
+        let src_size = Size { width: 2, height: 2 };
+        // Fitted sources are 4x4, with 2 pixels left horizontally in the wide
+        // destination and vertically in the tall one
+        let wide = Rect::from_point_and_size(point(10, 20), Size { width: 6, height: 4 });
+        let tall = Rect::from_point_and_size(point(10, 20), Size { width: 4, height: 6 });
+
+        for (anchor, x, y) in [
+            (TileAnchor::TopLeft, 0, 0),
+            (TileAnchor::TopCenter, 1, 0),
+            (TileAnchor::TopRight, 2, 0),
+            (TileAnchor::CenterLeft, 0, 1),
+            (TileAnchor::Center, 1, 1),
+            (TileAnchor::CenterRight, 2, 1),
+            (TileAnchor::BottomLeft, 0, 2),
+            (TileAnchor::BottomCenter, 1, 2),
+            (TileAnchor::BottomRight, 2, 2),
+            (TileAnchor::Position(TileAnchorPosition { left: -1, top: 3 }), -1, 3),
+        ] {
+            let policy = BlitSizePolicy::Fit(anchor);
+            let wide_target = policy.target_rect(src_size, &wide);
+            let tall_target = policy.target_rect(src_size, &tall);
+
+            assert_eq!(wide_target.size(), Size { width: 4, height: 4 });
+            assert_eq!(tall_target.size(), Size { width: 4, height: 4 });
+            assert_eq!(wide_target.top_left().x, 10 + x, "{anchor:?}");
+            assert_eq!(tall_target.top_left().y, 20 + y, "{anchor:?}");
+        }
+    }
+
+    #[test]
+    fn integer_scale_clips_the_source() {
+        let policy = BlitSizePolicy::IntegerScale(TileAnchor::TopLeft);
+
+        // Scaled down sources are kept at their size and clipped
+        assert_sampled(
+            policy,
+            3,
+            Size { width: 2, height: 2 },
+            (point(0, 0), point(1, 1)),
+            (point(1, 1), point(2, 2)),
+        );
+        assert_sampled(
+            policy,
+            2,
+            Size { width: 3, height: 3 },
+            (point(0, 0), point(1, 1)),
+            (point(1, 1), point(2, 2)),
+        );
+        assert_sampled(
+            policy,
+            5,
+            Size { width: 7, height: 7 },
+            (point(0, 0), point(1, 1)),
+            (point(4, 4), point(5, 5)),
+        );
+        // Scaled by 2 at the bottom right
+        assert_sampled(
+            BlitSizePolicy::IntegerScale(TileAnchor::BottomRight),
+            2,
+            Size { width: 5, height: 5 },
+            (point(1, 1), point(1, 1)),
+            (point(4, 4), point(2, 2)),
+        );
+    }
+
+    // Distinct color for each pixel, so that any misplaced pixel shows
+    fn gradient_bitmap(
+        size: Size,
+    ) -> RgbaBitmap {
+        let mut bitmap = RgbaBitmap::new(size);
+
+        for y in 0..size.height as i32 {
+            for x in 0..size.width as i32 {
+                bitmap.put_pixel(point(x, y), Some(Color::new(16*x as u8, 16*y as u8, 255)));
+            }
+        }
+        bitmap
+    }
+
+    #[test]
+    fn blit_rows_matches_blit_points() {
+        let src = gradient_bitmap(Size { width: 5, height: 4 });
+        let dst_size = Size { width: 16, height: 12 };
+        // Inside, partly out of the source bitmap
+        let src_rects = [
+            Rect::from_point_and_size(point(1, 1), Size { width: 3, height: 2 }),
+            Rect::from_point_and_size(point(-1, 2), Size { width: 4, height: 4 }),
+        ];
+        // Inside, clipped on the top left and on the bottom right of the
+        // destination bitmap
+        let dst_origins = [point(2, 1), point(-1, -1), point(11, 8)];
+        let mut blit_count = 0;
+
+        for scale in 1u32..=3 {
+            for src_rect in src_rects.iter() {
+                for dst_origin in dst_origins {
+                    // Sized after the clipped source, to keep integer scales
+                    let src_size = src_rect.intersected(&src.rect()).unwrap().size();
+                    let scaled_size = scale*src_size;
+                    let policies = [
+                        (BlitSizePolicy::Stretch, scaled_size),
+                        (BlitSizePolicy::Clip, src_size),
+                        // One column short of the scaled source, the scale drops
+                        // by one or the source is clipped to the destination rect
+                        (
+                            BlitSizePolicy::IntegerScale(TileAnchor::BottomRight),
+                            Size { width: scaled_size.width - 1, ..scaled_size },
+                        ),
+                    ];
+
+                    for (policy, dst_rect_size) in policies {
+                        let dst_rect = Rect::from_point_and_size(dst_origin, dst_rect_size);
+                        let Some((src_clipped, target_rect, visible_rect)) = blit_rects(
+                            src.rect(),
+                            src_rect,
+                            Rect::from_point_and_size(Point::zero(), dst_size),
+                            &dst_rect,
+                            policy,
+                        ) else {
+                            continue;
+                        };
+
+                        assert_eq!(target_rect.width()%src_clipped.width(), 0);
+                        assert_eq!(target_rect.height()%src_clipped.height(), 0);
+
+                        let mut by_rows = RgbaBitmap::new(dst_size);
+                        let mut by_points = RgbaBitmap::new(dst_size);
+
+                        blit_rows(&src, &src_clipped, &mut by_rows, &target_rect, &visible_rect);
+                        blit_points(src.rect(), src_rect, by_points.rect(), &dst_rect, policy, |s, d| {
+                            by_points.put_pixel(d, src.get_pixel(s));
+                        });
+
+                        assert!(by_points.data().iter().any(|byte| *byte != 0));
+                        assert_eq!(
+                            by_rows,
+                            by_points,
+                            "scale {scale}, {policy:?} {src_rect:?} -> {dst_rect:?}",
+                        );
+                        blit_count += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(blit_count, 3*2*3*3);
+    }
+
+    fn gradient_palette() -> Rc<Palette> {
+        let mut palette = Palette::new();
+
+        for index in 0..PALETTE_SIZE {
+            palette.set_color(index, &Color::new(index as u8, 255 - index as u8, 0)).unwrap();
+        }
+        Rc::new(palette)
+    }
+
+    #[test]
+    fn indexed_rgba_round_trip() {
+        let palette = gradient_palette();
+        let size = Size { width: 4, height: 3 };
+        let data = vec![0, 1, 2, 3, 255, 128, 0, 64, 17, 18, 0, 254];
+        let indexed = IndexedBitmap::from_data(size, data.clone(), palette.clone()).unwrap();
+        let rgba = indexed.to_rgba();
+
+        assert_eq!(&rgba.data()[0..8], [0, 0, 0, 0, 1, 254, 0, 255]);
+        assert_eq!(rgba.get_pixel(Point { x: 0, y: 1 }), Some(Color::new(255, 0, 0)));
+
+        // Exact colors leave no error to diffuse
+        for dithering in [Dithering::None, Dithering::FloydSteinberg] {
+            let round_trip = rgba.to_indexed(palette.clone(), ColorMetric::default(), dithering).unwrap();
+
+            assert_eq!(round_trip.data(), data, "{dithering:?}");
+        }
+    }
+
+    #[test]
+    fn matcher_is_built_for_missing_colors() {
+        let mut palette = Palette::new();
+
+        palette.set_color(1, &Color::new(255, 0, 0)).unwrap();
+        palette.set_color(2, &Color::new(0, 0, 255)).unwrap();
+        palette.set_color(3, &Color::new(0, 0, 0)).unwrap();
+
+        let mut bitmap = IndexedBitmap::new(Size { width: 3, height: 1 }, Rc::new(palette));
+
+        // Colors of the palette and transparent pixels are looked up
+        bitmap.put_pixel(Point { x: 0, y: 0 }, Some(Color::new(0, 0, 255)));
+        bitmap.put_pixel(Point { x: 1, y: 0 }, None);
+        assert!(bitmap.matcher.is_none());
+
+        bitmap.put_pixel(Point { x: 2, y: 0 }, Some(Color::new(240, 10, 20)));
+        assert!(bitmap.matcher.is_some());
+        assert_eq!(bitmap.data(), [2, 0, 1]);
+
+        // The matcher is kept for the next colors
+        bitmap.put_pixel(Point { x: 1, y: 0 }, Some(Color::new(10, 0, 200)));
+        assert_eq!(bitmap.data(), [2, 2, 1]);
     }
 }
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    Error,
+    Result,
+    Size,
+};
+
+
+/// Unit names, indexed by unit type.
+pub const UNIT_NAMES: [&str; 27] = [
+    "Carryall", "Ornithopter", "Infantry", "Troopers", "Soldier", "Trooper",
+    "Saboteur", "Launcher", "Deviator", "Tank", "Siege Tank", "Devastator",
+    "Sonic Tank", "Trike", "Raider Trike", "Quad", "Harvester", "MCV",
+    "Death Hand", "Rocket", "ARRocket", "GRRocket", "MiniRocket", "Bullet",
+    "Sonic Blast", "Sandworm", "Frigate",
+];
+
+/// Structure names, indexed by structure type.
+pub const STRUCTURE_NAMES: [&str; 19] = [
+    "Concrete Slab", "Large Concrete Slab", "Palace", "Light Factory",
+    "Heavy Factory", "Hi-Tech Factory", "House of IX", "WOR", "Construction Yard",
+    "Windtrap", "Barracks", "Starport", "Spice Refinery", "Repair Facility",
+    "Wall", "Gun Turret", "Rocket Turret", "Spice Silo", "Radar Outpost",
+];
+
+/// Footprint of a structure, in tiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum StructureLayout {
+    Layout1x1,
+    Layout2x1,
+    Layout1x2,
+    Layout2x2,
+    Layout2x3,
+    Layout3x2,
+    Layout3x3,
+}
+
+impl StructureLayout {
+    pub fn try_from_index(index: usize) -> Result<Self> {
+        [
+            Self::Layout1x1,
+            Self::Layout2x1,
+            Self::Layout1x2,
+            Self::Layout2x2,
+            Self::Layout2x3,
+            Self::Layout3x2,
+            Self::Layout3x3,
+        ]
+            .get(index)
+            .copied()
+            .ok_or(Error::StructureLayoutInvalidIndex(index))
+    }
+
+    pub fn size(&self) -> Size {
+        let (width, height) = match self {
+            Self::Layout1x1 => (1, 1),
+            Self::Layout2x1 => (2, 1),
+            Self::Layout1x2 => (1, 2),
+            Self::Layout2x2 => (2, 2),
+            Self::Layout2x3 => (2, 3),
+            Self::Layout3x2 => (3, 2),
+            Self::Layout3x3 => (3, 3),
+        };
+        Size { width, height }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UnitInfo {
+    pub name: String,
+    pub hitpoints: u16,
+    pub build_credits: u16,
+    pub build_time: u16,
+    /// Index of the build menu icon in the game sprites.
+    pub sprite_id: u16,
+    pub fog_uncover_radius: u16,
+    pub moving_speed: u16,
+    pub fire_distance: u16,
+    pub damage: u16,
+    pub ground_sprite_id: u16,
+    pub turret_sprite_id: u16,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StructureInfo {
+    pub name: String,
+    pub hitpoints: u16,
+    pub build_credits: u16,
+    pub build_time: u16,
+    /// Index of the build menu icon in the game sprites.
+    pub sprite_id: u16,
+    pub fog_uncover_radius: u16,
+    pub credits_storage: u16,
+    pub power_usage: i16,
+    pub layout: StructureLayout,
+}
@@ -2,7 +2,14 @@ use std::collections::HashMap;
 use std::ops::Mul;
 use std::str::FromStr;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::prelude::{
+    Error,
+    Result,
+    COLOR_HARKONNEN,
+    FACTION_RAMP_LENGTH,
+};
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct Color {
@@ -15,6 +22,23 @@ impl Color {
     pub fn new(red: u8, green: u8, blue: u8) -> Self {
         Self { red, green, blue }
     }
+
+    /// Perceived brightness of the color, between 0 and 255.
+    pub fn luminance(&self) -> f32 {
+        0.299*self.red as f32 + 0.587*self.green as f32 + 0.114*self.blue as f32
+    }
+
+    /// Darken the color by the given factor, between 0 and 1.
+    pub fn shade(&self, factor: f32) -> Self {
+        let factor = factor.clamp(0., 1.);
+        let scale = |component: u8| (factor*component as f32).round() as u8;
+
+        Self {
+            red: scale(self.red),
+            green: scale(self.green),
+            blue: scale(self.blue),
+        }
+    }
 }
 
 impl<T> Mul<T> for Color where T: Mul<u8, Output = u8> + Copy {
@@ -58,7 +82,7 @@ impl From<(u8, u8, u8)> for Color {
 impl FromStr for Color {
     type Err = csscolorparser::ParseColorError;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         let css_color = csscolorparser::parse(s)?.to_rgba8();
 
         Ok(Color {
@@ -69,44 +93,377 @@ impl FromStr for Color {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Palette {
+pub const PALETTE_SIZE: usize = 256;
+pub const DEFAULT_TRANSPARENT_INDEX: usize = 0;
+
+fn default_transparent_index() -> Option<usize> {
+    Some(DEFAULT_TRANSPARENT_INDEX)
+}
+
+/// Serialized form of the palette. The first two fields are the layout of
+/// the original palette format, the transparent index has been added later.
+#[derive(Serialize, Deserialize)]
+struct PaletteData {
     colors_index: HashMap<Color, usize>,
     colors: Vec<Color>,
+    #[serde(default = "default_transparent_index")]
+    transparent_index: Option<usize>,
+}
+
+impl From<PaletteData> for Palette {
+    fn from(data: PaletteData) -> Self {
+        let mut palette = Palette::new();
+
+        for (index, color) in data.colors.iter().take(PALETTE_SIZE).enumerate() {
+            palette.colors[index] = *color;
+        }
+        palette.transparent_index = data.transparent_index.filter(|index| *index < PALETTE_SIZE);
+        palette.update_colors_index();
+        palette
+    }
+}
+
+/// Serialized form of the palette in human readable formats, the colors
+/// index is rebuilt when reading.
+#[derive(Serialize, Deserialize)]
+struct PaletteColors {
+    colors: Vec<Color>,
+    #[serde(default = "default_transparent_index")]
+    transparent_index: Option<usize>,
+}
+
+impl From<PaletteColors> for Palette {
+    fn from(data: PaletteColors) -> Self {
+        PaletteData {
+            colors_index: HashMap::new(),
+            colors: data.colors,
+            transparent_index: data.transparent_index,
+        }.into()
+    }
+}
+
+impl From<Palette> for PaletteData {
+    fn from(palette: Palette) -> Self {
+        let colors_index = palette.colors_index
+            .iter()
+            .map(|(color, indices)| (*color, indices[0]))
+            .collect();
+
+        Self {
+            colors_index,
+            colors: palette.colors.to_vec(),
+            transparent_index: palette.transparent_index,
+        }
+    }
+}
+
+impl Serialize for Palette {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> where S: Serializer {
+        if serializer.is_human_readable() {
+            PaletteColors {
+                colors: self.colors.to_vec(),
+                transparent_index: self.transparent_index,
+            }.serialize(serializer)
+        } else {
+            PaletteData::from(self.clone()).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Palette {
+    fn deserialize<D>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        if deserializer.is_human_readable() {
+            PaletteColors::deserialize(deserializer).map(Palette::from)
+        } else {
+            PaletteData::deserialize(deserializer).map(Palette::from)
+        }
+    }
+}
+
+/// Palette is a fixed size table of 256 colors. One of its entries can be
+/// transparent, by default the first one.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colors: [Color; PALETTE_SIZE],
+    colors_index: HashMap<Color, Vec<usize>>,
+    transparent_index: Option<usize>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Palette {
+    /// Create a palette filled with black with the default transparent
+    /// index.
     pub fn new() -> Self {
-        Self {
+        let mut palette = Self {
+            colors: [Color::default(); PALETTE_SIZE],
             colors_index: HashMap::new(),
-            colors: Vec::new(),
+            transparent_index: default_transparent_index(),
+        };
+        palette.update_colors_index();
+        palette
+    }
+
+    fn update_colors_index(&mut self) {
+        self.colors_index.clear();
+        for (index, color) in self.colors.iter().enumerate() {
+            self.colors_index.entry(*color).or_default().push(index);
         }
     }
 
-    pub fn push(&mut self, color: &Color) -> usize {
-        let index = self.colors.len();
-        self.colors_index.insert(*color, index);
-        self.colors.push(*color);
-        index
+    pub fn set_color(
+        &mut self,
+        index: usize,
+        color: &Color,
+    ) -> Result<()> {
+        let entry = self.colors
+            .get_mut(index)
+            .ok_or(Error::PaletteInvalidIndex(index))?;
+        let previous_color = std::mem::replace(entry, *color);
+
+        if let Some(indices) = self.colors_index.get_mut(&previous_color) {
+            indices.retain(|i| *i != index);
+            if indices.is_empty() {
+                self.colors_index.remove(&previous_color);
+            }
+        }
+
+        let indices = self.colors_index.entry(*color).or_default();
+        let position = indices.partition_point(|i| *i < index);
+
+        indices.insert(position, index);
+        Ok(())
     }
 
+    pub fn transparent_index(&self) -> Option<usize> {
+        self.transparent_index
+    }
+
+    pub fn set_transparent_index(
+        &mut self,
+        transparent_index: Option<usize>,
+    ) -> Result<()> {
+        if let Some(index) = transparent_index.filter(|index| *index >= PALETTE_SIZE) {
+            return Err(Error::PaletteInvalidIndex(index));
+        }
+        self.transparent_index = transparent_index;
+        Ok(())
+    }
+
+    pub fn is_transparent(&self, index: usize) -> bool {
+        self.transparent_index == Some(index)
+    }
+
+    /// Iterate over all the palette entries, transparent one included.
     pub fn iter(&self) -> impl Iterator<Item = (usize, Color)> + '_ {
         self.colors.iter().copied().enumerate()
     }
 
+    /// Lowest opaque index of the given color.
     pub fn color_index(&self, color: &Color) -> Option<usize> {
-        self.colors_index.get(&color).copied()
+        self.color_indices(color)
+            .iter()
+            .copied()
+            .find(|index| !self.is_transparent(*index))
     }
 
+    /// All the indices of the given color, transparent one included.
+    pub fn color_indices(&self, color: &Color) -> &[usize] {
+        self.colors_index
+            .get(color)
+            .map(|indices| indices.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Color of the given entry, `None` if the entry is transparent.
     pub fn color_at(&self, index: usize) -> Option<Color> {
-        if index > 0 {
-            self.colors.get(index).copied()
-        } else {
+        if self.is_transparent(index) {
             None
+        } else {
+            self.entry_at(index)
         }
     }
 
+    /// Color of the given entry regardless of its transparency.
+    pub fn entry_at(&self, index: usize) -> Option<Color> {
+        self.colors.get(index).copied()
+    }
+
     pub fn len(&self) -> usize {
-        self.colors.len()
+        PALETTE_SIZE
+    }
+
+    /// Shades ramp of the given team color. The ramp follows the luminance
+    /// profile of the Harkonnen colors ramp, the base color being its
+    /// brightest shade.
+    pub fn team_color_ramp(&self, base: &Color) -> [Color; FACTION_RAMP_LENGTH] {
+        let luminances: [f32; FACTION_RAMP_LENGTH] = std::array::from_fn(|step| {
+            self.entry_at(COLOR_HARKONNEN + step)
+                .map(|color| color.luminance())
+                .unwrap_or(0.)
+        });
+        let max_luminance = luminances.iter().copied().fold(0., f32::max);
+
+        std::array::from_fn(|step| {
+            // Palettes without Harkonnen colors get a linear ramp
+            let factor = if max_luminance > 0. {
+                luminances[step]/max_luminance
+            } else {
+                1. - (step as f32)/(FACTION_RAMP_LENGTH as f32)
+            };
+            base.shade(factor)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::FactionPalette;
+
+    // Palette with a Harkonnen ramp darkening at an uneven rate, as the game
+    // palette does
+    fn game_like_palette() -> Palette {
+        let mut palette = Palette::new();
+
+        for index in 0..PALETTE_SIZE {
+            palette.set_color(index, &Color::new(index as u8, 255 - index as u8, 7)).unwrap();
+        }
+        for (step, red) in [252, 216, 184, 148, 112, 72, 40].iter().enumerate() {
+            palette.set_color(COLOR_HARKONNEN + step, &Color::new(*red, 0, 0)).unwrap();
+        }
+        palette
+    }
+
+    fn assert_darkening(ramp: &[Color]) {
+        for shades in ramp.windows(2) {
+            assert!(
+                shades[0].luminance() > shades[1].luminance(),
+                "{ramp:?} is not monotonic",
+            );
+        }
+    }
+
+    #[test]
+    fn team_color_ramp_darkens() {
+        let base = Color::new(40, 120, 240);
+
+        for palette in [game_like_palette(), Palette::new()] {
+            let ramp = palette.team_color_ramp(&base);
+
+            assert_eq!(ramp[0], base);
+            assert_darkening(&ramp);
+        }
+    }
+
+    #[test]
+    fn team_color_replaces_the_faction_range_only() {
+        let palette = game_like_palette();
+        let base = Color::new(40, 120, 240);
+        let ramp = palette.team_color_ramp(&base);
+        let team_palette = FactionPalette::with_team_color(&palette, &base);
+        let faction_range = COLOR_HARKONNEN..COLOR_HARKONNEN + FACTION_RAMP_LENGTH;
+
+        for index in 0..PALETTE_SIZE {
+            let expected = if faction_range.contains(&index) {
+                Some(ramp[index - COLOR_HARKONNEN])
+            } else {
+                palette.color_at(index)
+            };
+
+            assert_eq!(team_palette.color_at(index), expected, "index {index}");
+        }
+    }
+
+    #[test]
+    fn duplicated_colors_keep_all_their_indices() {
+        let mut palette = Palette::new();
+        let red = Color::new(255, 0, 0);
+
+        palette.set_color(3, &red).unwrap();
+        palette.set_color(200, &red).unwrap();
+        palette.set_color(17, &red).unwrap();
+
+        assert_eq!(palette.color_indices(&red), [3, 17, 200]);
+        assert_eq!(palette.color_index(&red), Some(3));
+
+        // Replacing an entry removes it from the indices of its old color
+        palette.set_color(3, &Color::new(0, 255, 0)).unwrap();
+
+        assert_eq!(palette.color_indices(&red), [17, 200]);
+        assert!(palette.color_indices(&Color::new(1, 2, 3)).is_empty());
+        assert_eq!(palette.color_indices(&Color::default()).len(), PALETTE_SIZE - 3);
+    }
+
+    #[test]
+    fn transparent_index() {
+        let mut palette = Palette::new();
+        let red = Color::new(255, 0, 0);
+
+        assert_eq!(palette.transparent_index(), Some(DEFAULT_TRANSPARENT_INDEX));
+
+        palette.set_color(0, &red).unwrap();
+        palette.set_color(9, &red).unwrap();
+
+        assert!(palette.is_transparent(0));
+        assert_eq!(palette.color_at(0), None);
+        assert_eq!(palette.entry_at(0), Some(red));
+        // The transparent entry is skipped when looking for a color
+        assert_eq!(palette.color_index(&red), Some(9));
+
+        palette.set_transparent_index(Some(9)).unwrap();
+
+        assert_eq!(palette.color_index(&red), Some(0));
+        assert_eq!(
+            palette.set_transparent_index(Some(PALETTE_SIZE)),
+            Err(Error::PaletteInvalidIndex(PALETTE_SIZE)),
+        );
+        assert_eq!(palette.transparent_index(), Some(9));
+
+        palette.set_transparent_index(None).unwrap();
+
+        assert!(!palette.is_transparent(0));
+        assert_eq!(palette.color_at(0), Some(red));
+    }
+
+    #[test]
+    fn old_palettes_are_read() {
+        // Layout of the palettes written before the transparent index, with
+        // only the colors of the source palette
+        #[derive(Serialize)]
+        struct OldPalette {
+            colors_index: HashMap<Color, usize>,
+            colors: Vec<Color>,
+        }
+
+        let colors = (0..16)
+            .map(|index| Color::new(index*16, 0, 0))
+            .collect::<Vec<Color>>();
+        let old_palette = OldPalette {
+            colors_index: colors.iter().enumerate().map(|(index, color)| (*color, index)).collect(),
+            colors: colors.clone(),
+        };
+        let data = rmp_serde::to_vec(&old_palette).unwrap();
+        let palette = rmp_serde::from_slice::<Palette>(&data).unwrap();
+
+        for (index, color) in colors.iter().enumerate() {
+            assert_eq!(palette.entry_at(index), Some(*color));
+        }
+        assert_eq!(palette.entry_at(PALETTE_SIZE - 1), Some(Color::default()));
+        assert_eq!(palette.transparent_index(), Some(DEFAULT_TRANSPARENT_INDEX));
+        assert_eq!(palette.color_index(&Color::new(32, 0, 0)), Some(2));
+        assert_eq!(
+            palette.color_indices(&Color::default()),
+            [0].into_iter().chain(16..PALETTE_SIZE).collect::<Vec<usize>>(),
+        );
     }
 }
@@ -34,6 +34,18 @@ macro_rules! factions {
                 )*
                 Err(Error::FactionInvalidString(v.into()))
             }
+
+            /// Identifier of the faction remap table.
+            pub fn id(&self) -> String {
+                format!("{self:?}").to_lowercase()
+            }
+
+            pub fn try_from_index(index: usize) -> Result<Self> {
+                [$(Self::$faction,)*]
+                    .get(index)
+                    .copied()
+                    .ok_or(Error::FactionInvalidIndex(index))
+            }
         }
     };
 }
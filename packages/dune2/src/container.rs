@@ -0,0 +1,456 @@
+use std::io::{
+    Read,
+    Write,
+};
+
+use flate2::Compression;
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::CompressionLevel;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::prelude::{
+    Error,
+    Result,
+};
+
+
+/// First bytes of an assets file.
+pub const ASSETS_MAGIC: [u8; 4] = *b"D2RC";
+
+/// Format version written by this version of the library. Files written
+/// before the header was introduced have no header and are read as version
+/// 0, version 1 files have a single payload and version 2 files are split
+/// in chunks.
+pub const ASSETS_FORMAT_VERSION: u16 = 2;
+
+/// Compression of the chunks of an assets file, see the `compression` bench
+/// for how they compare.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AssetsCompression {
+    None,
+    /// Smallest files.
+    #[default]
+    Deflate,
+    /// Zstandard, written at the fastest level of ruzstd.
+    Zstd,
+    /// LZ4 block prefixed by the decompressed size, the fastest to decode.
+    Lz4,
+}
+
+impl AssetsCompression {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+            Self::Zstd => 2,
+            Self::Lz4 => 3,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Result<Self> {
+        match code {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Deflate),
+            2 => Ok(Self::Zstd),
+            3 => Ok(Self::Lz4),
+            _ => Err(Error::AssetsInvalidCompression(code)),
+        }
+    }
+}
+
+/// Header of an assets file, all values are little endian:
+///
+/// | offset | size | content                                        |
+/// |--------|------|------------------------------------------------|
+/// | 0      | 4    | magic, `D2RC`                                  |
+/// | 4      | 2    | format version                                 |
+/// | 6      | 1    | compression code                               |
+/// | 7      | 1    | reserved, 0                                    |
+/// | 8      | 8    | size of the payload                            |
+/// | 16     | 4    | CRC-32 of the payload                          |
+///
+/// The payload follows the header. In version 1 files it is the assets,
+/// compressed with the compression of the header. In version 2 files it is
+/// the table of contents, a list of `AssetsChunkEntry`, followed by the
+/// chunks, each compressed with the compression of the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AssetsHeader {
+    pub version: u16,
+    pub compression: AssetsCompression,
+    pub payload_size: u64,
+    pub checksum: u32,
+}
+
+pub const ASSETS_HEADER_SIZE: usize = 20;
+
+pub(crate) fn checksum(data: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+impl AssetsHeader {
+    pub(crate) fn new(
+        compression: AssetsCompression,
+        payload: &[u8],
+    ) -> Self {
+        Self {
+            version: ASSETS_FORMAT_VERSION,
+            compression,
+            payload_size: payload.len() as u64,
+            checksum: checksum(payload),
+        }
+    }
+
+    /// Parse the header at the start of the given bytes. Returns `None` if
+    /// they do not start with the magic, which is the case of the files
+    /// written before the header was introduced.
+    pub fn parse(
+        data: &[u8],
+    ) -> Result<Option<Self>> {
+        if data.len() < ASSETS_HEADER_SIZE || data[0..4] != ASSETS_MAGIC {
+            return Ok(None);
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+
+        // Nothing else can be trusted in files of newer versions
+        if version > ASSETS_FORMAT_VERSION {
+            return Err(Error::AssetsUnsupportedVersion(version));
+        }
+
+        let mut payload_size = [0; 8];
+        let mut checksum = [0; 4];
+
+        payload_size.copy_from_slice(&data[8..16]);
+        checksum.copy_from_slice(&data[16..20]);
+
+        Ok(Some(Self {
+            version,
+            compression: AssetsCompression::from_code(data[6])?,
+            payload_size: u64::from_le_bytes(payload_size),
+            checksum: u32::from_le_bytes(checksum),
+        }))
+    }
+
+    pub fn to_bytes(&self) -> [u8; ASSETS_HEADER_SIZE] {
+        let mut data = [0; ASSETS_HEADER_SIZE];
+
+        data[0..4].copy_from_slice(&ASSETS_MAGIC);
+        data[4..6].copy_from_slice(&self.version.to_le_bytes());
+        data[6] = self.compression.code();
+        data[8..16].copy_from_slice(&self.payload_size.to_le_bytes());
+        data[16..20].copy_from_slice(&self.checksum.to_le_bytes());
+        data
+    }
+
+    /// Read the bytes of the header of an assets file, less bytes are read
+    /// if the file is smaller than the header.
+    pub(crate) fn read_bytes<R: Read>(
+        reader: &mut R,
+    ) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(ASSETS_HEADER_SIZE);
+
+        reader
+            .take(ASSETS_HEADER_SIZE as u64)
+            .read_to_end(&mut data)
+            .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))?;
+        Ok(data)
+    }
+
+    /// Read the header of an assets file, `None` for files without header.
+    pub fn read_from<R: Read>(
+        reader: &mut R,
+    ) -> Result<Option<Self>> {
+        Self::parse(&Self::read_bytes(reader)?)
+    }
+
+    pub fn write_to<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<()> {
+        writer.write_all(&self.to_bytes()).map_err(|err| {
+            Error::AssetsEncodeFailed(err.to_string())
+        })
+    }
+
+    /// Check the size and the checksum of the payload.
+    pub(crate) fn check_payload(
+        &self,
+        payload: &[u8],
+    ) -> Result<()> {
+        if payload.len() as u64 != self.payload_size {
+            return Err(Error::AssetsInvalidPayloadSize(self.payload_size));
+        }
+        if checksum(payload) != self.checksum {
+            return Err(Error::AssetsInvalidChecksum);
+        }
+        Ok(())
+    }
+
+    /// Read the payload following the header and check its checksum.
+    pub(crate) fn read_payload<R: Read>(
+        &self,
+        reader: &mut R,
+    ) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+
+        reader
+            .take(self.payload_size)
+            .read_to_end(&mut payload)
+            .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))?;
+
+        self.check_payload(&payload)?;
+        Ok(payload)
+    }
+}
+
+/// Part of the assets stored in a chunk.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AssetsChunk {
+    Palette,
+    Tilemaps,
+    TilePool,
+    Tileset(String),
+    Musics,
+    Units,
+    Structures,
+    Sounds,
+    Announcements,
+    RemapTables,
+    PaletteCycles,
+}
+
+/// Entry of the table of contents of a chunked assets file, the offsets
+/// start at the end of the table of contents.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AssetsChunkEntry {
+    pub chunk: AssetsChunk,
+    pub offset: u64,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+/// Serialize and compress a value.
+pub(crate) fn encode<T: Serialize>(
+    compression: AssetsCompression,
+    value: &T,
+) -> Result<Vec<u8>> {
+    let data = rmp_serde::to_vec(value)
+        .map_err(|err| Error::AssetsEncodeFailed(err.to_string()))?;
+
+    let encoded = match compression {
+        AssetsCompression::None => data,
+        AssetsCompression::Deflate => {
+            let mut output = DeflateEncoder::new(Vec::new(), Compression::best());
+
+            output
+                .write_all(&data)
+                .and_then(|_| output.finish())
+                .map_err(|err| Error::AssetsEncodeFailed(err.to_string()))?
+        },
+        AssetsCompression::Zstd => {
+            ruzstd::encoding::compress_to_vec(data.as_slice(), CompressionLevel::Fastest)
+        },
+        AssetsCompression::Lz4 => lz4_flex::compress_prepend_size(&data),
+    };
+
+    Ok(encoded)
+}
+
+/// Highest ratio between the decompressed and the compressed size of a LZ4
+/// block.
+const LZ4_MAX_RATIO: usize = 255;
+
+/// Decompress a LZ4 block prefixed by its decompressed size. The size is
+/// checked before anything is allocated, a crafted prefix could ask for
+/// up to 4 GB.
+fn lz4_decompress(
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let (size, block) = data
+        .split_first_chunk::<4>()
+        .ok_or(Error::AssetsDecodeFailed("missing LZ4 size".into()))?;
+    let size = u32::from_le_bytes(*size) as usize;
+
+    if size > block.len().saturating_mul(LZ4_MAX_RATIO) {
+        return Err(Error::AssetsDecodeFailed(format!("invalid LZ4 size {size}")));
+    }
+
+    lz4_flex::decompress(block, size)
+        .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))
+}
+
+/// Decompress and deserialize a value.
+pub(crate) fn decode<T: DeserializeOwned>(
+    compression: AssetsCompression,
+    data: &[u8],
+) -> Result<T> {
+    let decoded = match compression {
+        AssetsCompression::None => rmp_serde::from_slice(data),
+        AssetsCompression::Deflate => {
+            rmp_serde::decode::from_read(DeflateDecoder::new(data))
+        },
+        AssetsCompression::Zstd => {
+            let decoder = StreamingDecoder::new(data)
+                .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))?;
+
+            rmp_serde::decode::from_read(decoder)
+        },
+        AssetsCompression::Lz4 => rmp_serde::from_slice(&lz4_decompress(data)?),
+    };
+
+    decoded.map_err(|err| Error::AssetsDecodeFailed(err.to_string()))
+}
+
+/// Write a chunked assets file.
+pub(crate) fn write_chunks<W: Write>(
+    writer: &mut W,
+    compression: AssetsCompression,
+    chunks: &[(AssetsChunk, Vec<u8>)],
+) -> Result<()> {
+    let mut offset = 0;
+    let entries = chunks
+        .iter()
+        .map(|(chunk, data)| {
+            let entry = AssetsChunkEntry {
+                chunk: chunk.clone(),
+                offset,
+                size: data.len() as u64,
+                checksum: checksum(data),
+            };
+
+            offset += data.len() as u64;
+            entry
+        })
+        .collect::<Vec<AssetsChunkEntry>>();
+
+    let table_of_contents = rmp_serde::to_vec(&entries)
+        .map_err(|err| Error::AssetsEncodeFailed(err.to_string()))?;
+
+    AssetsHeader::new(compression, &table_of_contents).write_to(writer)?;
+
+    for data in std::iter::once(&table_of_contents).chain(chunks.iter().map(|(_, data)| data)) {
+        writer
+            .write_all(data)
+            .map_err(|err| Error::AssetsEncodeFailed(err.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assets::tests::{assert_same_assets, assets};
+    use crate::prelude::Assets;
+
+    fn header(
+        version: u16,
+        compression: AssetsCompression,
+        payload: &[u8],
+    ) -> AssetsHeader {
+        AssetsHeader {
+            version,
+            ..AssetsHeader::new(compression, payload)
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = header(1, AssetsCompression::Zstd, b"payload");
+        let data = header.to_bytes();
+
+        assert_eq!(AssetsHeader::parse(&data), Ok(Some(header)));
+        assert_eq!(AssetsHeader::parse(&data[..ASSETS_HEADER_SIZE - 1]), Ok(None));
+        assert_eq!(AssetsHeader::parse(&[0; ASSETS_HEADER_SIZE]), Ok(None));
+    }
+
+    #[test]
+    fn newer_versions_are_unsupported() {
+        let version = ASSETS_FORMAT_VERSION + 1;
+        let data = header(version, AssetsCompression::None, &[]).to_bytes();
+
+        assert_eq!(
+            AssetsHeader::parse(&data),
+            Err(Error::AssetsUnsupportedVersion(version)),
+        );
+        assert_eq!(
+            Assets::read_from(&mut data.as_slice()).unwrap_err(),
+            Error::AssetsUnsupportedVersion(version),
+        );
+    }
+
+    #[test]
+    fn unknown_compressions_are_rejected() {
+        let mut data = header(1, AssetsCompression::None, &[]).to_bytes();
+
+        data[6] = 9;
+        assert_eq!(AssetsHeader::parse(&data), Err(Error::AssetsInvalidCompression(9)));
+    }
+
+    #[test]
+    fn unversioned_files_are_read() {
+        let assets = assets();
+        let mut data = Vec::new();
+        let mut output = DeflateEncoder::new(&mut data, Compression::best());
+
+        output.write_all(&rmp_serde::to_vec(&assets).unwrap()).unwrap();
+        output.finish().unwrap();
+
+        assert_same_assets(&Assets::read_from(&mut data.as_slice()).unwrap(), &assets);
+    }
+
+    #[test]
+    fn version_1_files_are_read() {
+        let assets = assets();
+
+        for compression in [AssetsCompression::Deflate, AssetsCompression::Lz4] {
+            let payload = encode(compression, &assets).unwrap();
+            let mut data = header(1, compression, &payload).to_bytes().to_vec();
+
+            data.extend_from_slice(&payload);
+            assert_same_assets(&Assets::read_from(&mut data.as_slice()).unwrap(), &assets);
+
+            // Any change of the payload is caught by its checksum
+            let last = data.len() - 1;
+            data[last] ^= 1;
+            assert_eq!(
+                Assets::read_from(&mut data.as_slice()).unwrap_err(),
+                Error::AssetsInvalidChecksum,
+            );
+        }
+    }
+
+    #[test]
+    fn values_round_trip_with_every_compression() {
+        let value = (0..1000).map(|index| (index%7) as u8).collect::<Vec<u8>>();
+
+        for code in 0..4 {
+            let compression = AssetsCompression::from_code(code).unwrap();
+            let data = encode(compression, &value).unwrap();
+
+            assert_eq!(compression.code(), code);
+            assert_eq!(decode::<Vec<u8>>(compression, &data), Ok(value.clone()), "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn lz4_sizes_are_bounded() {
+        let data = encode(AssetsCompression::Lz4, &vec![0u8; 1000]).unwrap();
+        let mut crafted = data.clone();
+
+        crafted[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            decode::<Vec<u8>>(AssetsCompression::Lz4, &crafted),
+            Err(Error::AssetsDecodeFailed(format!("invalid LZ4 size {}", u32::MAX))),
+        );
+        assert!(decode::<Vec<u8>>(AssetsCompression::Lz4, &data[..3]).is_err());
+    }
+}
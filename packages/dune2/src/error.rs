@@ -3,17 +3,52 @@ use std::fmt;
 #[cfg(feature = "wasm")]
 use wasm_bindgen::JsValue;
 
-use crate::prelude::Size;
+use crate::prelude::{
+    Dune2Faction,
+    Size,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     FactionInvalidString(String),
+    FactionInvalidIndex(usize),
     #[cfg(feature = "wasm")]
     FactionInvalidValueType,
 
+    AssetsDecodeFailed(String),
+    AssetsEncodeFailed(String),
+    AssetsInvalidChecksum,
+    AssetsInvalidChunkBounds(u64, u64),
+    AssetsInvalidCompression(u8),
+    AssetsInvalidPayloadSize(u64),
+    AssetsUnsupportedVersion(u16),
+
+    BitmapInvalidDataSize(Size, usize),
+
+    PaletteInvalidIndex(usize),
+    PaletteCycleInvalid(String),
+    PaletteCyclesTooLong,
+    DitheringInvalidBufferSize(Size, usize),
+    DitheringNoCandidateColor,
+
+    RemapTableInvalidId(String),
+    RemapTableInvalidRange(String, usize),
+
+    MusicInvalidId(String),
+    MusicInvalidSequenceIndex(String, usize),
+
+    SoundInvalidId(String),
+    SoundInvalidFaction(Dune2Faction),
+    AnnouncementInvalidId(String),
+
+    StructureLayoutInvalidIndex(usize),
+
+    TilePoolInvalidIndex(usize),
+
     TilesetInvalidTileSize(String, Size),
     TilesetInvalidTileIndex(String, usize),
     TilesetInvalidId(String),
+    TilesetMixedTileStorage(String),
 }
 
 impl std::error::Error for Error {}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+
+use crate::container::{
+    checksum,
+    decode,
+};
+use crate::prelude::{
+    default_remap_tables,
+    Assets,
+    AssetsChunk,
+    AssetsChunkEntry,
+    AssetsCompression,
+    AssetsHeader,
+    Error,
+    FactionPalette,
+    FactionPaletteCache,
+    Palette,
+    Result,
+    Tileset,
+    TilePool,
+    ASSETS_HEADER_SIZE,
+};
+
+
+/// LazyAssets reads an assets file from memory, the tilesets of chunked
+/// files being decoded on first access. The other files are fully decoded
+/// when opened.
+pub struct LazyAssets {
+    data: Vec<u8>,
+    /// Offset of the first chunk in `data`.
+    chunks_offset: usize,
+    compression: AssetsCompression,
+    chunks: Vec<AssetsChunkEntry>,
+    /// Chunks of the tilesets which are not decoded yet.
+    pending_tilesets: HashMap<String, AssetsChunkEntry>,
+    pending_tile_pool: Option<AssetsChunkEntry>,
+    assets: Assets,
+    faction_palettes: FactionPaletteCache,
+}
+
+impl LazyAssets {
+    pub fn from_bytes(
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        let header = match AssetsHeader::parse(&data)? {
+            Some(header) if header.version >= 2 => header,
+            _ => {
+                let assets = Assets::read_from(&mut data.as_slice())?;
+
+                return Ok(Self {
+                    data: Vec::new(),
+                    chunks_offset: 0,
+                    compression: AssetsCompression::Deflate,
+                    chunks: Vec::new(),
+                    pending_tilesets: HashMap::new(),
+                    pending_tile_pool: None,
+                    assets,
+                    faction_palettes: FactionPaletteCache::default(),
+                });
+            },
+        };
+
+        let chunks_offset = usize::try_from(header.payload_size)
+            .ok()
+            .and_then(|size| size.checked_add(ASSETS_HEADER_SIZE))
+            .filter(|offset| *offset <= data.len())
+            .ok_or(Error::AssetsInvalidPayloadSize(header.payload_size))?;
+        let table_of_contents = &data[ASSETS_HEADER_SIZE..chunks_offset];
+
+        header.check_payload(table_of_contents)?;
+
+        let chunks = rmp_serde::from_slice::<Vec<AssetsChunkEntry>>(table_of_contents)
+            .map_err(|err| Error::AssetsDecodeFailed(err.to_string()))?;
+
+        let mut lazy_assets = Self {
+            data,
+            chunks_offset,
+            compression: header.compression,
+            chunks: Vec::new(),
+            pending_tilesets: HashMap::new(),
+            pending_tile_pool: None,
+            assets: Assets {
+                palette: Palette::default(),
+                tilesets: HashMap::new(),
+                tilemaps: Vec::new(),
+                musics: HashMap::new(),
+                units: Vec::new(),
+                structures: Vec::new(),
+                sounds: HashMap::new(),
+                announcements: Vec::new(),
+                remap_tables: default_remap_tables(),
+                palette_cycles: Vec::new(),
+                tile_pool: TilePool::new(),
+            },
+            faction_palettes: FactionPaletteCache::default(),
+        };
+
+        for entry in chunks.iter() {
+            lazy_assets.read_chunk(entry)?;
+        }
+
+        lazy_assets.chunks = chunks;
+        Ok(lazy_assets)
+    }
+
+    fn decode_chunk<T: DeserializeOwned>(
+        &self,
+        entry: &AssetsChunkEntry,
+    ) -> Result<T> {
+        let data = usize::try_from(entry.offset)
+            .ok()
+            .zip(usize::try_from(entry.size).ok())
+            .and_then(|(offset, size)| {
+                let start = self.chunks_offset.checked_add(offset)?;
+                self.data.get(start..start.checked_add(size)?)
+            })
+            .ok_or(Error::AssetsInvalidChunkBounds(entry.offset, entry.size))?;
+
+        if checksum(data) != entry.checksum {
+            return Err(Error::AssetsInvalidChecksum);
+        }
+        decode(self.compression, data)
+    }
+
+    /// Decode the chunks read when opening the file, the tilesets and the
+    /// tile pool are kept for later.
+    fn read_chunk(
+        &mut self,
+        entry: &AssetsChunkEntry,
+    ) -> Result<()> {
+        match &entry.chunk {
+            AssetsChunk::Palette => self.assets.palette = self.decode_chunk(entry)?,
+            AssetsChunk::Tilemaps => self.assets.tilemaps = self.decode_chunk(entry)?,
+            AssetsChunk::TilePool => self.pending_tile_pool = Some(entry.clone()),
+            AssetsChunk::Tileset(tileset_id) => {
+                self.pending_tilesets.insert(tileset_id.clone(), entry.clone());
+            },
+            AssetsChunk::Musics => self.assets.musics = self.decode_chunk(entry)?,
+            AssetsChunk::Units => self.assets.units = self.decode_chunk(entry)?,
+            AssetsChunk::Structures => self.assets.structures = self.decode_chunk(entry)?,
+            AssetsChunk::Sounds => self.assets.sounds = self.decode_chunk(entry)?,
+            AssetsChunk::Announcements => self.assets.announcements = self.decode_chunk(entry)?,
+            AssetsChunk::RemapTables => self.assets.remap_tables = self.decode_chunk(entry)?,
+            AssetsChunk::PaletteCycles => self.assets.palette_cycles = self.decode_chunk(entry)?,
+        }
+        Ok(())
+    }
+
+    /// Assets decoded so far, only the tilesets already accessed are
+    /// present.
+    pub fn assets(&self) -> &Assets {
+        &self.assets
+    }
+
+    /// Palette remapped with the table of the given faction, built once and
+    /// shared. The palette and the remap tables do not change once the file
+    /// is opened.
+    pub fn faction_palette(
+        &mut self,
+        faction_id: Option<&str>,
+    ) -> Rc<FactionPalette> {
+        let assets = &self.assets;
+
+        self.faction_palettes.get_or_insert_with(faction_id, || {
+            assets.faction_palette(faction_id)
+        })
+    }
+
+    /// Table of contents of chunked files, empty for the other files.
+    pub fn chunks(&self) -> &[AssetsChunkEntry] {
+        &self.chunks
+    }
+
+    /// Ids of all the tilesets of the file, decoded or not.
+    pub fn tileset_ids(&self) -> impl Iterator<Item = &str> {
+        self.assets.tilesets
+            .keys()
+            .chain(self.pending_tilesets.keys())
+            .map(|tileset_id| tileset_id.as_str())
+    }
+
+    pub fn is_tileset_loaded(
+        &self,
+        tileset_id: &str,
+    ) -> bool {
+        self.assets.tilesets.contains_key(tileset_id)
+    }
+
+    /// Get a tileset, decoding it on first access.
+    pub fn get_tileset(
+        &mut self,
+        tileset_id: &str,
+    ) -> Result<&Tileset> {
+        if let Some(entry) = self.pending_tilesets.get(tileset_id) {
+            let mut tileset: Tileset = self.decode_chunk(entry)?;
+
+            if tileset.pool_refs().is_some() {
+                if let Some(entry) = self.pending_tile_pool.take() {
+                    self.assets.tile_pool = self.decode_chunk(&entry)?;
+                }
+            }
+
+            tileset.link_tile_pool(&self.assets.tile_pool)?;
+            self.pending_tilesets.remove(tileset_id);
+            self.assets.tilesets.insert(tileset_id.into(), tileset);
+        }
+
+        self.assets.get_tileset(tileset_id)
+    }
+
+    /// Decode the remaining tilesets.
+    pub fn into_assets(
+        mut self,
+    ) -> Result<Assets> {
+        let tileset_ids = self.pending_tilesets
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+
+        for tileset_id in tileset_ids.iter() {
+            self.get_tileset(tileset_id)?;
+        }
+
+        if let Some(entry) = self.pending_tile_pool.take() {
+            self.assets.tile_pool = self.decode_chunk(&entry)?;
+        }
+        Ok(self.assets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assets::tests::{assert_same_assets, assets};
+
+    fn assets_data() -> Vec<u8> {
+        let mut data = Vec::new();
+
+        assets().write_to(&mut data).unwrap();
+        data
+    }
+
+    fn chunk_entry(
+        lazy_assets: &LazyAssets,
+        chunk: AssetsChunk,
+    ) -> AssetsChunkEntry {
+        lazy_assets.chunks()
+            .iter()
+            .find(|entry| entry.chunk == chunk)
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn tilesets_are_decoded_on_first_access() {
+        let mut lazy_assets = LazyAssets::from_bytes(assets_data()).unwrap();
+        let mut tileset_ids = lazy_assets.tileset_ids().collect::<Vec<&str>>();
+
+        tileset_ids.sort();
+        assert_eq!(tileset_ids, ["owned", "pooled"]);
+        assert!(lazy_assets.assets().tilesets.is_empty());
+
+        assert_eq!(lazy_assets.get_tileset("owned").unwrap().tile_count(), 3);
+        assert!(lazy_assets.is_tileset_loaded("owned"));
+        assert!(!lazy_assets.is_tileset_loaded("pooled"));
+
+        // Decoded and pending tilesets are both listed
+        let mut tileset_ids = lazy_assets.tileset_ids().collect::<Vec<&str>>();
+
+        tileset_ids.sort();
+        assert_eq!(tileset_ids, ["owned", "pooled"]);
+        assert_eq!(
+            lazy_assets.get_tileset("missing").unwrap_err(),
+            Error::TilesetInvalidId("missing".into()),
+        );
+    }
+
+    #[test]
+    fn pooled_tilesets_decode_the_tile_pool() {
+        let expected = assets();
+        let mut lazy_assets = LazyAssets::from_bytes(assets_data()).unwrap();
+
+        lazy_assets.get_tileset("owned").unwrap();
+        assert!(lazy_assets.assets().tile_pool.is_empty());
+
+        let tiles = lazy_assets.get_tileset("pooled")
+            .unwrap()
+            .tile_iter()
+            .map(|tile| tile.to_tile())
+            .collect::<Vec<_>>();
+        let expected_tiles = expected.tilesets["pooled"]
+            .tile_iter()
+            .map(|tile| tile.to_tile())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lazy_assets.assets().tile_pool.len(), expected.tile_pool.len());
+        assert_eq!(tiles, expected_tiles);
+    }
+
+    #[test]
+    fn into_assets_reads_every_chunk() {
+        let data = assets_data();
+        let mut lazy_assets = LazyAssets::from_bytes(data.clone()).unwrap();
+
+        lazy_assets.get_tileset("pooled").unwrap();
+        assert_same_assets(
+            &lazy_assets.into_assets().unwrap(),
+            &Assets::read_from(&mut data.as_slice()).unwrap(),
+        );
+        assert_same_assets(&Assets::read_from(&mut data.as_slice()).unwrap(), &assets());
+    }
+
+    #[test]
+    fn faction_palettes_are_shared() {
+        let mut lazy_assets = LazyAssets::from_bytes(assets_data()).unwrap();
+        let palette = lazy_assets.faction_palette(None);
+
+        assert!(Rc::ptr_eq(&palette, &lazy_assets.faction_palette(None)));
+    }
+
+    #[test]
+    fn corrupted_files_are_rejected() {
+        let data = assets_data();
+        let lazy_assets = LazyAssets::from_bytes(data.clone()).unwrap();
+        let chunks_offset = lazy_assets.chunks_offset;
+
+        // Chunks past the end of the file
+        let last = lazy_assets.chunks().last().unwrap().clone();
+        let truncated = data[..data.len() - 1].to_vec();
+
+        assert_eq!(
+            LazyAssets::from_bytes(truncated).err(),
+            Some(Error::AssetsInvalidChunkBounds(last.offset, last.size)),
+        );
+
+        // Tileset chunks are checked when decoded
+        let entry = chunk_entry(&lazy_assets, AssetsChunk::Tileset("owned".into()));
+        let mut corrupted = data.clone();
+
+        corrupted[chunks_offset + entry.offset as usize] ^= 1;
+
+        let mut lazy_assets = LazyAssets::from_bytes(corrupted).unwrap();
+
+        assert_eq!(lazy_assets.get_tileset("owned").unwrap_err(), Error::AssetsInvalidChecksum);
+        assert!(lazy_assets.get_tileset("pooled").is_ok());
+
+        // Table of contents larger than the file
+        let mut header = AssetsHeader::parse(&data).unwrap().unwrap();
+        let mut oversized = data.clone();
+
+        header.payload_size = data.len() as u64;
+        oversized[..ASSETS_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(
+            LazyAssets::from_bytes(oversized).err(),
+            Some(Error::AssetsInvalidPayloadSize(data.len() as u64)),
+        );
+    }
+}
@@ -1,15 +1,27 @@
 pub mod bitmap;
+pub mod catalog;
 pub mod color;
 pub mod constants;
+pub mod container;
 pub mod error;
+pub mod lazy_assets;
+pub mod music;
+pub mod palette_cycle;
 pub mod point;
+pub mod quantize;
+pub mod remap;
 pub mod rect;
 pub mod assets;
+pub mod savegame;
 pub mod shape;
 pub mod size;
+pub mod sound;
 pub mod tile;
+pub mod tile_pool;
+pub mod tile_storage;
 pub mod tilemap;
 pub mod tileset;
+pub mod upscale;
 pub mod prelude;
 
 mod utils;
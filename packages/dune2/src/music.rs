@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::prelude::{
+    Error,
+    Result,
+};
+
+
+/// Format of the sequences of a music.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub enum MusicFormat {
+    /// Each sequence is a complete Standard MIDI File.
+    #[default]
+    Midi,
+    /// A single sequence holds a raw Westwood AdLib driver file, with all its
+    /// subsongs.
+    Adlib,
+}
+
+/// Music is a collection of sequences.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Music {
+    id: String,
+    sequences: Vec<Box<[u8]>>,
+    #[serde(default)]
+    format: MusicFormat,
+}
+
+impl Music {
+    pub fn new(
+        music_id: &str,
+        format: MusicFormat,
+    ) -> Self {
+        Self {
+            id: music_id.into(),
+            sequences: Vec::new(),
+            format,
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        sequence: &[u8],
+    ) {
+        self.sequences.push(sequence.into());
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn format(&self) -> MusicFormat {
+        self.format
+    }
+
+    pub fn sequence_at(
+        &self,
+        sequence_index: usize,
+    ) -> Result<&[u8]> {
+        self.sequences
+            .get(sequence_index)
+            .map(|sequence| sequence.as_ref())
+            .ok_or(Error::MusicInvalidSequenceIndex(
+                self.id.clone(),
+                sequence_index,
+            ))
+    }
+
+    pub fn sequence_iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.sequences.iter().map(|sequence| sequence.as_ref())
+    }
+
+    pub fn sequence_count(&self) -> usize {
+        self.sequences.len()
+    }
+}
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    Error,
+    Palette,
+    Result,
+    PALETTE_SIZE,
+};
+
+
+/// Number of palette animation ticks per second.
+pub const PALETTE_CYCLE_TICK_RATE: u32 = 60;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PaletteCycleMode {
+    /// Colors move toward the higher indices.
+    #[default]
+    Forward,
+    /// Colors move toward the lower indices.
+    Backward,
+    /// Colors move toward the higher indices then back.
+    PingPong,
+}
+
+/// PaletteCycle rotates the colors of a range of palette indices, one step
+/// every `period` ticks.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PaletteCycle {
+    pub id: String,
+    pub first: usize,
+    pub count: usize,
+    pub period: u32,
+    #[serde(default)]
+    pub mode: PaletteCycleMode,
+}
+
+impl PaletteCycle {
+    pub fn check(&self) -> Result<()> {
+        if self.count == 0
+            || self.period == 0
+            || self.first.checked_add(self.count).is_none_or(|end| end > PALETTE_SIZE) {
+            return Err(Error::PaletteCycleInvalid(self.id.clone()));
+        }
+        Ok(())
+    }
+
+    /// Number of ticks after which the cycle repeats itself.
+    pub fn length(&self) -> Result<u32> {
+        if self.count == 0 {
+            return Ok(0);
+        }
+
+        let steps = match self.mode {
+            PaletteCycleMode::Forward | PaletteCycleMode::Backward => self.count,
+            PaletteCycleMode::PingPong => usize::max(1, 2*(self.count - 1)),
+        };
+
+        u32::try_from(steps)
+            .ok()
+            .and_then(|steps| self.period.checked_mul(steps))
+            .ok_or(Error::PaletteCycleInvalid(self.id.clone()))
+    }
+
+    /// Rotation of the range at the given tick, in indices.
+    pub fn offset_at(
+        &self,
+        tick: u32,
+    ) -> Result<usize> {
+        let length = self.length()?;
+
+        if length == 0 {
+            return Ok(0);
+        }
+
+        let step = (tick%length)/self.period;
+        let step = step as usize;
+
+        let offset = match self.mode {
+            PaletteCycleMode::Forward => step,
+            PaletteCycleMode::Backward => (self.count - step)%self.count,
+            PaletteCycleMode::PingPong if step < self.count => step,
+            PaletteCycleMode::PingPong => 2*(self.count - 1) - step,
+        };
+        Ok(offset)
+    }
+
+    /// Write the colors of `source` rotated for the given tick to `palette`.
+    pub fn apply(
+        &self,
+        source: &Palette,
+        palette: &mut Palette,
+        tick: u32,
+    ) -> Result<()> {
+        self.check()?;
+
+        let offset = self.offset_at(tick)?;
+
+        for i in 0..self.count {
+            let src_index = self.first + (i + self.count - offset)%self.count;
+
+            if let Some(color) = source.entry_at(src_index) {
+                palette.set_color(self.first + i, &color)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a%b) }
+}
+
+/// Ticks after which all the given cycles repeat themselves. The cycles are
+/// checked and their common length must fit in a `u32`.
+pub fn palette_cycles_length(
+    cycles: &[PaletteCycle],
+) -> Result<u32> {
+    cycles.iter().try_fold(1, |length, cycle| {
+        cycle.check()?;
+
+        let cycle_length = cycle.length()?;
+
+        (length/gcd(length, cycle_length))
+            .checked_mul(cycle_length)
+            .ok_or(Error::PaletteCyclesTooLong)
+    })
+}
+
+/// Ticks between two changes of the palette animated by the given cycles.
+pub fn palette_cycles_step(
+    cycles: &[PaletteCycle],
+) -> u32 {
+    cycles
+        .iter()
+        .map(|cycle| cycle.period)
+        .reduce(gcd)
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycle(
+        count: usize,
+        period: u32,
+        mode: PaletteCycleMode,
+    ) -> PaletteCycle {
+        PaletteCycle {
+            id: "test".into(),
+            first: 0,
+            count,
+            period,
+            mode,
+        }
+    }
+
+    #[test]
+    fn cycles_length_is_the_least_common_multiple() {
+        let cycles = [
+            cycle(4, 3, PaletteCycleMode::Forward),
+            cycle(3, 2, PaletteCycleMode::PingPong),
+        ];
+
+        assert_eq!(palette_cycles_length(&cycles), Ok(24));
+        assert_eq!(palette_cycles_step(&cycles), 1);
+    }
+
+    #[test]
+    fn overflowing_lengths_are_errors() {
+        let long_cycle = cycle(200, u32::MAX/100, PaletteCycleMode::Forward);
+
+        assert_eq!(long_cycle.length(), Err(Error::PaletteCycleInvalid("test".into())));
+        assert!(long_cycle.offset_at(0).is_err());
+
+        let cycles = [
+            cycle(251, u32::MAX/1000, PaletteCycleMode::Forward),
+            cycle(241, u32::MAX/1000, PaletteCycleMode::Forward),
+        ];
+
+        assert_eq!(palette_cycles_length(&cycles), Err(Error::PaletteCyclesTooLong));
+    }
+
+    #[test]
+    fn invalid_ranges_are_errors() {
+        let mut cycles = [cycle(4, 1, PaletteCycleMode::Forward)];
+
+        cycles[0].first = usize::MAX;
+
+        assert!(palette_cycles_length(&cycles).is_err());
+    }
+}
@@ -2,12 +2,24 @@
 
 pub use crate::assets::*;
 pub use crate::bitmap::*;
+pub use crate::catalog::*;
 pub use crate::constants::*;
+pub use crate::container::*;
 pub use crate::error::*;
+pub use crate::lazy_assets::*;
+pub use crate::music::*;
+pub use crate::palette_cycle::*;
+pub use crate::quantize::*;
+pub use crate::remap::*;
+pub use crate::savegame::*;
 pub use crate::shape::*;
+pub use crate::sound::*;
 pub use crate::tile::*;
+pub use crate::tile_pool::*;
+pub use crate::tile_storage::*;
 pub use crate::tilemap::*;
 pub use crate::tileset::*;
+pub use crate::upscale::*;
 
 #[cfg(feature = "wasm")]
 pub use crate::wasm::*;
@@ -0,0 +1,589 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    Color,
+    Error,
+    Palette,
+    RemapTable,
+    Result,
+    Size,
+};
+
+
+// Amplitude of the threshold map offsets of the ordered dithering, roughly
+// the distance between two neighbour shades of a 256 colors palette
+const ORDERED_DITHERING_SPREAD: f32 = 32.;
+
+const BAYER_MATRIX_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Metric used to compare two colors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorMetric {
+    /// Euclidean distance in the RGB space.
+    #[default]
+    Euclidean,
+    /// Euclidean distance in the RGB space weighted according to the mean
+    /// red value of the compared colors.
+    Redmean,
+    /// CIE76 ΔE, the euclidean distance in the CIELAB space.
+    CIELab,
+}
+
+fn srgb_to_linear(component: u8) -> f32 {
+    let c = component as f32/255.;
+
+    if c <= 0.04045 {
+        c/12.92
+    } else {
+        ((c + 0.055)/1.055).powf(2.4)
+    }
+}
+
+fn lab_f(t: f32) -> f32 {
+    const DELTA: f32 = 6./29.;
+
+    if t > DELTA*DELTA*DELTA {
+        t.cbrt()
+    } else {
+        t/(3.*DELTA*DELTA) + 4./29.
+    }
+}
+
+/// Convert a color to the CIELAB space using the D65 white point.
+fn color_to_lab(color: &Color) -> [f32; 3] {
+    let r = srgb_to_linear(color.red);
+    let g = srgb_to_linear(color.green);
+    let b = srgb_to_linear(color.blue);
+
+    let x = (0.4124*r + 0.3576*g + 0.1805*b)/0.95047;
+    let y = 0.2126*r + 0.7152*g + 0.0722*b;
+    let z = (0.0193*r + 0.1192*g + 0.9505*b)/1.08883;
+
+    let (fx, fy, fz) = (lab_f(x), lab_f(y), lab_f(z));
+
+    [116.*fy - 16., 500.*(fx - fy), 200.*(fy - fz)]
+}
+
+fn color_to_rgb(color: &Color) -> [f32; 3] {
+    [color.red as f32, color.green as f32, color.blue as f32]
+}
+
+impl ColorMetric {
+    /// Coordinates of the color in the space of the metric.
+    fn coordinates(
+        &self,
+        color: &Color,
+    ) -> [f32; 3] {
+        match self {
+            Self::Euclidean | Self::Redmean => color_to_rgb(color),
+            Self::CIELab => color_to_lab(color),
+        }
+    }
+
+    /// Squared distance between two colors coordinates.
+    fn squared_distance(
+        &self,
+        a: &[f32; 3],
+        b: &[f32; 3],
+    ) -> f32 {
+        let d0 = a[0] - b[0];
+        let d1 = a[1] - b[1];
+        let d2 = a[2] - b[2];
+
+        match self {
+            Self::Euclidean | Self::CIELab => d0*d0 + d1*d1 + d2*d2,
+            Self::Redmean => {
+                let red_mean = (a[0] + b[0])/2.;
+
+                (2. + red_mean/256.)*d0*d0
+                    + 4.*d1*d1
+                    + (2. + (255. - red_mean)/256.)*d2*d2
+            },
+        }
+    }
+
+    /// Lower bound of the factor applied to the squared difference along the
+    /// given axis, used to prune the k-d tree search.
+    fn min_axis_weight(
+        &self,
+        axis: usize,
+    ) -> f32 {
+        match self {
+            Self::Euclidean | Self::CIELab => 1.,
+            Self::Redmean => [2., 4., 2.][axis],
+        }
+    }
+
+    /// Distance between two colors.
+    pub fn distance(
+        &self,
+        a: &Color,
+        b: &Color,
+    ) -> f32 {
+        self.squared_distance(
+            &self.coordinates(a),
+            &self.coordinates(b),
+        ).sqrt()
+    }
+}
+
+/// Dithering algorithm used to convert truecolor pixels to palette indices.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Dithering {
+    /// Each pixel is mapped to its nearest color.
+    #[default]
+    None,
+    /// Error diffusion to the neighbour pixels.
+    FloydSteinberg,
+    /// 8x8 Bayer threshold map.
+    Ordered,
+}
+
+/// ColorMatcher maps arbitrary colors to the nearest entries of a palette.
+/// The candidate entries are indexed by a k-d tree over their coordinates
+/// and the results are cached, which makes it suitable for bulk queries.
+#[derive(Clone, Debug)]
+pub struct ColorMatcher {
+    metric: ColorMetric,
+    candidates: Vec<Candidate>,
+    // Positions of the candidates laid out as an implicit k-d tree, the
+    // middle of each range splitting it along the axis of its depth
+    tree: Vec<usize>,
+    // Position of the nearest candidate of the already matched colors
+    cache: HashMap<Color, usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Candidate {
+    index: usize,
+    color: Color,
+    coordinates: [f32; 3],
+}
+
+fn build_tree(
+    candidates: &[Candidate],
+) -> Vec<usize> {
+    fn split(
+        candidates: &[Candidate],
+        tree: &mut [usize],
+        depth: usize,
+    ) {
+        if tree.len() <= 1 {
+            return;
+        }
+
+        let axis = depth%3;
+        let middle = tree.len()/2;
+
+        tree.select_nth_unstable_by(middle, |a, b| {
+            candidates[*a].coordinates[axis].total_cmp(&candidates[*b].coordinates[axis])
+        });
+
+        let (before, after) = tree.split_at_mut(middle);
+
+        split(candidates, before, depth + 1);
+        split(candidates, &mut after[1..], depth + 1);
+    }
+
+    let mut tree = (0..candidates.len()).collect::<Vec<usize>>();
+
+    split(candidates, &mut tree, 0);
+    tree
+}
+
+impl ColorMatcher {
+    /// Create a matcher over all the opaque entries of the given palette.
+    pub fn new(
+        palette: &Palette,
+        metric: ColorMetric,
+    ) -> Self {
+        let candidates = palette
+            .iter()
+            .filter(|(index, _)| !palette.is_transparent(*index))
+            .map(|(index, color)| Candidate {
+                index,
+                color,
+                coordinates: metric.coordinates(&color),
+            })
+            .collect::<Vec<Candidate>>();
+
+        Self {
+            metric,
+            tree: build_tree(&candidates),
+            candidates,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Never match the given indices.
+    pub fn without_indices<I>(
+        mut self,
+        indices: I,
+    ) -> Self where I: IntoIterator<Item = usize> {
+        let indices = indices.into_iter().collect::<HashSet<usize>>();
+
+        self.candidates.retain(|candidate| !indices.contains(&candidate.index));
+        self.tree = build_tree(&self.candidates);
+        self.cache.clear();
+        self
+    }
+
+    /// Never match the indices recolored by the given remap table.
+    pub fn without_remap_table(
+        self,
+        remap_table: &RemapTable,
+    ) -> Self {
+        let indices = remap_table.ranges
+            .iter()
+            .flat_map(|range| range.source..range.source + range.count)
+            .collect::<Vec<usize>>();
+
+        self.without_indices(indices)
+    }
+
+    pub fn metric(&self) -> ColorMetric {
+        self.metric
+    }
+
+    fn nearest_candidate(
+        &mut self,
+        color: &Color,
+    ) -> Option<&Candidate> {
+        if let Some(position) = self.cache.get(color) {
+            return self.candidates.get(*position);
+        }
+
+        let coordinates = self.metric.coordinates(color);
+        let mut nearest = None;
+
+        self.search(&self.tree, 0, &coordinates, &mut nearest);
+
+        let (_, position) = nearest?;
+
+        self.cache.insert(*color, position);
+        self.candidates.get(position)
+    }
+
+    // Equally distant candidates are ordered by position, so that the first
+    // matching entry of the palette wins
+    fn search(
+        &self,
+        tree: &[usize],
+        depth: usize,
+        coordinates: &[f32; 3],
+        nearest: &mut Option<(f32, usize)>,
+    ) {
+        if tree.is_empty() {
+            return;
+        }
+
+        let axis = depth%3;
+        let middle = tree.len()/2;
+        let position = tree[middle];
+        let candidate = &self.candidates[position];
+        let distance = self.metric.squared_distance(coordinates, &candidate.coordinates);
+
+        if nearest.is_none_or(|(nearest_distance, nearest_position)| {
+            distance < nearest_distance
+                || (distance == nearest_distance && position < nearest_position)
+        }) {
+            *nearest = Some((distance, position));
+        }
+
+        let delta = coordinates[axis] - candidate.coordinates[axis];
+        let (near, far) = if delta < 0. {
+            (&tree[..middle], &tree[middle + 1..])
+        } else {
+            (&tree[middle + 1..], &tree[..middle])
+        };
+
+        self.search(near, depth + 1, coordinates, nearest);
+
+        let bound = self.metric.min_axis_weight(axis)*delta*delta;
+
+        if nearest.is_none_or(|(nearest_distance, _)| bound <= nearest_distance) {
+            self.search(far, depth + 1, coordinates, nearest);
+        }
+    }
+
+    /// Index of the nearest candidate entry, `None` if there is no candidate.
+    pub fn nearest(
+        &mut self,
+        color: &Color,
+    ) -> Option<usize> {
+        self.nearest_candidate(color).map(|candidate| candidate.index)
+    }
+
+    /// Convert a row-major buffer of truecolor pixels to palette indices.
+    /// Pixels outside of the optional `mask` are left to index 0 and take no
+    /// part in the error diffusion.
+    pub fn dither(
+        &mut self,
+        pixels: &[Color],
+        mask: Option<&[bool]>,
+        size: Size,
+        dithering: Dithering,
+    ) -> Result<Vec<u8>> {
+        let width = size.width as usize;
+        let height = size.height as usize;
+
+        if pixels.len() != width*height {
+            return Err(Error::DitheringInvalidBufferSize(size, pixels.len()));
+        }
+        if let Some(mask) = mask.filter(|mask| mask.len() != pixels.len()) {
+            return Err(Error::DitheringInvalidBufferSize(size, mask.len()));
+        }
+        if self.candidates.is_empty() {
+            return Err(Error::DitheringNoCandidateColor);
+        }
+
+        let is_masked = |offset: usize| mask.is_some_and(|mask| !mask[offset]);
+
+        match dithering {
+            Dithering::None => {
+                let indices = pixels.iter().enumerate().map(|(offset, color)| {
+                    if is_masked(offset) {
+                        return 0;
+                    }
+                    self.nearest_u8(color)
+                }).collect();
+
+                Ok(indices)
+            },
+            Dithering::Ordered => {
+                let indices = pixels.iter().enumerate().map(|(offset, color)| {
+                    if is_masked(offset) {
+                        return 0;
+                    }
+
+                    let threshold = BAYER_MATRIX_8X8[(offset/width)%8][(offset%width)%8];
+                    let bias = ORDERED_DITHERING_SPREAD*((threshold as f32 + 0.5)/64. - 0.5);
+
+                    self.nearest_u8(&color_from_rgb(&color_to_rgb(color).map(|c| c + bias)))
+                }).collect();
+
+                Ok(indices)
+            },
+            Dithering::FloydSteinberg => {
+                Ok(self.floyd_steinberg(pixels, is_masked, width, height))
+            },
+        }
+    }
+
+    fn nearest_u8(
+        &mut self,
+        color: &Color,
+    ) -> u8 {
+        // dither checks there is at least one candidate
+        self.nearest(color).unwrap_or(0) as u8
+    }
+
+    fn floyd_steinberg(
+        &mut self,
+        pixels: &[Color],
+        is_masked: impl Fn(usize) -> bool,
+        width: usize,
+        height: usize,
+    ) -> Vec<u8> {
+        let mut buffer = pixels.iter().map(color_to_rgb).collect::<Vec<[f32; 3]>>();
+        let mut indices = vec![0; pixels.len()];
+
+        for y in 0..height {
+            for x in 0..width {
+                let offset = y*width + x;
+
+                if is_masked(offset) {
+                    continue;
+                }
+
+                let wanted = buffer[offset];
+                let (index, actual) = self.nearest_candidate(&color_from_rgb(&wanted))
+                    .map(|candidate| (candidate.index as u8, color_to_rgb(&candidate.color)))
+                    .unwrap_or((0, wanted));
+                let error = [
+                    wanted[0] - actual[0],
+                    wanted[1] - actual[1],
+                    wanted[2] - actual[2],
+                ];
+
+                indices[offset] = index;
+
+                let mut diffuse = |dx: isize, dy: usize, weight: f32| {
+                    let nx = x as isize + dx;
+
+                    if nx >= 0 && (nx as usize) < width && y + dy < height {
+                        let neighbour_offset = (y + dy)*width + nx as usize;
+
+                        if is_masked(neighbour_offset) {
+                            return;
+                        }
+
+                        let neighbour = &mut buffer[neighbour_offset];
+
+                        for channel in 0..3 {
+                            neighbour[channel] += weight*error[channel];
+                        }
+                    }
+                };
+
+                diffuse( 1, 0, 7./16.);
+                diffuse(-1, 1, 3./16.);
+                diffuse( 0, 1, 5./16.);
+                diffuse( 1, 1, 1./16.);
+            }
+        }
+
+        indices
+    }
+}
+
+fn color_from_rgb(rgb: &[f32; 3]) -> Color {
+    let component = |c: f32| c.round().clamp(0., 255.) as u8;
+
+    Color::new(component(rgb[0]), component(rgb[1]), component(rgb[2]))
+}
+
+impl Palette {
+    /// Index of the opaque entry nearest to the given color.
+    pub fn nearest_color_index(
+        &self,
+        color: &Color,
+        metric: ColorMetric,
+    ) -> Option<usize> {
+        self.color_matcher(metric).nearest(color)
+    }
+
+    /// Matcher over the opaque entries of the palette for bulk queries.
+    pub fn color_matcher(
+        &self,
+        metric: ColorMetric,
+    ) -> ColorMatcher {
+        ColorMatcher::new(self, metric)
+    }
+
+    /// Convert a row-major buffer of truecolor pixels to the indices of the
+    /// opaque entries of the palette, see `ColorMatcher::dither`.
+    pub fn dither(
+        &self,
+        pixels: &[Color],
+        mask: Option<&[bool]>,
+        size: Size,
+        metric: ColorMetric,
+        dithering: Dithering,
+    ) -> Result<Vec<u8>> {
+        self.color_matcher(metric).dither(pixels, mask, size, dithering)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Brute force reference, the first nearest entry wins
+    fn nearest_by_scan(
+        palette: &Palette,
+        metric: ColorMetric,
+        color: &Color,
+    ) -> Option<usize> {
+        let coordinates = metric.coordinates(color);
+
+        palette
+            .iter()
+            .filter(|(index, _)| !palette.is_transparent(*index))
+            .map(|(index, entry)| {
+                (index, metric.squared_distance(&coordinates, &metric.coordinates(&entry)))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+
+    fn random_colors(
+        seed: u32,
+        count: usize,
+    ) -> Vec<Color> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        };
+
+        (0..count).map(|_| Color::new(next(), next(), next())).collect()
+    }
+
+    fn palette() -> Palette {
+        let mut palette = Palette::new();
+
+        // Duplicated entries exercise the ties
+        for (index, color) in random_colors(7, 200).iter().chain(random_colors(7, 56).iter()).enumerate() {
+            palette.set_color(index, color).unwrap();
+        }
+        palette
+    }
+
+    #[test]
+    fn tree_search_matches_the_scan() {
+        let palette = palette();
+
+        for metric in [ColorMetric::Euclidean, ColorMetric::Redmean, ColorMetric::CIELab] {
+            let mut matcher = palette.color_matcher(metric);
+
+            for color in random_colors(42, 2000) {
+                assert_eq!(
+                    matcher.nearest(&color),
+                    nearest_by_scan(&palette, metric, &color),
+                    "{metric:?} {color:?}",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn masked_pixels_do_not_diffuse() {
+        // Black and white palette
+        let mut palette = Palette::new();
+
+        palette.set_transparent_index(None).unwrap();
+        palette.set_color(1, &Color::new(255, 255, 255)).unwrap();
+
+        let size = Size { width: 3, height: 1 };
+        let gray = Color::new(120, 120, 120);
+        let pixels = [gray, Color::new(0, 0, 0), gray];
+        let mut matcher = palette.color_matcher(ColorMetric::Euclidean);
+
+        let indices = matcher
+            .dither(&pixels, None, size, Dithering::FloydSteinberg)
+            .unwrap();
+
+        assert_eq!(indices, [0, 0, 1]);
+
+        // The error of the first pixel is not carried through the masked one
+        let indices = matcher
+            .dither(&pixels, Some(&[true, false, true]), size, Dithering::FloydSteinberg)
+            .unwrap();
+
+        assert_eq!(indices, [0, 0, 0]);
+    }
+
+    #[test]
+    fn excluded_indices_are_never_matched() {
+        let palette = palette();
+        let mut matcher = palette
+            .color_matcher(ColorMetric::Euclidean)
+            .without_indices(0..128);
+
+        for color in random_colors(3, 500) {
+            assert!(matcher.nearest(&color).is_some_and(|index| index >= 128));
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    rgba_from_color,
+    Color,
+    Dune2Faction,
+    Error,
+    Palette,
+    Result,
+    COLOR_HARKONNEN,
+    PALETTE_SIZE,
+};
+
+
+// Faction colors ramps are 7 colors long and 16 colors apart in the palette
+pub const FACTION_RAMP_LENGTH: usize = 7;
+const FACTION_RAMP_STRIDE: usize = 16;
+
+/// Remap `count` palette indices starting at `source` to the same count of
+/// indices starting at `target`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct RemapRange {
+    pub source: usize,
+    pub target: usize,
+    pub count: usize,
+}
+
+/// RemapTable is a named set of palette indices remapping. It is used to
+/// recolor tiles according to a faction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemapTable {
+    pub id: String,
+    pub ranges: Vec<RemapRange>,
+}
+
+impl RemapTable {
+    /// Remap table of a built-in faction. The Harkonnen colors being the
+    /// reference, the table of the Harkonnen faction is empty.
+    pub fn with_faction(
+        faction: Dune2Faction,
+    ) -> Self {
+        let target = COLOR_HARKONNEN + FACTION_RAMP_STRIDE*(faction as usize);
+        let ranges = if target != COLOR_HARKONNEN {
+            vec![RemapRange {
+                source: COLOR_HARKONNEN,
+                target,
+                count: FACTION_RAMP_LENGTH,
+            }]
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            id: faction.id(),
+            ranges,
+        }
+    }
+
+    /// Check that the source and target indices of all the ranges are in
+    /// the palette.
+    pub fn validate(&self) -> Result<()> {
+        let in_palette = |start: usize, count: usize| {
+            start.checked_add(count).is_some_and(|end| end <= PALETTE_SIZE)
+        };
+
+        for (index, range) in self.ranges.iter().enumerate() {
+            if !in_palette(range.source, range.count) || !in_palette(range.target, range.count) {
+                return Err(Error::RemapTableInvalidRange(self.id.clone(), index));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remap(
+        &self,
+        index: usize,
+    ) -> usize {
+        self.ranges
+            .iter()
+            .find(|range| index >= range.source && index - range.source < range.count)
+            .map(|range| range.target + index - range.source)
+            .unwrap_or(index)
+    }
+}
+
+/// Remap tables of the built-in factions, indexed by faction id.
+pub fn default_remap_tables() -> HashMap<String, RemapTable> {
+    [
+        Dune2Faction::Harkonnen,
+        Dune2Faction::Atreides,
+        Dune2Faction::Ordos,
+        Dune2Faction::Fremen,
+        Dune2Faction::Sardaukar,
+        Dune2Faction::Mercenary,
+    ]
+        .iter()
+        .map(|faction| (faction.id(), RemapTable::with_faction(*faction)))
+        .collect()
+}
+
+/// FactionPaletteCache keeps the faction palettes built from an assets
+/// palette, indexed by remap table id. Its owner must not change the palette
+/// or the remap tables the palettes are built from.
+#[derive(Debug, Default)]
+pub struct FactionPaletteCache {
+    palettes: HashMap<Option<String>, Rc<FactionPalette>>,
+}
+
+impl FactionPaletteCache {
+    pub fn get_or_insert_with(
+        &mut self,
+        remap_table_id: Option<&str>,
+        f: impl FnOnce() -> FactionPalette,
+    ) -> Rc<FactionPalette> {
+        self.palettes
+            .entry(remap_table_id.map(String::from))
+            .or_insert_with(|| Rc::new(f()))
+            .clone()
+    }
+}
+
+/// FactionPalette is a 256 entries color lookup, obtained by applying a remap
+/// table to a palette. The RGBA values of the entries are precomputed for
+/// the rendering fast paths.
+#[derive(Clone, Debug)]
+pub struct FactionPalette {
+    colors: Box<[Option<Color>]>,
+    rgba: Box<[[u8; 4]; PALETTE_SIZE]>,
+}
+
+impl FactionPalette {
+    pub fn new(
+        palette: &Palette,
+        remap_table: Option<&RemapTable>,
+    ) -> Self {
+        let colors = (0..PALETTE_SIZE)
+            .map(|index| {
+                if palette.is_transparent(index) {
+                    return None;
+                }
+
+                let index = remap_table
+                    .map(|remap_table| remap_table.remap(index))
+                    .unwrap_or(index);
+                palette.entry_at(index)
+            })
+            .collect();
+
+        Self::from_colors(colors)
+    }
+
+    fn from_colors(colors: Box<[Option<Color>]>) -> Self {
+        let mut rgba = Box::new([[0; 4]; PALETTE_SIZE]);
+
+        for (rgba, color) in rgba.iter_mut().zip(colors.iter()) {
+            *rgba = rgba_from_color(*color);
+        }
+        Self { colors, rgba }
+    }
+
+    /// Palette where the Harkonnen colors ramp is replaced by the shades of
+    /// the given team color.
+    pub fn with_team_color(
+        palette: &Palette,
+        team_color: &Color,
+    ) -> Self {
+        let mut colors = Self::new(palette, None).colors;
+        let ramp = palette.team_color_ramp(team_color);
+
+        for (step, color) in ramp.iter().enumerate() {
+            colors[COLOR_HARKONNEN + step] = Some(*color);
+        }
+        Self::from_colors(colors)
+    }
+
+    pub fn color_at(
+        &self,
+        index: usize,
+    ) -> Option<Color> {
+        self.colors.get(index).copied().flatten()
+    }
+
+    /// RGBA values of the entries, transparent entries having a zero alpha.
+    pub fn rgba_table(&self) -> &[[u8; 4]; PALETTE_SIZE] {
+        &self.rgba
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_palette() -> Palette {
+        let mut palette = Palette::new();
+
+        for index in 0..PALETTE_SIZE {
+            palette.set_color(index, &Color::new(index as u8, 0, 0)).unwrap();
+        }
+        palette
+    }
+
+    fn custom_remap_table() -> RemapTable {
+        RemapTable {
+            id: "smugglers".into(),
+            ranges: vec![
+                RemapRange { source: COLOR_HARKONNEN, target: 32, count: 4 },
+                RemapRange { source: COLOR_HARKONNEN + 4, target: 250, count: 3 },
+            ],
+        }
+    }
+
+    #[test]
+    fn remap_indices() {
+        let remap_table = custom_remap_table();
+
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN - 1), COLOR_HARKONNEN - 1);
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN), 32);
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN + 3), 35);
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN + 4), 250);
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN + 6), 252);
+        assert_eq!(remap_table.remap(COLOR_HARKONNEN + 7), COLOR_HARKONNEN + 7);
+        assert_eq!(remap_table.remap(usize::MAX), usize::MAX);
+
+        let atreides = RemapTable::with_faction(Dune2Faction::Atreides);
+
+        assert!(RemapTable::with_faction(Dune2Faction::Harkonnen).ranges.is_empty());
+        assert_eq!(atreides.remap(COLOR_HARKONNEN), COLOR_HARKONNEN + FACTION_RAMP_STRIDE);
+    }
+
+    #[test]
+    fn validate_ranges() {
+        assert!(custom_remap_table().validate().is_ok());
+        for remap_table in default_remap_tables().values() {
+            assert!(remap_table.validate().is_ok());
+        }
+
+        for (range_index, range) in [
+            (1, RemapRange { source: 250, target: 0, count: 7 }),
+            (1, RemapRange { source: 0, target: PALETTE_SIZE, count: 1 }),
+            (1, RemapRange { source: usize::MAX, target: 0, count: 2 }),
+        ] {
+            let mut remap_table = custom_remap_table();
+
+            remap_table.ranges.truncate(range_index);
+            remap_table.ranges.push(range);
+            assert_eq!(
+                remap_table.validate(),
+                Err(Error::RemapTableInvalidRange("smugglers".into(), range_index)),
+            );
+        }
+    }
+
+    #[test]
+    fn custom_faction_palette() {
+        let palette = gradient_palette();
+        let faction_palette = FactionPalette::new(&palette, Some(&custom_remap_table()));
+
+        assert_eq!(faction_palette.color_at(COLOR_HARKONNEN), Some(Color::new(32, 0, 0)));
+        assert_eq!(faction_palette.color_at(COLOR_HARKONNEN + 5), Some(Color::new(251, 0, 0)));
+        assert_eq!(faction_palette.color_at(100), Some(Color::new(100, 0, 0)));
+        // The transparent index is not remapped
+        assert_eq!(faction_palette.color_at(0), None);
+        assert_eq!(faction_palette.rgba_table()[COLOR_HARKONNEN], [32, 0, 0, 255]);
+    }
+
+    #[test]
+    fn cache_shares_palettes() {
+        let palette = gradient_palette();
+        let remap_table = custom_remap_table();
+        let mut cache = FactionPaletteCache::default();
+        let mut build_count = 0;
+        let mut get = |cache: &mut FactionPaletteCache, remap_table_id: Option<&str>| {
+            cache.get_or_insert_with(remap_table_id, || {
+                build_count += 1;
+                FactionPalette::new(&palette, remap_table_id.map(|_| &remap_table))
+            })
+        };
+
+        let first = get(&mut cache, Some("smugglers"));
+        let second = get(&mut cache, Some("smugglers"));
+        let unmapped = get(&mut cache, None);
+
+        assert!(Rc::ptr_eq(&first, &second));
+        assert!(!Rc::ptr_eq(&first, &unmapped));
+        assert!(Rc::ptr_eq(&unmapped, &get(&mut cache, None)));
+        assert_eq!(build_count, 2);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::prelude::{
+    Dune2Faction,
+    Point,
+    Result,
+    Shape,
+    Tilemap,
+};
+
+
+pub const SAVEGAME_MAP_SIZE: u32 = 64;
+
+/// A house (player) state.
+#[derive(Clone, Debug)]
+pub struct SavegameHouse {
+    pub house: u16,
+    pub flags: u16,
+    pub credits: u16,
+    pub credits_storage: u16,
+    pub credits_quota: u16,
+    pub power_production: u16,
+    pub power_usage: u16,
+    pub unit_count: u16,
+    pub unit_count_max: u16,
+}
+
+impl SavegameHouse {
+    pub fn faction(&self) -> Result<Dune2Faction> {
+        Dune2Faction::try_from_index(self.house as usize)
+    }
+
+    pub fn is_human(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+}
+
+/// Fields shared by units and structures.
+#[derive(Clone, Debug)]
+pub struct SavegameObject {
+    pub index: u16,
+    pub kind: u8,
+    pub flags: u32,
+    pub house: u8,
+    /// Position in map coordinates, a tile being 256 units wide.
+    pub position: Point,
+    pub hitpoints: u16,
+}
+
+impl SavegameObject {
+    pub fn faction(&self) -> Result<Dune2Faction> {
+        Dune2Faction::try_from_index(self.house as usize)
+    }
+
+    /// Position of the tile the object is on.
+    pub fn tile_position(&self) -> Point {
+        Point {
+            x: self.position.x >> 8,
+            y: self.position.y >> 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SavegameUnit {
+    pub object: SavegameObject,
+}
+
+#[derive(Clone, Debug)]
+pub struct SavegameStructure {
+    pub object: SavegameObject,
+    pub upgrade_level: u8,
+    pub hitpoints_max: u16,
+}
+
+/// A map tile. Only the tiles which differ from the landscape generated from
+/// the scenario seed are stored in a savegame.
+#[derive(Clone, Copy, Debug)]
+pub struct SavegameTile {
+    pub index: u16,
+    /// Index of the ground tile in `ICON.ICN`.
+    pub ground_tile: u16,
+    pub overlay_tile: u8,
+    pub house: u8,
+    pub is_unveiled: bool,
+    pub has_unit: bool,
+    pub has_structure: bool,
+}
+
+impl SavegameTile {
+    pub fn position(&self) -> Point {
+        Point {
+            x: (self.index as u32%SAVEGAME_MAP_SIZE) as i32,
+            y: (self.index as u32/SAVEGAME_MAP_SIZE) as i32,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Savegame {
+    pub description: String,
+    pub version: u16,
+    pub houses: Vec<SavegameHouse>,
+    pub units: Vec<SavegameUnit>,
+    pub structures: Vec<SavegameStructure>,
+    pub tiles: Vec<SavegameTile>,
+}
+
+impl Savegame {
+    /// Build a tilemap of the saved ground tiles. Tiles missing from the
+    /// savegame, the ones equal to the landscape generated from the map
+    /// seed, are mapped to `default_tile`.
+    pub fn tilemap(
+        &self,
+        tileset_id: &str,
+        default_tile: usize,
+    ) -> Tilemap {
+        let mut tiles = vec![
+            default_tile;
+            (SAVEGAME_MAP_SIZE*SAVEGAME_MAP_SIZE) as usize
+        ];
+
+        for tile in self.tiles.iter() {
+            if let Some(dst) = tiles.get_mut(tile.index as usize) {
+                *dst = tile.ground_tile as usize;
+            }
+        }
+
+        Tilemap {
+            class: "savegame".into(),
+            shape: Shape {
+                rows: SAVEGAME_MAP_SIZE,
+                columns: SAVEGAME_MAP_SIZE,
+            },
+            tiles: tiles.into_boxed_slice(),
+            tileset: tileset_id.into(),
+        }
+    }
+}
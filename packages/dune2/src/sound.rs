@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    Dune2Faction,
+    Error,
+    Result,
+};
+
+
+/// Mono 16-bit PCM sound.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Sound {
+    sample_rate: u32,
+    samples: Box<[i16]>,
+}
+
+impl Sound {
+    pub fn new(
+        sample_rate: u32,
+        samples: &[i16],
+    ) -> Self {
+        Self {
+            sample_rate,
+            samples: samples.into(),
+        }
+    }
+
+    pub fn silence(
+        sample_rate: u32,
+        duration: f32,
+    ) -> Self {
+        let count = (duration*sample_rate as f32).round() as usize;
+
+        Self {
+            sample_rate,
+            samples: vec![0; count].into_boxed_slice(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32/self.sample_rate as f32
+    }
+
+    /// Linearly resample the sound to the given sample rate.
+    pub fn resample(
+        &self,
+        sample_rate: u32,
+    ) -> Self {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Self {
+                sample_rate,
+                samples: self.samples.clone(),
+            };
+        }
+
+        let ratio = self.sample_rate as f64/sample_rate as f64;
+        let count = (self.samples.len() as f64/ratio).round() as usize;
+        let last = self.samples.len() - 1;
+
+        let samples = (0..count).map(|index| {
+            let position = index as f64*ratio;
+            let i = usize::min(position as usize, last);
+            let j = usize::min(i + 1, last);
+            let t = position - i as f64;
+
+            ((1. - t)*self.samples[i] as f64 + t*self.samples[j] as f64).round() as i16
+        }).collect();
+
+        Self {
+            sample_rate,
+            samples,
+        }
+    }
+}
+
+/// An announcement is a sentence made of voice fragments. Each fragment id
+/// is prefixed by the letter of the announcing faction to get the actual
+/// sound id.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Announcement {
+    pub id: String,
+    pub fragments: Vec<String>,
+}
+
+/// Prefix of the voice fragments of the given faction.
+pub fn voice_prefix(
+    faction: Dune2Faction,
+) -> Result<char> {
+    match faction {
+        Dune2Faction::Atreides => Ok('A'),
+        Dune2Faction::Harkonnen => Ok('H'),
+        Dune2Faction::Ordos => Ok('O'),
+        _ => Err(Error::SoundInvalidFaction(faction)),
+    }
+}
@@ -1,14 +1,21 @@
-use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::prelude::*;
-use crate::utils::point_to_index;
+use crate::utils::{
+    from_hex,
+    point_to_index,
+    to_hex,
+};
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
-pub struct TileAnchorPosition { left: i32, top: i32 }
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TileAnchorPosition { pub(crate) left: i32, pub(crate) top: i32 }
 
 /// TileAnchor is an enum used to how to place the source tile when resizing a
 /// given tile.
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TileAnchor {
     Position (TileAnchorPosition),
 
@@ -26,7 +33,7 @@ pub enum TileAnchor {
 }
 
 impl TileAnchor {
-    fn to_position(
+    pub(crate) fn to_position(
         &self,
         src: &Size,
         dst: &Size,
@@ -35,8 +42,8 @@ impl TileAnchor {
             Self::Position (TileAnchorPosition { left, .. }) => *left,
 
             Self::TopLeft |
-            Self::CenterLeft => 0,
-            Self::BottomLeft |
+            Self::CenterLeft |
+            Self::BottomLeft => 0,
 
             Self::TopCenter |
             Self::Center |
@@ -69,24 +76,117 @@ impl TileAnchor {
 
 
 /// TileTransformation is an enum to specify which transformation you want to
-/// apply to a given tile.
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+/// apply to a given tile. Rotations are clockwise.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TileTransformation {
     FlipX,
     FlipY,
     FlipXY,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror along the top-left to bottom-right diagonal.
+    Transpose,
 }
 
+impl TileTransformation {
+    pub const ALL: [Self; 7] = [
+        Self::FlipX,
+        Self::FlipY,
+        Self::FlipXY,
+        Self::Rotate90,
+        Self::Rotate180,
+        Self::Rotate270,
+        Self::Transpose,
+    ];
+
+    /// Size of a tile of the given size once transformed, quarter turns and
+    /// transposition swap the width and the height.
+    pub fn transformed_size(
+        &self,
+        size: Size,
+    ) -> Size {
+        match self {
+            Self::Rotate90 | Self::Rotate270 | Self::Transpose => Size {
+                width: size.height,
+                height: size.width,
+            },
+            _ => size,
+        }
+    }
+
+    /// Coordinates in the source tile of the pixel at (x, y) in the
+    /// transformed tile.
+    fn source_position(
+        &self,
+        x: u32,
+        y: u32,
+        size: Size,
+    ) -> (u32, u32) {
+        match self {
+            Self::FlipX => (size.width - x - 1, y),
+            Self::FlipY => (x, size.height - y - 1),
+            Self::FlipXY | Self::Rotate180 => (
+                size.width - x - 1,
+                size.height - y - 1,
+            ),
+            Self::Rotate90 => (y, size.height - x - 1),
+            Self::Rotate270 => (size.width - y - 1, x),
+            Self::Transpose => (y, x),
+        }
+    }
+
+    /// Position in the transformed tile of a point of a tile of the given
+    /// size. Points are pixel corners, (width, height) being the bottom right
+    /// corner of the tile.
+    pub fn transform_point(
+        &self,
+        p: Point,
+        size: Size,
+    ) -> Point {
+        let width = size.width as i32;
+        let height = size.height as i32;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+        match self {
+            Self::FlipX => Point { x: width - p.x, y: p.y },
+            Self::FlipY => Point { x: p.x, y: height - p.y },
+            Self::FlipXY | Self::Rotate180 => Point {
+                x: width - p.x,
+                y: height - p.y,
+            },
+            Self::Rotate90 => Point { x: height - p.y, y: p.x },
+            Self::Rotate270 => Point { x: p.y, y: width - p.x },
+            Self::Transpose => Point { x: p.y, y: p.x },
+        }
+    }
+}
+
+
+/// Part of a trimmed tile covered by its data, the other pixels have the
+/// palette index the tile was trimmed with.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TileTrim {
+    pub offset: Point,
+    pub size: Size,
+    pub transparent_index: u8,
+}
+
+/// Tiles compare equal if their data, size, origin and trim are equal.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct Tile {
     data: Box<[u8]>,
     size: Size,
+    /// Point of the tile drawn at the position of the sprite, the center of
+    /// the tile if not specified.
+    origin: Option<Point>,
+    /// Only set for trimmed tiles, `data` then covers only part of the tile.
+    trim: Option<TileTrim>,
 }
 
 struct TileTransformIterator<'a> {
     current_x: u32,
     current_y: u32,
+    size: Size,
     tile: &'a Tile,
     transformation: TileTransformation,
 }
@@ -99,6 +199,7 @@ impl<'a> TileTransformIterator<'a> {
         return Self {
             current_x: 0,
             current_y: 0,
+            size: transformation.transformed_size(tile.size),
             tile,
             transformation,
         }
@@ -109,28 +210,19 @@ impl Iterator for TileTransformIterator<'_> {
     type Item = (u8, (u32, u32));
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_y >= self.tile.size.height {
+        if self.current_y >= self.size.height || self.size.width == 0 {
             return None
         };
 
-        let (x, y) = match self.transformation {
-            TileTransformation::FlipX => (
-                self.tile.size.width - self.current_x - 1,
-                self.current_y,
-            ),
-            TileTransformation::FlipY => (
-                self.current_x,
-                self.tile.size.height - self.current_y - 1,
-            ),
-            TileTransformation::FlipXY => (
-                self.tile.size.width - self.current_x - 1,
-                self.tile.size.height - self.current_y - 1,
-            ),
-        };
+        let (x, y) = self.transformation.source_position(
+            self.current_x,
+            self.current_y,
+            self.tile.size,
+        );
         let data = self.tile.data[(y*self.tile.size.width + x) as usize];
         let item = (data, (self.current_x, self.current_y));
 
-        if self.current_x < self.tile.size.width - 1 {
+        if self.current_x < self.size.width - 1 {
             self.current_x += 1;
         } else {
             self.current_x  = 0;
@@ -149,33 +241,146 @@ impl Tile {
         return Tile {
             data: data.into(),
             size,
+            origin: None,
+            trim: None,
         }
     }
 
+    pub fn with_origin(
+        mut self,
+        origin: Option<Point>,
+    ) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn origin(
+        &self,
+    ) -> Option<Point> {
+        self.origin
+    }
+
+    /// Origin of the tile, defaults to its center.
+    pub fn origin_or_center(
+        &self,
+    ) -> Point {
+        self.origin.unwrap_or(Point {
+            x: (self.size.width/2) as i32,
+            y: (self.size.height/2) as i32,
+        })
+    }
+
+    /// Size of the tile, trimmed tiles keep the size they had before being
+    /// trimmed.
     pub fn size(
         &self,
     ) -> Size {
         self.size
     }
 
+    /// Palette indices of the pixels of `data_rect`.
+    pub fn data(
+        &self,
+    ) -> &[u8] {
+        &self.data
+    }
+
+    pub fn trim_info(
+        &self,
+    ) -> Option<TileTrim> {
+        self.trim
+    }
+
+    /// Part of the tile covered by `data`, the whole tile if it is not
+    /// trimmed.
+    pub fn data_rect(
+        &self,
+    ) -> Rect {
+        match self.trim {
+            Some(trim) => Rect::from_point_and_size(trim.offset, trim.size),
+            None => Rect::from_point_and_size(Point::zero(), self.size),
+        }
+    }
+
+    /// Borrowed view of the tile.
+    pub fn view(
+        &self,
+    ) -> TileView<'_> {
+        TileView {
+            data: &self.data,
+            stride: self.data_rect().width() as usize,
+            size: self.size,
+            origin: self.origin,
+            trim: self.trim,
+        }
+    }
+
+    /// Palette index of the given pixel, `None` if out of the tile bounds.
+    pub fn index_at(
+        &self,
+        p: Point,
+    ) -> Option<u8> {
+        self.view().index_at(p)
+    }
+
+    /// Smallest rect containing all the pixels which are not of the given
+    /// index, `None` if there is no such pixel.
+    pub fn bounding_box(
+        &self,
+        transparent_index: u8,
+    ) -> Option<Rect> {
+        self.view().bounding_box(transparent_index)
+    }
+
+    /// Only keep the data of the bounding box of the pixels which are not of
+    /// the given index. The tile keeps its size and renders the same.
+    pub fn trim(
+        &self,
+        transparent_index: u8,
+    ) -> Tile {
+        self.view().trim(transparent_index)
+    }
+
+    /// Tile with data for all its pixels.
+    pub fn untrim(
+        &self,
+    ) -> Tile {
+        self.view().untrim()
+    }
+
     pub fn transform(
         &self,
         transform: Option<TileTransformation>,
     ) -> Tile {
         match transform {
+            Some(_) if self.trim.is_some() => self.untrim().transform(transform),
             Some(transform) => {
                 Self {
-                    size: self.size,
+                    size: transform.transformed_size(self.size),
+                    origin: self.origin.map(|origin| {
+                        transform.transform_point(origin, self.size)
+                    }),
                     data: TileTransformIterator::new(self, transform)
                         .map(|(d, _)| d)
                         .collect::<Vec<_>>()
                         .into_boxed_slice(),
+                    trim: None,
                 }
             },
             None => self.clone()
         }
     }
 
+    /// Apply the given transformations in order.
+    pub fn transform_all(
+        &self,
+        transforms: &[TileTransformation],
+    ) -> Tile {
+        transforms
+            .iter()
+            .fold(self.clone(), |tile, transform| tile.transform(Some(*transform)))
+    }
+
     pub fn resize(
         &self,
         size: Size,
@@ -184,6 +389,9 @@ impl Tile {
         if size == self.size {
             return self.clone();
         }
+        if self.trim.is_some() {
+            return self.untrim().resize(size, maybe_anchor);
+        }
 
         // 1. Get the anchor position
         let anchor = maybe_anchor.unwrap_or(TileAnchor::Center);
@@ -247,39 +455,425 @@ impl Tile {
             }
         }
 
+        // 4. Move the origin with the pixels, tiles without origin keep
+        // using their center
+        let origin = self.origin.map(|origin| origin + Point {
+            x: anchor_position.left,
+            y: anchor_position.top,
+        });
+
         Tile {
             data: data.into_boxed_slice(),
-            size
+            size,
+            origin,
+            trim: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TileData {
+    data: Box<[u8]>,
+    size: Size,
+    #[serde(default)]
+    origin: Option<Point>,
+    #[serde(default)]
+    trim: Option<TileTrim>,
+}
+
+#[derive(Serialize)]
+struct TileDataRef<'a> {
+    data: &'a [u8],
+    size: Size,
+    origin: Option<Point>,
+    trim: Option<TileTrim>,
+}
+
+/// Serialized form of the tiles in human readable formats, each row of
+/// `data_rect` being an hexadecimal string.
+#[derive(Deserialize, Serialize)]
+struct TileRows {
+    size: Size,
+    #[serde(default)]
+    origin: Option<Point>,
+    #[serde(default)]
+    trim: Option<TileTrim>,
+    rows: Vec<String>,
+}
+
+impl Serialize for Tile {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> where S: Serializer {
+        if serializer.is_human_readable() {
+            TileRows {
+                size: self.size,
+                origin: self.origin,
+                trim: self.trim,
+                rows: self.view().data_rows().map(to_hex).collect(),
+            }.serialize(serializer)
+        } else {
+            TileDataRef {
+                data: &self.data,
+                size: self.size,
+                origin: self.origin,
+                trim: self.trim,
+            }.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Tile {
+    fn deserialize<D>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        if !deserializer.is_human_readable() {
+            let data = TileData::deserialize(deserializer)?;
+
+            return Ok(Tile {
+                data: data.data,
+                size: data.size,
+                origin: data.origin,
+                trim: data.trim,
+            });
+        }
+
+        let data = TileRows::deserialize(deserializer)?;
+        let mut tile = Tile {
+            data: Box::new([]),
+            size: data.size,
+            origin: data.origin,
+            trim: data.trim,
+        };
+        let data_rect = tile.data_rect();
+        let mut pixels = Vec::with_capacity((data_rect.width()*data_rect.height()) as usize);
+
+        if data.rows.len() != data_rect.height() as usize {
+            return Err(de::Error::invalid_length(data.rows.len(), &"a row per line of the tile"));
+        }
+
+        for row in data.rows.iter() {
+            match from_hex(row) {
+                Some(row) if row.len() == data_rect.width() as usize => pixels.extend(row),
+                _ => return Err(de::Error::invalid_value(
+                    de::Unexpected::Str(row),
+                    &"an hexadecimal string of a pixel per column of the tile",
+                )),
+            }
+        }
+
+        tile.data = pixels.into_boxed_slice();
+        Ok(tile)
+    }
+}
+
+/// TileView is a borrowed tile, its data being part of a larger buffer such
+/// as the storage of a tileset. It has the same methods as `Tile`, methods
+/// producing a new tile returning an owned `Tile`.
+#[derive(Clone, Copy, Debug)]
+pub struct TileView<'a> {
+    /// Data of the tile, rows being `stride` bytes apart.
+    data: &'a [u8],
+    stride: usize,
+    size: Size,
+    origin: Option<Point>,
+    trim: Option<TileTrim>,
+}
+
+impl<'a> TileView<'a> {
+    pub(crate) fn new(
+        data: &'a [u8],
+        stride: usize,
+        size: Size,
+        origin: Option<Point>,
+        trim: Option<TileTrim>,
+    ) -> Self {
+        Self {
+            data,
+            stride,
+            size,
+            origin,
+            trim,
+        }
+    }
+
+    pub fn origin(
+        &self,
+    ) -> Option<Point> {
+        self.origin
+    }
+
+    /// Origin of the tile, defaults to its center.
+    pub fn origin_or_center(
+        &self,
+    ) -> Point {
+        self.origin.unwrap_or(Point {
+            x: (self.size.width/2) as i32,
+            y: (self.size.height/2) as i32,
+        })
+    }
+
+    pub fn size(
+        &self,
+    ) -> Size {
+        self.size
+    }
+
+    pub fn trim_info(
+        &self,
+    ) -> Option<TileTrim> {
+        self.trim
+    }
+
+    /// Part of the tile covered by the data, the whole tile if it is not
+    /// trimmed.
+    pub fn data_rect(
+        &self,
+    ) -> Rect {
+        match self.trim {
+            Some(trim) => Rect::from_point_and_size(trim.offset, trim.size),
+            None => Rect::from_point_and_size(Point::zero(), self.size),
+        }
+    }
+
+    /// Palette indices of the given row of `data_rect`.
+    pub fn data_row(
+        &self,
+        y: u32,
+    ) -> &'a [u8] {
+        let offset = y as usize*self.stride;
+        &self.data[offset..offset + self.data_rect().width() as usize]
+    }
+
+    fn data_rows(
+        &self,
+    ) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.data_rect().height()).map(|y| self.data_row(y))
+    }
+
+    /// Palette index of the given pixel, `None` if out of the tile bounds.
+    pub fn index_at(
+        &self,
+        p: Point,
+    ) -> Option<u8> {
+        if p.x < 0 || (p.x as u32) >= self.size.width {
+            return None;
+        }
+        if p.y < 0 || (p.y as u32) >= self.size.height {
+            return None;
+        }
+
+        let data_rect = self.data_rect();
+        let x = p.x - data_rect.left();
+        let y = p.y - data_rect.top();
+
+        if x < 0 || (x as u32) >= data_rect.width() || y < 0 || (y as u32) >= data_rect.height() {
+            self.trim.map(|trim| trim.transparent_index)
+        } else {
+            Some(self.data_row(y as u32)[x as usize])
+        }
+    }
+
+    /// Smallest rect containing all the pixels which are not of the given
+    /// index, `None` if there is no such pixel.
+    pub fn bounding_box(
+        &self,
+        transparent_index: u8,
+    ) -> Option<Rect> {
+        let (mut left, mut top) = (usize::MAX, usize::MAX);
+        let (mut right, mut bottom) = (0, 0);
+
+        for (y, row) in self.data_rows().enumerate() {
+            let first = row.iter().position(|index| *index != transparent_index);
+            let last = row.iter().rposition(|index| *index != transparent_index);
+
+            if let (Some(first), Some(last)) = (first, last) {
+                left = usize::min(left, first);
+                right = usize::max(right, last + 1);
+                top = usize::min(top, y);
+                bottom = y + 1;
+            }
+        }
+
+        if left == usize::MAX {
+            return None;
+        }
+
+        Some(Rect::from_point_and_size(
+            self.data_rect().top_left() + Point { x: left as i32, y: top as i32 },
+            Size {
+                width: (right - left) as u32,
+                height: (bottom - top) as u32,
+            },
+        ))
+    }
+
+    /// Owned copy of the tile.
+    pub fn to_tile(
+        &self,
+    ) -> Tile {
+        Tile {
+            data: self.data_rows().flatten().copied().collect(),
+            size: self.size,
+            origin: self.origin,
+            trim: self.trim,
+        }
+    }
+
+    /// Only keep the data of the bounding box of the pixels which are not of
+    /// the given index. The tile keeps its size and renders the same.
+    pub fn trim(
+        &self,
+        transparent_index: u8,
+    ) -> Tile {
+        if self.trim.is_some() {
+            return self.untrim().trim(transparent_index);
+        }
+
+        let rect = self
+            .bounding_box(transparent_index)
+            .unwrap_or(Rect::zero());
+        let data = rect
+            .iter()
+            .filter_map(|p| self.index_at(p))
+            .collect::<Vec<u8>>();
+
+        Tile {
+            data: data.into_boxed_slice(),
+            size: self.size,
+            origin: self.origin,
+            trim: Some(TileTrim {
+                offset: rect.top_left(),
+                size: rect.size(),
+                transparent_index,
+            }),
+        }
+    }
+
+    /// Tile with data for all its pixels.
+    pub fn untrim(
+        &self,
+    ) -> Tile {
+        if self.trim.is_none() {
+            return self.to_tile();
+        }
+
+        let data = Rect::from_point_and_size(Point::zero(), self.size)
+            .iter()
+            .filter_map(|p| self.index_at(p))
+            .collect::<Vec<u8>>();
+
+        Tile {
+            data: data.into_boxed_slice(),
+            size: self.size,
+            origin: self.origin,
+            trim: None,
+        }
+    }
+
+    pub fn transform(
+        &self,
+        transform: Option<TileTransformation>,
+    ) -> Tile {
+        self.to_tile().transform(transform)
+    }
+
+    /// Apply the given transformations in order.
+    pub fn transform_all(
+        &self,
+        transforms: &[TileTransformation],
+    ) -> Tile {
+        self.to_tile().transform_all(transforms)
+    }
+
+    pub fn resize(
+        &self,
+        size: Size,
+        maybe_anchor: Option<TileAnchor>,
+    ) -> Tile {
+        self.to_tile().resize(size, maybe_anchor)
+    }
+}
+
+/// Views compare equal if their tiles do, whatever the buffer they come
+/// from.
+impl PartialEq for TileView<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size
+            && self.origin == other.origin
+            && self.trim == other.trim
+            && self.data_rows().eq(other.data_rows())
+    }
+}
+
+impl Eq for TileView<'_> {}
+
+impl Hash for TileView<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.size.hash(state);
+        self.origin.hash(state);
+        self.trim.hash(state);
+
+        for row in self.data_rows() {
+            row.hash(state);
         }
     }
 }
 
 pub struct TileBitmap<'a> {
-    tile: &'a Tile,
-    palette: &'a Palette,
-    faction: Option<Dune2Faction>,
+    tile: TileView<'a>,
+    palette: Rc<FactionPalette>,
 }
 
 impl<'a> TileBitmap<'a> {
+    /// Use the assets palette and the remap table of the given faction.
     pub fn with_assets(
-        tile: &'a Tile,
+        tile: TileView<'a>,
         faction: Option<Dune2Faction>,
         assets: &'a Assets,
     ) -> Self {
+        let faction_id = faction.map(|faction| faction.id());
+
         Self {
-            faction,
-            palette: &assets.palette,
+            palette: Rc::new(assets.faction_palette(faction_id.as_deref())),
             tile,
         }
     }
 
+    /// Use the given palette and the built-in remap table of the given
+    /// faction.
     pub fn with_palette(
-        tile: &'a Tile,
+        tile: TileView<'a>,
         faction: Option<Dune2Faction>,
         palette: &'a Palette,
+    ) -> Self {
+        let remap_table = faction.map(RemapTable::with_faction);
+
+        Self {
+            palette: Rc::new(FactionPalette::new(palette, remap_table.as_ref())),
+            tile,
+        }
+    }
+
+    /// Use the assets palette with the faction colors replaced by the shades
+    /// of the given team color.
+    pub fn with_team_color(
+        tile: TileView<'a>,
+        team_color: &Color,
+        assets: &'a Assets,
+    ) -> Self {
+        Self {
+            palette: Rc::new(assets.team_color_palette(team_color)),
+            tile,
+        }
+    }
+
+    pub fn with_faction_palette(
+        tile: TileView<'a>,
+        palette: Rc<FactionPalette>,
     ) -> Self {
         Self {
-            faction,
             palette,
             tile,
         }
@@ -301,21 +895,277 @@ impl BitmapGetPixel for TileBitmap<'_> {
         &self,
         p: Point,
     ) -> Option<Color> {
-        if let Some(index) = point_to_index(p, self.size()) {
-            let mut color_index = self.tile.data[index] as usize;
+        self.tile
+            .index_at(p)
+            .and_then(|index| self.palette.color_at(index as usize))
+    }
+
+    fn get_palette_index(
+        &self,
+        p: Point,
+    ) -> Option<u8> {
+        self.tile.index_at(p)
+    }
+
+    fn get_rgba_row(
+        &self,
+        p: Point,
+        row: &mut [u8],
+    ) {
+        row.fill(0);
+
+        let table = self.palette.rgba_table();
+        let data_rect = self.tile.data_rect();
+
+        // Margins of trimmed tiles
+        if let Some(trim) = self.tile.trim {
+            if let Some((row_offset, _, count)) = clip_row(p, row.len()/4, self.size()) {
+                let (pixels, _) = row[4*row_offset..4*(row_offset + count)].as_chunks_mut::<4>();
+
+                pixels.fill(table[trim.transparent_index as usize]);
+            }
+        }
+
+        if let Some((row_offset, offset, count)) = clip_row(
+            p - data_rect.top_left(),
+            row.len()/4,
+            data_rect.size(),
+        ) {
+            let width = data_rect.width() as usize;
+            let indices = &self.tile.data_row((offset/width) as u32)[offset%width..offset%width + count];
+            let (pixels, _) = row[4*row_offset..4*(row_offset + count)].as_chunks_mut::<4>();
+
+            for (rgba, index) in pixels.iter_mut().zip(indices) {
+                *rgba = table[*index as usize];
+            }
+        }
+    }
+}
 
-            if let Some(faction) = self.faction {
-                let faction_palette_offset = 16*(faction as usize);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                if color_index >= COLOR_HARKONNEN
-                    && color_index < COLOR_HARKONNEN + 7 {
-                    color_index = color_index + faction_palette_offset
+    const SIZE_2X3: Size = Size { width: 2, height: 3 };
+
+    // 2x3 tile with a distinct index for each pixel:
+    // 0 1
+    // 2 3
+    // 4 5
+    fn tile_2x3() -> Tile {
+        Tile::new(&[0, 1, 2, 3, 4, 5], SIZE_2X3)
+    }
+
+    fn rows(tile: &Tile) -> Vec<Vec<u8>> {
+        let size = tile.size();
+
+        (0..size.height as i32)
+            .map(|y| (0..size.width as i32)
+                .map(|x| tile.index_at(Point { x, y }).unwrap())
+                .collect())
+            .collect()
+    }
+
+    #[test]
+    fn transformations_of_a_non_square_tile() {
+        for (transformation, expected) in [
+            (TileTransformation::FlipX, vec![vec![1, 0], vec![3, 2], vec![5, 4]]),
+            (TileTransformation::FlipY, vec![vec![4, 5], vec![2, 3], vec![0, 1]]),
+            (TileTransformation::FlipXY, vec![vec![5, 4], vec![3, 2], vec![1, 0]]),
+            (TileTransformation::Rotate90, vec![vec![4, 2, 0], vec![5, 3, 1]]),
+            (TileTransformation::Rotate180, vec![vec![5, 4], vec![3, 2], vec![1, 0]]),
+            (TileTransformation::Rotate270, vec![vec![1, 3, 5], vec![0, 2, 4]]),
+            (TileTransformation::Transpose, vec![vec![0, 2, 4], vec![1, 3, 5]]),
+        ] {
+            let transformed = tile_2x3().transform(Some(transformation));
+            let expected_size = Size {
+                width: expected[0].len() as u32,
+                height: expected.len() as u32,
+            };
+
+            assert_eq!(transformation.transformed_size(SIZE_2X3), expected_size);
+            assert_eq!(transformed.size(), expected_size);
+            assert_eq!(rows(&transformed), expected, "{transformation:?}");
+        }
+    }
+
+    #[test]
+    fn source_positions_invert_the_transformations() {
+        for transformation in TileTransformation::ALL {
+            let transformed = tile_2x3().transform(Some(transformation));
+            let size = transformed.size();
+
+            for y in 0..size.height {
+                for x in 0..size.width {
+                    let (src_x, src_y) = transformation.source_position(x, y, SIZE_2X3);
+
+                    assert_eq!(
+                        transformed.index_at(Point { x: x as i32, y: y as i32 }),
+                        Some((2*src_y + src_x) as u8),
+                        "{transformation:?} ({x}, {y})",
+                    );
                 }
             }
+        }
+    }
 
-            self.palette.color_at(color_index)
-        } else {
-            None
+    #[test]
+    fn transform_points() {
+        for (transformation, origin, corner) in [
+            (TileTransformation::FlipX, (2, 0), (0, 3)),
+            (TileTransformation::FlipY, (0, 3), (2, 0)),
+            (TileTransformation::FlipXY, (2, 3), (0, 0)),
+            (TileTransformation::Rotate90, (3, 0), (0, 2)),
+            (TileTransformation::Rotate180, (2, 3), (0, 0)),
+            (TileTransformation::Rotate270, (0, 2), (3, 0)),
+            (TileTransformation::Transpose, (0, 0), (3, 2)),
+        ] {
+            let transform = |x, y| transformation.transform_point(Point { x, y }, SIZE_2X3);
+
+            assert_eq!(transform(0, 0), Point { x: origin.0, y: origin.1 }, "{transformation:?}");
+            assert_eq!(transform(2, 3), Point { x: corner.0, y: corner.1 }, "{transformation:?}");
         }
+
+        // Each pixel lands where the corners of its square do
+        for transformation in TileTransformation::ALL {
+            let transformed = tile_2x3().transform(Some(transformation));
+
+            for (index, p) in (0..6).map(|index| (index, Point { x: index%2, y: index/2 })) {
+                let a = transformation.transform_point(p, SIZE_2X3);
+                let b = transformation.transform_point(p + Point { x: 1, y: 1 }, SIZE_2X3);
+                let corner = Point { x: a.x.min(b.x), y: a.y.min(b.y) };
+
+                assert_eq!(transformed.index_at(corner), Some(index as u8), "{transformation:?} {p:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn origins_follow_the_transformations() {
+        let origin = Point { x: 1, y: 2 };
+
+        for transformation in TileTransformation::ALL {
+            let tile = tile_2x3().with_origin(Some(origin)).transform(Some(transformation));
+
+            assert_eq!(tile.origin(), Some(transformation.transform_point(origin, SIZE_2X3)));
+            assert_eq!(tile_2x3().transform(Some(transformation)).origin(), None);
+        }
+
+        // The bottom left corner goes to the top left once rotated, then to
+        // the top right of the 3x2 tile once flipped
+        let tile = tile_2x3()
+            .with_origin(Some(Point { x: 0, y: 3 }))
+            .transform_all(&[TileTransformation::Rotate90, TileTransformation::FlipX]);
+
+        assert_eq!(tile.origin(), Some(Point { x: 3, y: 0 }));
+    }
+
+    #[test]
+    fn origins_follow_the_resizes() {
+        let size = Size { width: 4, height: 4 };
+
+        for (anchor, origin) in [
+            (TileAnchor::TopLeft, Point { x: 1, y: 2 }),
+            (TileAnchor::Center, Point { x: 2, y: 2 }),
+            (TileAnchor::BottomRight, Point { x: 3, y: 3 }),
+            (TileAnchor::Position(TileAnchorPosition { left: -1, top: 1 }), Point { x: 0, y: 3 }),
+        ] {
+            let resized = tile_2x3().with_origin(Some(Point { x: 1, y: 2 })).resize(size, Some(anchor));
+
+            assert_eq!(resized.origin(), Some(origin), "{anchor:?}");
+            assert_eq!(tile_2x3().resize(size, Some(anchor)).origin(), None, "{anchor:?}");
+        }
+
+        // Shrinking moves the origin out of the tile
+        let resized = tile_2x3()
+            .with_origin(Some(Point { x: 1, y: 0 }))
+            .resize(Size { width: 1, height: 1 }, Some(TileAnchor::BottomRight));
+
+        assert_eq!(resized.origin(), Some(Point { x: 0, y: -2 }));
+        assert_eq!(resized.index_at(Point::zero()), Some(5));
+    }
+
+    // 4x3 tile with a 2x2 opaque block, the other pixels are of index 9
+    fn sprite_4x3() -> Tile {
+        Tile::new(&[
+            9, 9, 9, 9,
+            9, 1, 2, 9,
+            9, 3, 9, 9,
+        ], Size { width: 4, height: 3 }).with_origin(Some(Point { x: 2, y: 3 }))
+    }
+
+    #[test]
+    fn trim_to_the_bounding_box() {
+        let tile = sprite_4x3();
+        let trimmed = tile.trim(9);
+
+        assert_eq!(
+            tile.bounding_box(9),
+            Some(Rect::from_point_and_size(Point { x: 1, y: 1 }, Size { width: 2, height: 2 })),
+        );
+        assert_eq!(trimmed.data(), [1, 2, 3, 9]);
+        assert_eq!(trimmed.size(), tile.size());
+        assert_eq!(trimmed.origin(), tile.origin());
+        assert_eq!(trimmed.bounding_box(9), tile.bounding_box(9));
+        assert_eq!(trimmed.index_at(Point { x: 0, y: 0 }), Some(9));
+        assert_eq!(trimmed.index_at(Point { x: 1, y: 2 }), Some(3));
+        assert_eq!(trimmed.index_at(Point { x: 4, y: 0 }), None);
+
+        // Trimming again with another index starts from the whole tile
+        assert_eq!(trimmed.trim(1).data_rect(), tile.data_rect());
+    }
+
+    #[test]
+    fn untrim_restores_the_tile() {
+        let tile = sprite_4x3();
+
+        for transparent_index in [9, 1, 0] {
+            assert_eq!(tile.trim(transparent_index).untrim(), tile, "{transparent_index}");
+        }
+        assert_eq!(tile.untrim(), tile);
+        // Trimmed tiles are untrimmed before being transformed
+        assert_eq!(
+            tile.trim(9).transform(Some(TileTransformation::Rotate90)),
+            tile.transform(Some(TileTransformation::Rotate90)),
+        );
+    }
+
+    #[test]
+    fn trim_transparent_tiles() {
+        let size = Size { width: 3, height: 2 };
+        let tile = Tile::new(&[7; 6], size);
+        let trimmed = tile.trim(7);
+
+        assert_eq!(tile.bounding_box(7), None);
+        assert!(trimmed.data().is_empty());
+        assert_eq!(trimmed.data_rect().size(), Size::zero());
+        assert_eq!(trimmed.bounding_box(7), None);
+        assert_eq!(trimmed.index_at(Point { x: 2, y: 1 }), Some(7));
+        assert_eq!(trimmed.untrim(), tile);
+    }
+
+    #[test]
+    fn views_compare_by_content() {
+        fn hash(tile: TileView) -> u64 {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            tile.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let tile = tile_2x3();
+        // Same tile in the first two columns of a 3 columns wide buffer
+        let buffer = [0, 1, 9, 2, 3, 9, 4, 5];
+        let view = TileView::new(&buffer, 3, SIZE_2X3, None, None);
+
+        assert_eq!(view, tile.view());
+        assert_eq!(hash(view), hash(tile.view()));
+        assert_eq!(view.to_tile(), tile);
+
+        let with_origin = tile.clone().with_origin(Some(Point { x: 1, y: 1 }));
+        let other_data = Tile::new(&[0, 1, 2, 3, 4, 6], SIZE_2X3);
+
+        assert_ne!(view, with_origin.view());
+        assert_ne!(view, other_data.view());
+        assert_ne!(view, tile.trim(0).view());
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::prelude::{
+    Error,
+    Result,
+    Tile,
+    TileView,
+};
+use crate::tile_storage::TileStorage;
+
+
+/// TilePool stores the tiles shared by several tilesets, identical tiles
+/// being stored once.
+#[derive(Clone, Debug, Default)]
+pub struct TilePool {
+    storage: Rc<TileStorage>,
+    /// Indices of the tiles by hash.
+    indices: HashMap<u64, Vec<usize>>,
+}
+
+fn tile_hash(
+    tile: TileView,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tile.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl TilePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_storage(
+        storage: TileStorage,
+    ) -> Self {
+        let mut indices = HashMap::<u64, Vec<usize>>::new();
+
+        for (index, tile) in storage.iter().enumerate() {
+            indices.entry(tile_hash(tile)).or_default().push(index);
+        }
+
+        Self {
+            storage: Rc::new(storage),
+            indices,
+        }
+    }
+
+    /// Index of the given tile in the pool, the tile is added if the pool
+    /// does not contain it yet.
+    pub fn insert(
+        &mut self,
+        tile: TileView,
+    ) -> usize {
+        if let Some(index) = self.find(tile) {
+            return index;
+        }
+
+        let index = Rc::make_mut(&mut self.storage).push(tile);
+
+        self.indices.entry(tile_hash(tile)).or_default().push(index);
+        index
+    }
+
+    /// Index of a tile identical to the given one.
+    pub fn find(
+        &self,
+        tile: TileView,
+    ) -> Option<usize> {
+        self.indices
+            .get(&tile_hash(tile))?
+            .iter()
+            .copied()
+            .find(|index| self.storage.tile(*index) == tile)
+    }
+
+    pub fn tile_at(
+        &self,
+        index: usize,
+    ) -> Result<TileView<'_>> {
+        self.storage
+            .get(index)
+            .ok_or(Error::TilePoolInvalidIndex(index))
+    }
+
+    pub fn tile_iter(&self) -> impl ExactSizeIterator<Item = TileView<'_>> {
+        self.storage.iter()
+    }
+
+    pub(crate) fn storage(&self) -> &Rc<TileStorage> {
+        &self.storage
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.len() == 0
+    }
+}
+
+impl FromIterator<Tile> for TilePool {
+    fn from_iter<I>(iter: I) -> Self where I: IntoIterator<Item = Tile> {
+        let mut storage = TileStorage::default();
+
+        // Keep the indices of duplicated tiles, lookups give the first one
+        for tile in iter {
+            storage.push(tile.view());
+        }
+        Self::with_storage(storage)
+    }
+}
+
+impl Serialize for TilePool {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> where S: Serializer {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(self.tile_iter().map(|tile| tile.to_tile()))
+        } else {
+            self.storage.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TilePool {
+    fn deserialize<D>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        if deserializer.is_human_readable() {
+            return Vec::<Tile>::deserialize(deserializer).map(TilePool::from_iter);
+        }
+
+        let storage = TileStorage::deserialize(deserializer)?;
+
+        if !storage.is_valid() {
+            return Err(de::Error::custom("invalid tile pool data"));
+        }
+        Ok(TilePool::with_storage(storage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::prelude::Size;
+
+    const SIZE: Size = Size { width: 2, height: 2 };
+
+    fn tile(index: u8) -> Tile {
+        Tile::new(&[index, 0, 0, index], SIZE)
+    }
+
+    #[test]
+    fn identical_tiles_are_inserted_once() {
+        let mut pool = TilePool::new();
+
+        assert_eq!(pool.insert(tile(1).view()), 0);
+        assert_eq!(pool.insert(tile(2).view()), 1);
+        assert_eq!(pool.insert(tile(1).view()), 0);
+        assert_eq!(pool.len(), 2);
+
+        assert_eq!(pool.find(tile(2).view()), Some(1));
+        assert_eq!(pool.find(tile(3).view()), None);
+        assert_eq!(pool.tile_at(1).unwrap().to_tile(), tile(2));
+        assert_eq!(pool.tile_at(2), Err(Error::TilePoolInvalidIndex(2)));
+    }
+
+    #[test]
+    fn collected_pool_keeps_duplicated_tiles() {
+        let pool = [tile(1), tile(2), tile(1)].into_iter().collect::<TilePool>();
+
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.find(tile(1).view()), Some(0));
+        assert_eq!(
+            pool.tile_iter().map(|tile| tile.to_tile()).collect::<Vec<Tile>>(),
+            vec![tile(1), tile(2), tile(1)],
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::{
+    Point,
+    Size,
+    TileTrim,
+    TileView,
+};
+use crate::utils::{
+    deserialize_bytes,
+    serialize_bytes,
+};
+
+
+/// TileLayout is an enum to specify how the pixel data of the tiles of a
+/// tileset is arranged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TileLayout {
+    /// Data of the tiles one after the other, trimmed tiles only store the
+    /// data of their data rect.
+    #[default]
+    Packed,
+    /// Tiles arranged on a grid of the given number of columns (at least
+    /// one), the data being an image usable as a texture atlas. The margins
+    /// of trimmed tiles are filled with their transparent index.
+    Atlas { columns: u32 },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct TileEntry {
+    offset: usize,
+    size: Size,
+    origin: Option<Point>,
+    trim: Option<TileTrim>,
+}
+
+impl TileEntry {
+    fn data_size(&self) -> Size {
+        self.trim.map_or(self.size, |trim| trim.size)
+    }
+}
+
+/// TileStorage stores the pixel data of several tiles in a single buffer.
+/// Missing fields default to an empty storage, which is how the empty tile
+/// pools of the previous format are read.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct TileStorage {
+    layout: TileLayout,
+    /// Size of the atlas cells, tiles of atlas storages must have this size.
+    cell_size: Size,
+    #[serde(serialize_with = "serialize_bytes", deserialize_with = "deserialize_bytes")]
+    data: Vec<u8>,
+    tiles: Vec<TileEntry>,
+}
+
+impl Default for TileStorage {
+    fn default() -> Self {
+        Self::new(TileLayout::Packed, Size::zero())
+    }
+}
+
+impl TileStorage {
+    pub(crate) fn new(
+        layout: TileLayout,
+        cell_size: Size,
+    ) -> Self {
+        Self {
+            layout,
+            cell_size,
+            data: Vec::new(),
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Same tiles stored with the given layout.
+    pub(crate) fn with_layout(
+        &self,
+        layout: TileLayout,
+        cell_size: Size,
+    ) -> Self {
+        let mut storage = Self::new(layout, cell_size);
+
+        for tile in self.iter() {
+            storage.push(tile);
+        }
+        storage
+    }
+
+    pub(crate) fn layout(&self) -> TileLayout {
+        self.layout
+    }
+
+    fn atlas_columns(&self) -> Option<usize> {
+        match self.layout {
+            TileLayout::Packed => None,
+            TileLayout::Atlas { columns } => Some(u32::max(columns, 1) as usize),
+        }
+    }
+
+    /// Distance between two rows of data of a tile which data is of the
+    /// given width.
+    fn stride(
+        &self,
+        data_width: u32,
+    ) -> usize {
+        match self.atlas_columns() {
+            Some(columns) => columns.saturating_mul(self.cell_size.width as usize),
+            None => data_width as usize,
+        }
+    }
+
+    /// Size of the atlas image, `None` for packed storages.
+    pub(crate) fn atlas_size(&self) -> Option<Size> {
+        self.atlas_columns().map(|_| {
+            let width = self.stride(0);
+
+            Size {
+                width: width as u32,
+                height: self.data.len().checked_div(width).unwrap_or(0) as u32,
+            }
+        })
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Append a tile, returns its index.
+    pub(crate) fn push(
+        &mut self,
+        tile: TileView,
+    ) -> usize {
+        let index = self.tiles.len();
+        let data_rect = tile.data_rect();
+        let stride = self.stride(data_rect.width());
+
+        let offset = match self.atlas_columns() {
+            Some(columns) => {
+                let cell_width = self.cell_size.width as usize;
+                let cell_height = self.cell_size.height as usize;
+                let (column, row) = (index%columns, index/columns);
+                let cell_offset = row*cell_height*stride + column*cell_width;
+
+                // Tiles are added a row of cells at a time
+                let end = (row + 1)*cell_height*stride;
+                if self.data.len() < end {
+                    self.data.resize(end, 0);
+                }
+
+                if let Some(trim) = tile.trim_info() {
+                    for y in 0..cell_height {
+                        let start = cell_offset + y*stride;
+                        self.data[start..start + cell_width].fill(trim.transparent_index);
+                    }
+                }
+
+                let offset = cell_offset
+                    + data_rect.top() as usize*stride
+                    + data_rect.left() as usize;
+
+                for y in 0..data_rect.height() {
+                    let row = tile.data_row(y);
+                    let start = offset + y as usize*stride;
+                    self.data[start..start + row.len()].copy_from_slice(row);
+                }
+                offset
+            },
+            None => {
+                let offset = self.data.len();
+
+                for y in 0..data_rect.height() {
+                    self.data.extend_from_slice(tile.data_row(y));
+                }
+                offset
+            },
+        };
+
+        self.tiles.push(TileEntry {
+            offset,
+            size: tile.size(),
+            origin: tile.origin(),
+            trim: tile.trim_info(),
+        });
+        index
+    }
+
+    /// Length of the data of a tile, from its first pixel to its last one.
+    /// `None` if it overflows, which only happens with crafted files.
+    fn data_len(
+        &self,
+        entry: &TileEntry,
+    ) -> Option<usize> {
+        let data_size = entry.data_size();
+
+        if data_size.width > 0 && data_size.height > 0 {
+            (data_size.height as usize - 1)
+                .checked_mul(self.stride(data_size.width))?
+                .checked_add(data_size.width as usize)
+        } else {
+            Some(0)
+        }
+    }
+
+    /// Check that the data of every tile is in the buffer, storages read
+    /// from a file are checked before any view is created.
+    pub(crate) fn is_valid(&self) -> bool {
+        self.tiles.iter().all(|entry| {
+            let fits = |offset: i32, length: u32, bound: u32| {
+                u32::try_from(offset)
+                    .ok()
+                    .and_then(|offset| offset.checked_add(length))
+                    .is_some_and(|end| end <= bound)
+            };
+            let data_rect_fits = entry.trim.is_none_or(|trim| {
+                fits(trim.offset.x, trim.size.width, entry.size.width)
+                    && fits(trim.offset.y, trim.size.height, entry.size.height)
+            });
+            let data_fits = self.data_len(entry)
+                .and_then(|len| entry.offset.checked_add(len))
+                .is_some_and(|end| end <= self.data.len());
+
+            data_rect_fits && data_fits
+        })
+    }
+
+    fn view(
+        &self,
+        entry: &TileEntry,
+    ) -> TileView<'_> {
+        let data_size = entry.data_size();
+        let stride = self.stride(data_size.width);
+        // Entries are valid, they were pushed or checked by `is_valid`
+        let len = self.data_len(entry).unwrap_or(0);
+
+        TileView::new(
+            &self.data[entry.offset..entry.offset + len],
+            stride,
+            entry.size,
+            entry.origin,
+            entry.trim,
+        )
+    }
+
+    pub(crate) fn get(
+        &self,
+        index: usize,
+    ) -> Option<TileView<'_>> {
+        self.tiles.get(index).map(|entry| self.view(entry))
+    }
+
+    /// Same as `get`, panics if the index is out of bounds.
+    pub(crate) fn tile(
+        &self,
+        index: usize,
+    ) -> TileView<'_> {
+        self.view(&self.tiles[index])
+    }
+
+    pub(crate) fn iter(&self) -> impl ExactSizeIterator<Item = TileView<'_>> {
+        self.tiles.iter().map(|entry| self.view(entry))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.tiles.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::prelude::Tile;
+
+    fn storage_with_entry(
+        offset: usize,
+        trim: Option<TileTrim>,
+    ) -> TileStorage {
+        let mut storage = TileStorage::new(TileLayout::Packed, Size::zero());
+
+        storage.data = vec![0; 16];
+        storage.tiles.push(TileEntry {
+            offset,
+            size: Size { width: 4, height: 4 },
+            origin: None,
+            trim,
+        });
+        storage
+    }
+
+    fn trim(x: i32, y: i32, width: u32, height: u32) -> Option<TileTrim> {
+        Some(TileTrim {
+            offset: Point { x, y },
+            size: Size { width, height },
+            transparent_index: 0,
+        })
+    }
+
+    #[test]
+    fn crafted_entries_are_invalid() {
+        assert!(storage_with_entry(0, None).is_valid());
+        assert!(storage_with_entry(12, trim(1, 2, 2, 2)).is_valid());
+
+        for (offset, trim) in [
+            (1, None),
+            (usize::MAX, None),
+            (0, trim(-1, 0, 2, 2)),
+            (0, trim(3, 0, 2, 2)),
+            (0, trim(1, 1, u32::MAX, 1)),
+            (0, trim(1, 1, 1, u32::MAX)),
+            (0, trim(i32::MAX, 0, u32::MAX, 1)),
+        ] {
+            assert!(!storage_with_entry(offset, trim).is_valid(), "{offset} {trim:?}");
+        }
+
+        let mut atlas = storage_with_entry(0, None);
+
+        atlas.layout = TileLayout::Atlas { columns: u32::MAX };
+        atlas.cell_size = Size { width: u32::MAX, height: 4 };
+        assert!(!atlas.is_valid());
+    }
+
+    #[test]
+    fn packed_and_atlas_storages_hold_the_same_tiles() {
+        let size = Size { width: 2, height: 2 };
+        let tiles = [
+            Tile::new(&[1, 2, 3, 4], size),
+            Tile::new(&[0, 0, 0, 5], size).with_origin(Some(Point { x: 1, y: 0 })).trim(0),
+            Tile::new(&[6, 7, 8, 9], size),
+        ];
+        let mut packed = TileStorage::new(TileLayout::Packed, size);
+
+        for tile in tiles.iter() {
+            packed.push(tile.view());
+        }
+
+        let atlas = packed.with_layout(TileLayout::Atlas { columns: 2 }, size);
+
+        // The trimmed tile only stores its single opaque pixel when packed
+        assert_eq!(packed.data(), [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(packed.atlas_size(), None);
+        assert_eq!(atlas.data(), [
+            1, 2, 0, 0,
+            3, 4, 0, 5,
+            6, 7, 0, 0,
+            8, 9, 0, 0,
+        ]);
+        assert_eq!(atlas.atlas_size(), Some(Size { width: 4, height: 4 }));
+
+        for storage in [&packed, &atlas] {
+            assert!(storage.is_valid());
+            assert_eq!(storage.len(), tiles.len());
+            assert_eq!(
+                storage.iter().map(|tile| tile.to_tile()).collect::<Vec<Tile>>(),
+                tiles,
+            );
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "wasm")]
@@ -63,6 +65,22 @@ impl Tilemap {
     }
 }
 
+impl Tilemap {
+    /// Replace the tiles indices by their new index given by `remap`, e.g.
+    /// after removing the duplicated tiles of the tileset.
+    pub fn remap_tiles(
+        &mut self,
+        remap: &[usize],
+    ) -> Result<()> {
+        for tile in self.tiles.iter_mut() {
+            *tile = *remap
+                .get(*tile)
+                .ok_or(Error::TilesetInvalidTileIndex(self.tileset.to_string(), *tile))?;
+        }
+        Ok(())
+    }
+}
+
 pub struct TilemapBitmap<'a> {
     bitmaps: Vec<TileBitmap<'a>>,
     tilemap_shape: Shape,
@@ -74,13 +92,45 @@ impl<'a> TilemapBitmap<'a> {
         tilemap: &Tilemap,
         faction: Option<Dune2Faction>,
         assets: &'a Assets,
+    ) -> Result<Self> {
+        let faction_id = faction.map(|faction| faction.id());
+        let palette = assets.faction_palette(faction_id.as_deref());
+
+        Self::try_with_faction_palette(tilemap, Rc::new(palette), assets)
+    }
+
+    pub fn try_with_team_color(
+        tilemap: &Tilemap,
+        team_color: &Color,
+        assets: &'a Assets,
+    ) -> Result<Self> {
+        let palette = assets.team_color_palette(team_color);
+
+        Self::try_with_faction_palette(tilemap, Rc::new(palette), assets)
+    }
+
+    pub fn try_with_faction_palette(
+        tilemap: &Tilemap,
+        palette: Rc<FactionPalette>,
+        assets: &'a Assets,
     ) -> Result<Self> {
         let tileset = assets.get_tileset(&tilemap.tileset)?;
+
+        Self::try_with_tileset(tilemap, tileset, palette)
+    }
+
+    /// Render the tilemap with the given tileset instead of the one it
+    /// references, e.g. an upscaled copy of it.
+    pub fn try_with_tileset(
+        tilemap: &Tilemap,
+        tileset: &'a Tileset,
+        palette: Rc<FactionPalette>,
+    ) -> Result<Self> {
         let bitmaps = tilemap.tiles
             .iter()
             .map(|tile_index| -> Result<TileBitmap> {
                 let tile = tileset.tile_at(*tile_index)?;
-                Ok(TileBitmap::with_assets(tile, faction, assets))
+                Ok(TileBitmap::with_faction_palette(tile, palette.clone()))
             })
             .collect::<Result<Vec<TileBitmap>>>()?;
 
@@ -102,8 +152,10 @@ impl Bitmap for TilemapBitmap<'_> {
     }
 }
 
-impl BitmapGetPixel for TilemapBitmap<'_> {
-    fn get_pixel(&self, point: Point) -> Option<Color> {
+impl TilemapBitmap<'_> {
+    /// Tile bitmap containing the given point and the point coordinates in
+    /// that bitmap.
+    fn locate(&self, point: Point) -> Option<(&TileBitmap<'_>, Point)> {
         if point.x < 0 || point.y < 0 {
             return None;
         }
@@ -118,9 +170,68 @@ impl BitmapGetPixel for TilemapBitmap<'_> {
         let tile_bitmap_x = (x%self.tile_size.width) as i32;
         let tile_bitmap_y = (y%self.tile_size.height) as i32;
 
-        self.bitmaps[index].get_pixel(Point {
+        Some((&self.bitmaps[index], Point {
             x: tile_bitmap_x,
             y: tile_bitmap_y,
-        })
+        }))
+    }
+}
+
+impl BitmapGetPixel for TilemapBitmap<'_> {
+    fn get_pixel(&self, point: Point) -> Option<Color> {
+        self.locate(point).and_then(|(bitmap, point)| bitmap.get_pixel(point))
+    }
+
+    fn get_palette_index(&self, point: Point) -> Option<u8> {
+        self.locate(point).and_then(|(bitmap, point)| bitmap.get_palette_index(point))
+    }
+
+    /// Rows are read tile by tile.
+    fn get_rgba_row(&self, point: Point, row: &mut [u8]) {
+        row.fill(0);
+
+        if let Some((row_offset, _, count)) = clip_row(point, row.len()/4, self.size()) {
+            let mut x = point.x + row_offset as i32;
+            let end = x + count as i32;
+
+            while x < end {
+                if let Some((bitmap, tile_point)) = self.locate(Point { x, y: point.y }) {
+                    let len = i32::min(bitmap.width() as i32 - tile_point.x, end - x);
+                    let start = 4*(x - point.x) as usize;
+
+                    bitmap.get_rgba_row(tile_point, &mut row[start..start + 4*len as usize]);
+                    x += len;
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tilemap(tiles: &[usize]) -> Tilemap {
+        Tilemap {
+            class: "map".into(),
+            shape: Shape { rows: 1, columns: tiles.len() as u32 },
+            tiles: tiles.into(),
+            tileset: "tileset".into(),
+        }
+    }
+
+    #[test]
+    fn tiles_are_remapped() {
+        let mut map = tilemap(&[0, 1, 2, 1]);
+
+        map.remap_tiles(&[0, 1, 0]).unwrap();
+        assert_eq!(map.tiles.as_ref(), [0, 1, 0, 1]);
+
+        assert_eq!(
+            tilemap(&[0, 3]).remap_tiles(&[0, 1, 0]),
+            Err(Error::TilesetInvalidTileIndex("tileset".into(), 3)),
+        );
     }
 }
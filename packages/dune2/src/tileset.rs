@@ -1,18 +1,52 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::prelude::{
     Error,
     Result,
     Size,
     Tile,
+    TileLayout,
+    TilePool,
+    TileView,
 };
+use crate::tile_storage::TileStorage;
 
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Tileset tiles are stored in a single buffer, either owned by the tileset
+/// or shared with other tilesets through a `TilePool`, in which case only
+/// the indices of the tiles in the pool are stored.
+#[derive(Clone, Debug)]
 pub struct Tileset {
     id: String,
     tile_size: Size,
+    storage: Rc<TileStorage>,
+    pool_refs: Option<Vec<usize>>,
+}
+
+#[derive(Deserialize)]
+struct TilesetData {
+    id: String,
+    tile_size: Size,
+    /// Tiles of the tilesets written before the tiles were stored in a
+    /// single buffer.
+    #[serde(default)]
     tiles: Vec<Tile>,
+    #[serde(default)]
+    pool_refs: Vec<usize>,
+    #[serde(default)]
+    storage: TileStorage,
+}
+
+#[derive(Serialize)]
+struct TilesetDataRef<'a> {
+    id: &'a str,
+    tile_size: Size,
+    tiles: &'a [Tile],
+    pool_refs: &'a [usize],
+    storage: &'a TileStorage,
 }
 
 impl Tileset {
@@ -20,22 +54,46 @@ impl Tileset {
         tileset_id: &str,
         tile_size: Size,
     ) -> Self {
-        let tiles = Vec::new();
+        let storage = TileStorage::new(TileLayout::Packed, tile_size);
         Self {
             id: tileset_id.into(),
             tile_size,
-            tiles,
+            storage: Rc::new(storage),
+            pool_refs: None,
         }
     }
 
-    pub fn add(
-        &mut self,
-        tile: Tile,
-    ) -> Result<()> {
-        let tile_size = tile.size();
+    /// Store the tiles with the given layout. Pooled tilesets keep the
+    /// layout of the pool.
+    pub fn with_layout(
+        mut self,
+        layout: TileLayout,
+    ) -> Self {
+        if self.pool_refs.is_none() {
+            self.storage = Rc::new(self.storage.with_layout(layout, self.tile_size));
+        }
+        self
+    }
 
+    pub fn layout(&self) -> TileLayout {
+        self.storage.layout()
+    }
+
+    /// Size of the image formed by `data` for the atlas layout.
+    pub fn atlas_size(&self) -> Option<Size> {
+        self.storage.atlas_size()
+    }
+
+    /// Pixel data of the tiles, pooled tilesets share the data of the pool.
+    pub fn data(&self) -> &[u8] {
+        self.storage.data()
+    }
+
+    fn check_tile_size(
+        &self,
+        tile_size: Size,
+    ) -> Result<()> {
         if self.tile_size == tile_size {
-            self.tiles.push(tile);
             Ok(())
         } else {
             Err(Error::TilesetInvalidTileSize(
@@ -45,24 +103,142 @@ impl Tileset {
         }
     }
 
+    pub fn add(
+        &mut self,
+        tile: Tile,
+    ) -> Result<()> {
+        if self.pool_refs.is_some() {
+            return Err(Error::TilesetMixedTileStorage(self.id.clone()));
+        }
+
+        self.check_tile_size(tile.size())?;
+        Rc::make_mut(&mut self.storage).push(tile.view());
+        Ok(())
+    }
+
+    /// Add a tile of the given pool. Tilesets cannot mix owned and pooled
+    /// tiles, and the tiles are resolved once the tileset is linked to the
+    /// complete pool with `link_tile_pool`.
+    pub fn add_from_pool(
+        &mut self,
+        pool: &TilePool,
+        pool_index: usize,
+    ) -> Result<()> {
+        if self.pool_refs.is_none() && self.tile_count() > 0 {
+            return Err(Error::TilesetMixedTileStorage(self.id.clone()));
+        }
+
+        self.check_tile_size(pool.tile_at(pool_index)?.size())?;
+        self.pool_refs.get_or_insert_with(Vec::new).push(pool_index);
+        Ok(())
+    }
+
+    /// Move the tiles to the given pool, identical tiles being shared. The
+    /// tileset must be linked to the pool with `link_tile_pool` once every
+    /// tileset was moved, the pool storage being copied otherwise each time
+    /// a tile is inserted.
+    pub fn move_to_pool(
+        &mut self,
+        pool: &mut TilePool,
+    ) -> Result<()> {
+        if self.pool_refs.is_some() {
+            return Err(Error::TilesetMixedTileStorage(self.id.clone()));
+        }
+
+        let pool_refs = self.storage
+            .iter()
+            .map(|tile| pool.insert(tile))
+            .collect::<Vec<usize>>();
+
+        self.storage = Rc::default();
+        self.pool_refs = Some(pool_refs);
+        Ok(())
+    }
+
+    /// Indices of the tiles in the pool if the tileset uses one.
+    pub fn pool_refs(&self) -> Option<&[usize]> {
+        self.pool_refs.as_deref()
+    }
+
+    /// Resolve the pool references of the tileset, e.g. once deserialized
+    /// or moved to the pool.
+    pub fn link_tile_pool(
+        &mut self,
+        pool: &TilePool,
+    ) -> Result<()> {
+        let Some(pool_refs) = self.pool_refs.as_ref() else {
+            return Ok(());
+        };
+
+        for index in pool_refs.iter() {
+            self.check_tile_size(pool.tile_at(*index)?.size())?;
+        }
+
+        self.storage = pool.storage().clone();
+        Ok(())
+    }
+
+    /// Remove the duplicated tiles, only the first occurrence of a tile is
+    /// kept. Returns the new index of each of the former tiles.
+    pub fn dedup(&mut self) -> Vec<usize> {
+        let mut indices = HashMap::<TileView, usize>::new();
+        let mut storage = TileStorage::new(self.layout(), self.tile_size);
+        let mut pool_refs = Vec::new();
+        let mut remap = Vec::with_capacity(self.tile_count());
+
+        for (index, tile) in self.tile_iter().enumerate() {
+            let new_index = *indices.entry(tile).or_insert_with(|| {
+                match &self.pool_refs {
+                    Some(refs) => {
+                        pool_refs.push(refs[index]);
+                        pool_refs.len() - 1
+                    },
+                    None => storage.push(tile),
+                }
+            });
+            remap.push(new_index);
+        }
+
+        if self.pool_refs.is_some() {
+            self.pool_refs = Some(pool_refs);
+        } else {
+            self.storage = Rc::new(storage);
+        }
+        remap
+    }
+
     pub fn get_id(&self) -> &str {
         &self.id
     }
 
+    /// Index of a tile in the storage.
+    fn storage_index(
+        &self,
+        tile_index: usize,
+    ) -> Option<usize> {
+        match &self.pool_refs {
+            Some(pool_refs) => pool_refs.get(tile_index).copied(),
+            None => (tile_index < self.storage.len()).then_some(tile_index),
+        }
+    }
+
     pub fn tile_at(
         &self,
         tile_index: usize,
-    ) -> Result<&Tile> {
-        self.tiles
-            .get(tile_index)
+    ) -> Result<TileView<'_>> {
+        self.storage_index(tile_index)
+            .and_then(|index| self.storage.get(index))
             .ok_or(Error::TilesetInvalidTileIndex(
                 self.id.clone(),
                 tile_index,
             ))
     }
 
-    pub fn tile_iter(&self) -> std::slice::Iter<'_, Tile> {
-        self.tiles.iter()
+    pub fn tile_iter(&self) -> impl ExactSizeIterator<Item = TileView<'_>> {
+        // Pool references are checked when linking the tileset to the pool
+        (0..self.tile_count()).map(|tile_index| {
+            self.storage.tile(self.storage_index(tile_index).unwrap_or(tile_index))
+        })
     }
 
     pub fn tile_size(&self) -> Size {
@@ -70,6 +246,212 @@ impl Tileset {
     }
 
     pub fn tile_count(&self) -> usize {
-        self.tiles.len()
+        match &self.pool_refs {
+            Some(pool_refs) => pool_refs.len(),
+            None => self.storage.len(),
+        }
+    }
+}
+
+impl Serialize for Tileset {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> where S: Serializer {
+        // Pooled tiles are stored in the pool only
+        let empty_storage = TileStorage::default();
+        let (storage, pool_refs) = match self.pool_refs.as_ref() {
+            Some(pool_refs) => (&empty_storage, pool_refs.as_slice()),
+            None => (self.storage.as_ref(), [].as_slice()),
+        };
+
+        // Human readable formats list the tiles one by one, the storage
+        // only keeping the layout
+        if serializer.is_human_readable() && self.pool_refs.is_none() {
+            let tiles = self.tile_iter()
+                .map(|tile| tile.to_tile())
+                .collect::<Vec<Tile>>();
+
+            return TilesetDataRef {
+                id: &self.id,
+                tile_size: self.tile_size,
+                tiles: &tiles,
+                pool_refs,
+                storage: &TileStorage::new(self.layout(), self.tile_size),
+            }.serialize(serializer);
+        }
+
+        TilesetDataRef {
+            id: &self.id,
+            tile_size: self.tile_size,
+            tiles: &[],
+            pool_refs,
+            storage,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tileset {
+    fn deserialize<D>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let data = TilesetData::deserialize(deserializer)?;
+        let storage = data.storage;
+
+        if !storage.is_valid() {
+            return Err(de::Error::custom(format!("invalid tileset '{}' data", data.id)));
+        }
+
+        let mut tileset = Self {
+            id: data.id,
+            tile_size: data.tile_size,
+            storage: Rc::new(storage),
+            pool_refs: None,
+        };
+
+        for tile in data.tiles {
+            tileset.add(tile).map_err(de::Error::custom)?;
+        }
+
+        // Pooled tilesets are linked to the pool once the assets are read
+        if !data.pool_refs.is_empty() {
+            if tileset.tile_count() > 0 {
+                return Err(de::Error::custom(
+                    Error::TilesetMixedTileStorage(tileset.id),
+                ));
+            }
+            tileset.pool_refs = Some(data.pool_refs);
+        }
+        Ok(tileset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: Size = Size { width: 2, height: 2 };
+
+    fn tile(index: u8) -> Tile {
+        Tile::new(&[index, 0, 0, index], SIZE)
+    }
+
+    fn tileset(
+        tileset_id: &str,
+        tiles: &[u8],
+    ) -> Tileset {
+        let mut tileset = Tileset::new(tileset_id, SIZE);
+
+        for index in tiles {
+            tileset.add(tile(*index)).unwrap();
+        }
+        tileset
+    }
+
+    fn tiles(tileset: &Tileset) -> Vec<Tile> {
+        tileset.tile_iter().map(|tile| tile.to_tile()).collect()
+    }
+
+    #[test]
+    fn moved_tilesets_share_the_pool_storage() {
+        let mut pool = TilePool::new();
+        let mut first = tileset("first", &[1, 2, 1]);
+        let mut second = tileset("second", &[2, 3]);
+
+        first.move_to_pool(&mut pool).unwrap();
+        second.move_to_pool(&mut pool).unwrap();
+        first.link_tile_pool(&pool).unwrap();
+        second.link_tile_pool(&pool).unwrap();
+
+        assert_eq!(pool.len(), 3);
+        assert_eq!(first.pool_refs(), Some([0, 1, 0].as_slice()));
+        assert_eq!(second.pool_refs(), Some([1, 2].as_slice()));
+        assert!(Rc::ptr_eq(&first.storage, pool.storage()));
+        assert!(Rc::ptr_eq(&second.storage, pool.storage()));
+        assert_eq!(tiles(&first), vec![tile(1), tile(2), tile(1)]);
+        assert_eq!(tiles(&second), vec![tile(2), tile(3)]);
+
+        assert_eq!(
+            first.move_to_pool(&mut pool),
+            Err(Error::TilesetMixedTileStorage("first".into())),
+        );
+    }
+
+    #[test]
+    fn pooled_and_owned_tiles_cannot_be_mixed() {
+        let pool = [tile(1)].into_iter().collect::<TilePool>();
+        let mut owned = tileset("owned", &[1]);
+        let mut pooled = Tileset::new("pooled", SIZE);
+
+        assert_eq!(
+            owned.add_from_pool(&pool, 0),
+            Err(Error::TilesetMixedTileStorage("owned".into())),
+        );
+
+        pooled.add_from_pool(&pool, 0).unwrap();
+        pooled.link_tile_pool(&pool).unwrap();
+        assert_eq!(tiles(&pooled), vec![tile(1)]);
+        assert_eq!(
+            pooled.add(tile(2)),
+            Err(Error::TilesetMixedTileStorage("pooled".into())),
+        );
+        assert_eq!(
+            pooled.add_from_pool(&pool, 1),
+            Err(Error::TilePoolInvalidIndex(1)),
+        );
+    }
+
+    #[test]
+    fn dedup_keeps_the_first_occurrences() {
+        let mut owned = tileset("owned", &[1, 2, 1, 3, 2]);
+
+        assert_eq!(owned.dedup(), vec![0, 1, 0, 2, 1]);
+        assert_eq!(tiles(&owned), vec![tile(1), tile(2), tile(3)]);
+
+        let mut pool = TilePool::new();
+        let mut pooled = tileset("pooled", &[3, 1, 3]);
+
+        pooled.move_to_pool(&mut pool).unwrap();
+        pooled.link_tile_pool(&pool).unwrap();
+
+        assert_eq!(pooled.dedup(), vec![0, 1, 0]);
+        assert_eq!(pooled.pool_refs(), Some([0, 1].as_slice()));
+        assert_eq!(tiles(&pooled), vec![tile(3), tile(1)]);
+    }
+
+    #[test]
+    fn legacy_tiles_are_migrated() {
+        // Layout of the tilesets written before the tiles were stored in a
+        // single buffer
+        #[derive(Serialize)]
+        struct OldTileset {
+            id: String,
+            tile_size: Size,
+            tiles: Vec<Tile>,
+        }
+
+        let old_tileset = |tiles: Vec<Tile>| {
+            rmp_serde::to_vec(&OldTileset {
+                id: "old".into(),
+                tile_size: SIZE,
+                tiles,
+            }).unwrap()
+        };
+
+        let data = old_tileset(vec![tile(1), tile(2)]);
+        let tileset = rmp_serde::from_slice::<Tileset>(&data).unwrap();
+
+        assert_eq!(tileset.get_id(), "old");
+        assert_eq!(tileset.layout(), TileLayout::Packed);
+        assert_eq!(tileset.pool_refs(), None);
+        assert_eq!(tiles(&tileset), vec![tile(1), tile(2)]);
+
+        let data = old_tileset(vec![tile(1), Tile::new(&[1; 6], Size { width: 3, height: 2 })]);
+        let err = rmp_serde::from_slice::<Tileset>(&data).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            Error::TilesetInvalidTileSize("old".into(), Size { width: 3, height: 2 }).to_string(),
+        );
     }
 }
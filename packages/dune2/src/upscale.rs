@@ -0,0 +1,398 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::prelude::{
+    Palette,
+    Result,
+    Size,
+    Tile,
+    Tileset,
+    TileView,
+};
+
+
+/// Pixel-art scaling filter. Filters work on palette indices, so scaled
+/// tiles can still be remapped to any faction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub enum ScaleFilter {
+    /// No filtering.
+    #[default]
+    Nearest,
+    Scale2x,
+    Scale3x,
+    /// Scale2x applied twice.
+    Scale4x,
+    /// Original name of Scale2x, both give the same result.
+    EPX,
+    /// 2xBR without blending, edges colors are picked among the neighbour
+    /// pixels ones.
+    XBR,
+}
+
+/// Indices of a tile with clamped coordinates access.
+struct Pixels<'a> {
+    data: &'a [u8],
+    size: Size,
+}
+
+impl Pixels<'_> {
+    fn at(&self, x: i32, y: i32) -> u8 {
+        let x = x.clamp(0, self.size.width as i32 - 1) as u32;
+        let y = y.clamp(0, self.size.height as i32 - 1) as u32;
+
+        self.data[(y*self.size.width + x) as usize]
+    }
+}
+
+fn scale2x(
+    data: &[u8],
+    size: Size,
+) -> Vec<u8> {
+    let pixels = Pixels { data, size };
+    let width = 2*size.width as usize;
+    let mut output = vec![0; 4*data.len()];
+
+    for y in 0..size.height as i32 {
+        for x in 0..size.width as i32 {
+            let b = pixels.at(x, y - 1);
+            let d = pixels.at(x - 1, y);
+            let e = pixels.at(x, y);
+            let f = pixels.at(x + 1, y);
+            let h = pixels.at(x, y + 1);
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            let offset = 2*(y as usize)*width + 2*x as usize;
+
+            output[offset] = e0;
+            output[offset + 1] = e1;
+            output[offset + width] = e2;
+            output[offset + width + 1] = e3;
+        }
+    }
+    output
+}
+
+fn scale3x(
+    data: &[u8],
+    size: Size,
+) -> Vec<u8> {
+    let pixels = Pixels { data, size };
+    let width = 3*size.width as usize;
+    let mut output = vec![0; 9*data.len()];
+
+    for y in 0..size.height as i32 {
+        for x in 0..size.width as i32 {
+            let a = pixels.at(x - 1, y - 1);
+            let b = pixels.at(x, y - 1);
+            let c = pixels.at(x + 1, y - 1);
+            let d = pixels.at(x - 1, y);
+            let e = pixels.at(x, y);
+            let f = pixels.at(x + 1, y);
+            let g = pixels.at(x - 1, y + 1);
+            let h = pixels.at(x, y + 1);
+            let i = pixels.at(x + 1, y + 1);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            let offset = 3*(y as usize)*width + 3*x as usize;
+
+            for (row, chunk) in block.chunks_exact(3).enumerate() {
+                output[offset + row*width..offset + row*width + 3].copy_from_slice(chunk);
+            }
+        }
+    }
+    output
+}
+
+/// Luma/chroma of the palette entries, used to compare colors like xBR
+/// does.
+struct YUVPalette {
+    entries: Vec<[f32; 3]>,
+    transparent_index: Option<usize>,
+}
+
+impl YUVPalette {
+    fn new(palette: &Palette) -> Self {
+        let entries = palette.iter().map(|(_, color)| {
+            let r = color.red as f32;
+            let g = color.green as f32;
+            let b = color.blue as f32;
+
+            [
+                0.299*r + 0.587*g + 0.114*b,
+                -0.169*r - 0.331*g + 0.5*b,
+                0.5*r - 0.419*g - 0.081*b,
+            ]
+        }).collect();
+
+        Self {
+            entries,
+            transparent_index: palette.transparent_index(),
+        }
+    }
+
+    fn distance(&self, a: u8, b: u8) -> f32 {
+        if a == b {
+            return 0.;
+        }
+
+        // Transparent pixels differ from any opaque ones
+        let (a, b) = (a as usize, b as usize);
+        if self.transparent_index == Some(a) || self.transparent_index == Some(b) {
+            return f32::MAX/16.;
+        }
+
+        let ya = self.entries[a];
+        let yb = self.entries[b];
+
+        48.*(ya[0] - yb[0]).abs() + 7.*(ya[1] - yb[1]).abs() + 6.*(ya[2] - yb[2]).abs()
+    }
+}
+
+fn xbr2x(
+    data: &[u8],
+    size: Size,
+    palette: &Palette,
+) -> Vec<u8> {
+    let pixels = Pixels { data, size };
+    let yuv = YUVPalette::new(palette);
+    let width = 2*size.width as usize;
+    let mut output = vec![0; 4*data.len()];
+
+    for y in 0..size.height as i32 {
+        for x in 0..size.width as i32 {
+            let e = pixels.at(x, y);
+
+            // Each corner is handled as the bottom right one of a kernel
+            // rotated by the quarter turns (cx, cy) -> (-cy, cx)
+            for (cx, cy) in [(1, 1), (-1, 1), (-1, -1), (1, -1)] {
+                let at = |dx: i32, dy: i32| {
+                    // Rotate the bottom right kernel offsets to the corner
+                    let (rx, ry) = match (cx, cy) {
+                        (1, 1) => (dx, dy),
+                        (-1, 1) => (-dy, dx),
+                        (-1, -1) => (-dx, -dy),
+                        _ => (dy, -dx),
+                    };
+                    pixels.at(x + rx, y + ry)
+                };
+
+                let b = at(0, -1);
+                let c = at(1, -1);
+                let d = at(-1, 0);
+                let f = at(1, 0);
+                let g = at(-1, 1);
+                let h = at(0, 1);
+                let i = at(1, 1);
+                let f4 = at(2, 0);
+                let i4 = at(2, 1);
+                let h5 = at(0, 2);
+                let i5 = at(1, 2);
+
+                let dist = |p, q| yuv.distance(p, q);
+
+                let edge_e = dist(e, c) + dist(e, g) + dist(i, f4) + dist(i, h5) + 4.*dist(h, f);
+                let edge_i = dist(h, d) + dist(h, i5) + dist(f, i4) + dist(f, b) + 4.*dist(e, i);
+
+                let pixel = if edge_e < edge_i && e != f && e != h {
+                    if dist(e, f) <= dist(e, h) { f } else { h }
+                } else {
+                    e
+                };
+
+                let sx = ((cx + 1)/2) as usize;
+                let sy = ((cy + 1)/2) as usize;
+
+                output[(2*y as usize + sy)*width + 2*x as usize + sx] = pixel;
+            }
+        }
+    }
+    output
+}
+
+impl ScaleFilter {
+    /// Scale factor of the filter.
+    pub fn factor(&self) -> u32 {
+        match self {
+            Self::Nearest => 1,
+            Self::Scale2x | Self::EPX | Self::XBR => 2,
+            Self::Scale3x => 3,
+            Self::Scale4x => 4,
+        }
+    }
+
+    /// Scale a tile. The palette is used by the filters comparing colors
+    /// rather than indices.
+    pub fn apply(
+        &self,
+        tile: TileView,
+        palette: &Palette,
+    ) -> Tile {
+        let tile = tile.untrim();
+        let size = tile.size();
+
+        if size.width == 0 || size.height == 0 {
+            return Tile::new(&[], self.factor()*size).with_origin(tile.origin());
+        }
+
+        let data = match self {
+            Self::Nearest => tile.data().to_vec(),
+            Self::Scale2x | Self::EPX => scale2x(tile.data(), size),
+            Self::Scale3x => scale3x(tile.data(), size),
+            Self::Scale4x => scale2x(&scale2x(tile.data(), size), 2u32*size),
+            Self::XBR => xbr2x(tile.data(), size, palette),
+        };
+
+        Tile::new(&data, self.factor()*size)
+            .with_origin(tile.origin().map(|origin| origin*self.factor() as i32))
+    }
+
+    /// Scale all the tiles of a tileset.
+    pub fn apply_tileset(
+        &self,
+        tileset: &Tileset,
+        palette: &Palette,
+    ) -> Result<Tileset> {
+        let mut scaled = Tileset::new(tileset.get_id(), self.factor()*tileset.tile_size());
+
+        for tile in tileset.tile_iter() {
+            scaled.add(self.apply(tile, palette))?;
+        }
+        Ok(scaled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::prelude::Color;
+
+    const STAIRCASE_SIZE: Size = Size { width: 4, height: 4 };
+    const STAIRCASE: [u8; 16] = [
+        1, 0, 0, 0,
+        1, 1, 0, 0,
+        0, 1, 1, 0,
+        0, 0, 1, 1,
+    ];
+
+    fn palette() -> Palette {
+        let mut palette = Palette::new();
+
+        palette.set_transparent_index(None).unwrap();
+        palette.set_color(1, &Color::new(255, 255, 255)).unwrap();
+        palette.set_color(2, &Color::new(200, 40, 40)).unwrap();
+        palette.set_color(3, &Color::new(60, 60, 60)).unwrap();
+        palette
+    }
+
+    // Quarter turn clockwise
+    fn rotate(
+        data: &[u8],
+        size: Size,
+    ) -> (Vec<u8>, Size) {
+        let (width, height) = (size.width as usize, size.height as usize);
+        let rotated = (0..width)
+            .flat_map(|y| (0..height).map(move |x| data[(height - 1 - x)*width + y]))
+            .collect();
+
+        (rotated, Size { width: size.height, height: size.width })
+    }
+
+    #[test]
+    fn scale2x_smooths_the_staircase() {
+        assert_eq!(scale2x(&STAIRCASE, STAIRCASE_SIZE), [
+            1, 1, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 0, 0, 0, 0, 0,
+            1, 1, 1, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 1, 0, 0, 0,
+            0, 1, 1, 1, 1, 0, 0, 0,
+            0, 0, 0, 1, 1, 1, 1, 0,
+            0, 0, 0, 1, 1, 1, 1, 1,
+            0, 0, 0, 0, 1, 1, 1, 1,
+        ]);
+    }
+
+    #[test]
+    fn scale3x_smooths_the_staircase() {
+        assert_eq!(scale3x(&STAIRCASE, STAIRCASE_SIZE), [
+            1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0,
+            0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0,
+            0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 0,
+            0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0,
+            0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1,
+            0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1,
+            0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1,
+        ]);
+    }
+
+    #[test]
+    fn xbr_smooths_the_staircase() {
+        assert_eq!(xbr2x(&STAIRCASE, STAIRCASE_SIZE, &palette()), [
+            1, 1, 0, 0, 0, 0, 0, 0,
+            1, 1, 1, 0, 0, 0, 0, 0,
+            1, 1, 1, 0, 0, 0, 0, 0,
+            1, 1, 1, 1, 1, 0, 0, 0,
+            0, 1, 1, 1, 1, 0, 0, 0,
+            0, 0, 0, 1, 1, 1, 1, 0,
+            0, 0, 0, 1, 1, 1, 1, 1,
+            0, 0, 0, 0, 1, 1, 1, 1,
+        ]);
+    }
+
+    // The four corners of xBR use the same kernel rotated, so rotating the
+    // input rotates the output
+    #[test]
+    fn xbr_corners_are_rotations_of_each_other() {
+        let size = Size { width: 5, height: 4 };
+        let data = [
+            0, 1, 1, 0, 3,
+            1, 2, 2, 1, 0,
+            0, 2, 3, 3, 0,
+            3, 0, 1, 0, 2,
+        ];
+        let palette = palette();
+        let (rotated, rotated_size) = rotate(&data, size);
+
+        let scaled = xbr2x(&data, size, &palette);
+        let (expected, _) = rotate(&scaled, 2u32*size);
+
+        assert_eq!(xbr2x(&rotated, rotated_size, &palette), expected);
+
+        let (expected, _) = rotate(&scale2x(&data, size), 2u32*size);
+        assert_eq!(scale2x(&rotated, rotated_size), expected);
+
+        let (expected, _) = rotate(&scale3x(&data, size), 3u32*size);
+        assert_eq!(scale3x(&rotated, rotated_size), expected);
+    }
+}
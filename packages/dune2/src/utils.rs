@@ -1,3 +1,9 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, SeqAccess, Visitor};
+
 use crate::prelude::{
     Point,
     Size,
@@ -17,3 +23,90 @@ pub fn point_to_index(
     }
     Some(((p.y as u32)*size.width + (p.x as u32)) as usize)
 }
+
+/// Hexadecimal representation of a buffer, two lowercase digits per byte.
+pub fn to_hex(
+    data: &[u8],
+) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parse the hexadecimal representation of a buffer, `None` if the string
+/// is not made of pairs of hexadecimal digits.
+pub fn from_hex(
+    hex: &str,
+) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// Serialize a buffer as a byte string rather than a sequence of integers,
+/// human readable formats get an hexadecimal string.
+pub fn serialize_bytes<S>(
+    data: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error> where S: Serializer {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&to_hex(data))
+    } else {
+        serializer.serialize_bytes(data)
+    }
+}
+
+/// Deserialize a buffer written by `serialize_bytes` or as a sequence of
+/// integers.
+pub fn deserialize_bytes<'de, D>(
+    deserializer: D,
+) -> Result<Vec<u8>, D::Error> where D: Deserializer<'de> {
+    if deserializer.is_human_readable() {
+        let hex = String::deserialize(deserializer)?;
+
+        return from_hex(&hex).ok_or_else(|| {
+            de::Error::invalid_value(de::Unexpected::Str(&hex), &"an hexadecimal string")
+        });
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a byte buffer")
+        }
+
+        fn visit_bytes<E>(self, data: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(data.to_vec())
+        }
+
+        fn visit_byte_buf<E>(self, data: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(data)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Vec<u8>, A::Error> where A: SeqAccess<'de> {
+            let mut data = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+
+            while let Some(byte) = seq.next_element()? {
+                data.push(byte);
+            }
+            Ok(data)
+        }
+    }
+
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+/// Serialize a map with its keys sorted, so that the output of human
+/// readable formats does not change from one run to another.
+pub fn serialize_sorted_map<K, V, S>(
+    map: &HashMap<K, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> where K: Ord + Serialize, V: Serialize, S: Serializer {
+    map.iter().collect::<BTreeMap<&K, &V>>().serialize(serializer)
+}
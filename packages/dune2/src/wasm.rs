@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use js_sys::JsString;
 use wasm_bindgen::prelude::*;
 
@@ -5,12 +7,11 @@ extern crate js_sys;
 extern crate web_sys;
 
 use crate::prelude::*;
-use crate::utils::point_to_index;
 
 
 #[wasm_bindgen]
 pub struct Dune2AssetsData {
-    assets: Assets,
+    assets: LazyAssets,
 }
 
 #[wasm_bindgen]
@@ -19,8 +20,7 @@ impl Dune2AssetsData {
     pub fn load(
         data: &[u8],
     ) -> core::result::Result<Dune2AssetsData, JsError> {
-        let mut reader = std::io::Cursor::new(data);
-        let assets = Assets::read_from(&mut reader)?;
+        let assets = LazyAssets::from_bytes(data.to_vec())?;
 
         Ok(Self { assets })
     }
@@ -29,7 +29,7 @@ impl Dune2AssetsData {
     pub fn get_color_count(
         &self,
     ) -> usize {
-        self.assets.palette.len()
+        self.assets.assets().palette.len()
     }
 
     #[wasm_bindgen(js_name = getColor)]
@@ -37,7 +37,7 @@ impl Dune2AssetsData {
         &self,
         color_index: usize,
     ) -> wasm_bindgen::Clamped<Vec<u8>> {
-        self.assets.palette
+        self.assets.assets().palette
             .color_at(color_index)
             .map(|color| wasm_bindgen::Clamped(vec![
                 color.red,
@@ -53,15 +53,27 @@ impl Dune2AssetsData {
         &self,
     ) -> Vec<JsString> {
         self.assets
-            .tilesets
-            .keys()
-            .map(|tileset| JsString::from(tileset.as_str()))
+            .tileset_ids()
+            .map(JsString::from)
             .collect()
     }
 
+    /// Decode the given tilesets, the other tilesets are decoded on first
+    /// access.
+    #[wasm_bindgen(js_name = loadTilesets)]
+    pub fn load_tilesets(
+        &mut self,
+        tileset_ids: Vec<String>,
+    ) -> core::result::Result<(), JsValue> {
+        for tileset_id in tileset_ids.iter() {
+            self.assets.get_tileset(tileset_id)?;
+        }
+        Ok(())
+    }
+
     #[wasm_bindgen(js_name = getTilesetTileSize)]
     pub fn get_tileset_tile_size(
-        &self,
+        &mut self,
         tileset_id: &str,
     ) -> core::result::Result<Size, JsValue> {
         let tile_size = self.assets
@@ -73,7 +85,7 @@ impl Dune2AssetsData {
 
     #[wasm_bindgen(js_name = getTilesetTileCount)]
     pub fn get_tileset_tile_count(
-        &self,
+        &mut self,
         tileset_id: &str,
     ) -> core::result::Result<usize, JsValue> {
         let tile_count = self.assets
@@ -85,70 +97,83 @@ impl Dune2AssetsData {
 
     #[wasm_bindgen(js_name = getTilesetImageData)]
     pub fn get_tileset_image_data(
-        &self,
+        &mut self,
         tileset_id: &str,
         columns: u32,
         faction: Option<Dune2Faction>,
     ) -> core::result::Result<web_sys::ImageData, JsValue> {
-        let palette = &self.assets.palette;
-        let tileset = self.assets.get_tileset(tileset_id)?;
+        let faction_id = faction.map(|faction| faction.id());
+        let palette = self.assets.faction_palette(faction_id.as_deref());
 
-        let tile_count = tileset.tile_count() as u32;
-        let tile_size = tileset.tile_size();
-        let rows = if tile_count%columns == 0 {
-            tile_count/columns
-        } else {
-            tile_count/columns + 1
-        };
+        self.tileset_image_data(tileset_id, columns, palette)
+    }
 
-        let mut dst = RGBABitmap::new(Size {
-            width: columns*tile_size.width,
-            height: rows*tile_size.height,
-        });
+    /// Same as `getTilesetImageData` with the colors remapped by the remap
+    /// table of the given id.
+    #[wasm_bindgen(js_name = getTilesetRemappedImageData)]
+    pub fn get_tileset_remapped_image_data(
+        &mut self,
+        tileset_id: &str,
+        columns: u32,
+        remap_table_id: &str,
+    ) -> core::result::Result<web_sys::ImageData, JsValue> {
+        self.assets.assets().get_remap_table(remap_table_id)?;
 
-        for (tile_index, tile) in tileset.tile_iter().enumerate() {
-            let col = (tile_index as u32)%columns;
-            let row = (tile_index as u32)/columns;
+        let palette = self.assets.faction_palette(Some(remap_table_id));
 
-            let src = TileBitmap::with_palette(tile, faction, palette);
-            let src_rect = src.rect();
+        self.tileset_image_data(tileset_id, columns, palette)
+    }
 
-            let dst_rect = Rect::from_point_and_size(
-                Point {
-                    x: (col*tile_size.width) as i32,
-                    y: (row*tile_size.height) as i32,
-                },
-                tile_size,
-            );
+    #[wasm_bindgen(js_name = getTilesetTeamColorImageData)]
+    pub fn get_tileset_team_color_image_data(
+        &mut self,
+        tileset_id: &str,
+        columns: u32,
+        team_color: &str,
+    ) -> core::result::Result<web_sys::ImageData, JsValue> {
+        let team_color = team_color
+            .parse::<Color>()
+            .map_err(|err| JsValue::from(format!("{err}")))?;
+        let palette = self.assets.assets().team_color_palette(&team_color);
 
-            bitmap_blit(&src, &src_rect, &mut dst, &dst_rect);
-        }
+        self.tileset_image_data(tileset_id, columns, Rc::new(palette))
+    }
 
-        web_sys::ImageData::new_with_u8_clamped_array_and_sh(
-            wasm_bindgen::Clamped(dst.data.as_slice()),
-            dst.width(),
-            dst.height(),
-        )
+    /// Point of the tile to draw at the sprite position, in unscaled tile
+    /// pixels.
+    #[wasm_bindgen(js_name = getTilesetTileOrigin)]
+    pub fn get_tileset_tile_origin(
+        &mut self,
+        tileset: &str,
+        tile: usize,
+    ) -> core::result::Result<Point, JsValue> {
+        let tile = self.assets.get_tileset(tileset)?.tile_at(tile)?;
+
+        Ok(tile.origin_or_center())
     }
 
     #[wasm_bindgen(js_name = getTilesetTileImageData)]
     pub fn get_tileset_tile_image_data(
-        &self,
+        &mut self,
         tileset: &str,
         tile: usize,
         faction: Option<Dune2Faction>,
         scale: Option<u32>,
+        filter: Option<ScaleFilter>,
     ) -> core::result::Result<web_sys::ImageData, JsValue> {
         let scale = u32::max(1, scale.unwrap_or(1));
 
-        let src_bitmap = self.assets.get_tile_bitmap(
-            tileset,
-            tile,
-            faction
-        )?;
+        self.assets.get_tileset(tileset)?;
+
+        let assets = self.assets.assets();
+        let tile = filter.unwrap_or_default().apply(
+            assets.get_tileset(tileset)?.tile_at(tile)?,
+            &assets.palette,
+        );
+        let src_bitmap = TileBitmap::with_assets(tile.view(), faction, assets);
         let src_rect = src_bitmap.rect();
 
-        let mut dst_bitmap = RGBABitmap::new(
+        let mut dst_bitmap = RgbaBitmap::new(
             src_bitmap.size()*scale,
         );
         let dst_rect = dst_bitmap.rect();
@@ -157,11 +182,12 @@ impl Dune2AssetsData {
             &src_bitmap,
             &src_rect,
             &mut dst_bitmap,
-            &dst_rect
+            &dst_rect,
+            BlitSizePolicy::Stretch,
         );
 
         web_sys::ImageData::new_with_u8_clamped_array_and_sh(
-            wasm_bindgen::Clamped(dst_bitmap.data.as_slice()),
+            wasm_bindgen::Clamped(dst_bitmap.data()),
             dst_bitmap.width(),
             dst_bitmap.height(),
         )
@@ -171,7 +197,7 @@ impl Dune2AssetsData {
     pub fn get_tilemap_count(
         &self,
     ) -> usize {
-        self.assets.tilemaps.len()
+        self.assets.assets().tilemaps.len()
     }
 
     #[wasm_bindgen(js_name = getTilemap)]
@@ -179,59 +205,155 @@ impl Dune2AssetsData {
         &self,
         tilemap_index: usize,
     ) -> Option<Tilemap> {
-        self.assets.tilemaps
+        self.assets.assets().tilemaps
             .get(tilemap_index)
             .and_then(|tilemap| Some(tilemap.clone()))
     }
-}
 
-struct RGBABitmap {
-    data: Vec<u8>,
-    size: Size,
-}
+    #[wasm_bindgen(js_name = getMusics)]
+    pub fn get_musics(
+        &self,
+    ) -> Vec<JsString> {
+        self.assets
+            .assets()
+            .musics
+            .keys()
+            .map(|music| JsString::from(music.as_str()))
+            .collect()
+    }
 
-impl RGBABitmap {
-    fn new(size: Size) -> Self {
-        Self {
-            data: vec![0; 4*(size.width*size.height) as usize],
-            size,
-        }
+    #[wasm_bindgen(js_name = getMusicFormat)]
+    pub fn get_music_format(
+        &self,
+        music_id: &str,
+    ) -> core::result::Result<MusicFormat, JsValue> {
+        let format = self.assets
+            .assets()
+            .get_music(music_id)
+            .map(|music| music.format())?;
+
+        Ok(format)
     }
-}
 
-impl Bitmap for RGBABitmap {
-    fn width(&self) -> u32 {
-        self.size.width
+    #[wasm_bindgen(js_name = getMusicSequenceCount)]
+    pub fn get_music_sequence_count(
+        &self,
+        music_id: &str,
+    ) -> core::result::Result<usize, JsValue> {
+        let sequence_count = self.assets
+            .assets()
+            .get_music(music_id)
+            .map(|music| music.sequence_count())?;
+
+        Ok(sequence_count)
     }
 
-    fn height(&self) -> u32 {
-        self.size.height
+    #[wasm_bindgen(js_name = getMusicSequence)]
+    pub fn get_music_sequence(
+        &self,
+        music_id: &str,
+        sequence_index: usize,
+    ) -> core::result::Result<Vec<u8>, JsValue> {
+        let sequence = self.assets
+            .assets()
+            .get_music(music_id)?
+            .sequence_at(sequence_index)?;
+
+        Ok(Vec::from(sequence))
     }
 }
 
-impl BitmapPutPixel for RGBABitmap {
-    fn put_pixel(
+impl Dune2AssetsData {
+    fn tileset_image_data(
         &mut self,
-        p: Point,
-        color: Option<Color>,
-    ) -> &mut Self {
-        let size = self.size();
-        if let Some(offset) = point_to_index(p, size).map(|offset| 4*offset) {
-            match color {
-                Some(color) => {
-                    self.data[offset + 0] = color.red;
-                    self.data[offset + 1] = color.green;
-                    self.data[offset + 2] = color.blue;
-                    self.data[offset + 3] = 255;
-                },
-                None => {
-                    self.data[offset + 0] = 0;
-                    self.data[offset + 1] = 0;
-                    self.data[offset + 2] = 0;
-                    self.data[offset + 3] = 0;
+        tileset_id: &str,
+        columns: u32,
+        palette: Rc<FactionPalette>,
+    ) -> core::result::Result<web_sys::ImageData, JsValue> {
+        let tileset = self.assets.get_tileset(tileset_id)?;
+
+        let tile_count = tileset.tile_count() as u32;
+        let tile_size = tileset.tile_size();
+        let rows = if tile_count%columns == 0 {
+            tile_count/columns
+        } else {
+            tile_count/columns + 1
+        };
+
+        let mut dst = RgbaBitmap::new(Size {
+            width: columns*tile_size.width,
+            height: rows*tile_size.height,
+        });
+
+        for (tile_index, tile) in tileset.tile_iter().enumerate() {
+            let col = (tile_index as u32)%columns;
+            let row = (tile_index as u32)/columns;
+
+            let src = TileBitmap::with_faction_palette(tile, palette.clone());
+            let src_rect = src.rect();
+
+            let dst_rect = Rect::from_point_and_size(
+                Point {
+                    x: (col*tile_size.width) as i32,
+                    y: (row*tile_size.height) as i32,
                 },
-            };
+                tile_size,
+            );
+
+            bitmap_blit(&src, &src_rect, &mut dst, &dst_rect, BlitSizePolicy::Stretch);
+        }
+
+        web_sys::ImageData::new_with_u8_clamped_array_and_sh(
+            wasm_bindgen::Clamped(dst.data()),
+            dst.width(),
+            dst.height(),
+        )
+    }
+}
+
+/// Decoding times of an assets file in milliseconds, averaged over the
+/// iterations of `benchDecode`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeTimings {
+    /// Size of the file in bytes
+    pub size: usize,
+    /// Time to decode the whole file
+    pub decode: f64,
+    /// Time to open the file and decode its first tileset only
+    #[wasm_bindgen(js_name = decodeOneTileset)]
+    pub decode_one_tileset: f64,
+}
+
+/// Measure the decoding of an assets file in the browser, the counterpart of
+/// the native `compression` bench for the target the assets are used on.
+#[wasm_bindgen(js_name = benchDecode)]
+pub fn bench_decode(
+    data: &[u8],
+    iterations: u32,
+) -> core::result::Result<DecodeTimings, JsValue> {
+    let iterations = iterations.max(1);
+
+    let start = js_sys::Date::now();
+    for _ in 0..iterations {
+        Assets::read_from(&mut &data[..])?;
+    }
+    let decode = (js_sys::Date::now() - start)/iterations as f64;
+
+    let start = js_sys::Date::now();
+    for _ in 0..iterations {
+        let mut assets = LazyAssets::from_bytes(data.to_vec())?;
+        let tileset_id = assets.tileset_ids().next().map(String::from);
+
+        if let Some(tileset_id) = tileset_id {
+            assets.get_tileset(&tileset_id)?;
         }
-        self
     }
+    let decode_one_tileset = (js_sys::Date::now() - start)/iterations as f64;
+
+    Ok(DecodeTimings {
+        size: data.len(),
+        decode,
+        decode_one_tileset,
+    })
 }
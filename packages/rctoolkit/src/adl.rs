@@ -0,0 +1,1377 @@
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Result};
+
+use crate::opl::OPL2;
+
+
+// The driver callback is called 72 times per second
+const CALLBACKS_PER_SECOND: f64 = 72.;
+
+// Dune II uses the first revision of the Westwood AdLib driver. Its files
+// start with a 120 entries subsong table, followed by 150 program offsets
+// and 150 instrument offsets.
+const TRACK_ENTRY_COUNT: usize = 120;
+const PROGRAM_COUNT: usize = 150;
+
+// In the first revision of the driver, jump targets are absolute addresses
+// in the driver memory where the sound data was loaded at this offset.
+const SOUND_DATA_ADDRESS: isize = 191;
+
+// Maximum count of opcodes a channel can execute in a single callback. It
+// keeps malformed programs which loop without waiting from hanging.
+const MAX_OPCODES_PER_TICK: usize = 1024;
+
+const CONTROL_CHANNEL: usize = 9;
+
+const REGISTER_OFFSETS: [u8; 9] = [
+    0x00, 0x01, 0x02, 0x08, 0x09, 0x0a, 0x10, 0x11, 0x12,
+];
+
+const FREQUENCIES: [u16; 13] = [
+    0x0134, 0x0147, 0x015a, 0x016f, 0x0184, 0x019c,
+    0x01b4, 0x01ce, 0x01e9, 0x0207, 0x0225, 0x0246,
+    0x0268,
+];
+
+// Rhythm instruments registers, in the order of the rhythm opcodes bits:
+// hi-hat, cymbal, tom-tom, snare drum and bass drum.
+const RHYTHM_REGISTERS: [u8; 5] = [0x51, 0x55, 0x52, 0x54, 0x53];
+
+// Opcode parameter counts, indexed by opcode & 0x7f
+const OPCODE_PARAMS: [usize; 75] = [
+    1, 2, 1, 1,  2, 2, 0, 1,  0, 1, 2, 2,  1, 5, 1, 1,
+    1, 3, 0, 1,  0, 4, 0, 0,  0, 0, 1, 0,  1, 1, 1, 0,
+    1, 1, 0, 0,  1, 0, 1, 0,  0, 1, 0, 1,  2, 2, 1, 1,
+    1, 0, 0, 1,  0, 2, 0, 0,  0, 1, 0, 0,  1, 1, 0, 2,
+    0, 9, 1, 0,  2, 2, 2, 1,  1, 2, 0,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum PrimaryEffect {
+    #[default]
+    None,
+    Slide,
+    Vibrato,
+}
+
+// Result of an opcode execution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Flow {
+    // Go on with the next opcode
+    Continue,
+    // Stop parsing this tick and run the channel effects
+    Yield,
+    // Stop parsing this tick and skip the channel effects
+    Stop,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Channel {
+    dataptr: Option<usize>,
+    dataptr_stack: [usize; 4],
+    dataptr_stack_pos: usize,
+    priority: u8,
+    repeat_counter: u8,
+    duration: u8,
+    position: u8,
+    tempo: u8,
+    tempo_reset: bool,
+    spacing1: u8,
+    spacing2: u8,
+    fractional_spacing: u8,
+    duration_randomness: u8,
+
+    base_octave: i8,
+    base_note: i8,
+    base_freq: u8,
+    raw_note: u8,
+    pitch_bend: i8,
+    reg_ax: u8,
+    reg_bx: u8,
+
+    two_chan: bool,
+    op_level1: u8,
+    op_level2: u8,
+    op_extra_level1: u8,
+    op_extra_level2: u8,
+    op_extra_level3: u8,
+    volume_modifier: u8,
+
+    primary_effect: PrimaryEffect,
+    slide_tempo: u8,
+    slide_timer: u8,
+    slide_step: i16,
+    vibrato_tempo: u8,
+    vibrato_timer: u8,
+    vibrato_step_range: u8,
+    vibrato_steps_countdown: u8,
+    vibrato_num_steps: u8,
+    vibrato_delay: u8,
+    vibrato_delay_countdown: u8,
+    vibrato_step: i16,
+
+    secondary_effect: bool,
+    secondary_effect_tempo: u8,
+    secondary_effect_timer: u8,
+    secondary_effect_size: i8,
+    secondary_effect_pos: i8,
+    secondary_effect_regbase: u8,
+    secondary_effect_data: usize,
+}
+
+impl Channel {
+    fn init(&mut self) {
+        let op_extra_level2 = self.op_extra_level2;
+
+        *self = Channel {
+            tempo: 0xff,
+            spacing1: 1,
+            op_extra_level2,
+            ..Default::default()
+        };
+    }
+}
+
+fn wrapping_overflow(
+    value: &mut u8,
+    increment: u8,
+) -> bool {
+    let (result, overflow) = value.overflowing_add(increment);
+    *value = result;
+    overflow
+}
+
+/// Interpreter of the Westwood AdLib driver programs, driving an emulated
+/// OPL2 chip.
+struct Driver<'a> {
+    opl: OPL2,
+    sound_data: &'a [u8],
+    channels: [Channel; 10],
+    cur_channel: usize,
+    cur_reg_offset: u8,
+    tempo: u8,
+    callback_timer: u8,
+    beat_divider: u8,
+    beat_div_count: u8,
+    beat_counter: u8,
+    beat_waiting: u8,
+    program_start_timeout: u8,
+    program_queue: VecDeque<usize>,
+    vibrato_am_bits: u8,
+    rhythm_section_bits: u8,
+    rhythm_base_levels: [u8; 5],
+    rhythm_levels1: [u8; 5],
+    rhythm_levels2: [u8; 5],
+    rnd: u16,
+}
+
+impl<'a> Driver<'a> {
+    fn new(
+        sound_data: &'a [u8],
+        sample_rate: u32,
+    ) -> Self {
+        let mut driver = Self {
+            opl: OPL2::new(sample_rate),
+            sound_data,
+            channels: [Channel::default(); 10],
+            cur_channel: 0,
+            cur_reg_offset: 0,
+            tempo: 0,
+            callback_timer: 0xff,
+            beat_divider: 0,
+            beat_div_count: 0,
+            beat_counter: 0,
+            beat_waiting: 0,
+            program_start_timeout: 0,
+            program_queue: VecDeque::new(),
+            vibrato_am_bits: 0,
+            rhythm_section_bits: 0,
+            rhythm_base_levels: [0; 5],
+            rhythm_levels1: [0; 5],
+            rhythm_levels2: [0; 5],
+            rnd: 0x1234,
+        };
+
+        // Enable waveform selection
+        driver.opl.write(0x01, 0x20);
+        // Select FM music mode
+        driver.opl.write(0x08, 0x00);
+        // Disable the rhythm section
+        driver.opl.write(0xbd, 0x00);
+
+        for (index, channel) in driver.channels.iter_mut().enumerate() {
+            if index != CONTROL_CHANNEL {
+                driver.opl.write(0x40 + REGISTER_OFFSETS[index], 0x3f);
+                driver.opl.write(0x43 + REGISTER_OFFSETS[index], 0x3f);
+            }
+            channel.init();
+        }
+
+        driver
+    }
+
+    /**************************************************************************
+     * Sound data access
+     *************************************************************************/
+
+    fn byte_at(&self, offset: usize) -> Option<u8> {
+        self.sound_data.get(offset).copied()
+    }
+
+    fn program(&self, program_id: usize) -> Option<usize> {
+        let offset = 2*program_id;
+        let bytes = self.sound_data.get(offset..offset + 2)?;
+        let program = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+
+        if program == 0 || program >= self.sound_data.len() {
+            None
+        } else {
+            Some(program)
+        }
+    }
+
+    fn instrument(&self, instrument_id: usize) -> Option<usize> {
+        self.program(PROGRAM_COUNT + instrument_id)
+            .filter(|offset| offset + 11 <= self.sound_data.len())
+    }
+
+    fn absolute_offset(&self, address: i16) -> Option<usize> {
+        let offset = address as isize - SOUND_DATA_ADDRESS;
+
+        if offset >= 0 && (offset as usize) < self.sound_data.len() {
+            Some(offset as usize)
+        } else {
+            None
+        }
+    }
+
+    fn random(&mut self) -> u16 {
+        self.rnd = self.rnd.wrapping_add(0x9248);
+        let low_bits = self.rnd & 7;
+        self.rnd >>= 3;
+        self.rnd |= low_bits << 13;
+        self.rnd
+    }
+
+    /**************************************************************************
+     * Programs scheduling
+     *************************************************************************/
+
+    fn start_sound(
+        &mut self,
+        program_id: usize,
+    ) -> Result<()> {
+        let program = self.program(program_id)
+            .ok_or(anyhow!("ADL: invalid program #{program_id}"))?;
+
+        self.program_queue.push_back(program);
+        Ok(())
+    }
+
+    fn start_program(
+        &mut self,
+        program: usize,
+    ) {
+        let (Some(chan), Some(priority)) = (
+            self.byte_at(program),
+            self.byte_at(program + 1),
+        ) else { return };
+
+        let chan = chan as usize;
+
+        if chan > CONTROL_CHANNEL {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        if priority >= channel.priority {
+            channel.init();
+            channel.priority = priority;
+            channel.dataptr = Some(program + 2);
+            channel.tempo = 0xff;
+            channel.position = 0xff;
+            channel.duration = 1;
+            channel.volume_modifier = 0xff;
+
+            self.init_adlib_channel(chan);
+        }
+    }
+
+    fn init_adlib_channel(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL {
+            return;
+        }
+
+        // When the rhythm section is enabled, channels 6, 7 and 8 are special
+        if self.rhythm_section_bits != 0 && chan >= 6 {
+            return;
+        }
+
+        let offset = REGISTER_OFFSETS[chan];
+
+        // Attack / Decay rates
+        self.opl.write(0x60 + offset, 0xff);
+        self.opl.write(0x63 + offset, 0xff);
+        // Sustain level / Release rate
+        self.opl.write(0x80 + offset, 0xff);
+        self.opl.write(0x83 + offset, 0xff);
+        // Key-On / Octave / Frequency
+        self.opl.write(0xb0 + chan as u8, 0x00);
+        self.opl.write(0xb0 + chan as u8, 0x20);
+    }
+
+    fn is_idle(&self) -> bool {
+        self.program_queue.is_empty()
+            && self.channels.iter().all(|channel| channel.dataptr.is_none())
+    }
+
+    fn callback(&mut self) {
+        if self.program_start_timeout > 0 {
+            self.program_start_timeout -= 1;
+        } else if let Some(program) = self.program_queue.pop_front() {
+            self.start_program(program);
+        }
+
+        self.execute_programs();
+
+        if wrapping_overflow(&mut self.callback_timer, self.tempo) {
+            self.beat_div_count = self.beat_div_count.wrapping_sub(1);
+            if self.beat_div_count == 0 {
+                self.beat_div_count = self.beat_divider;
+                self.beat_counter = self.beat_counter.wrapping_add(1);
+            }
+        }
+    }
+
+    fn execute_programs(&mut self) {
+        for chan in (0..=CONTROL_CHANNEL).rev() {
+            if self.channels[chan].dataptr.is_none() {
+                continue;
+            }
+
+            self.cur_channel = chan;
+            self.cur_reg_offset = if chan == CONTROL_CHANNEL {
+                0
+            } else {
+                REGISTER_OFFSETS[chan]
+            };
+
+            if self.channels[chan].tempo_reset {
+                self.channels[chan].tempo = self.tempo;
+            }
+
+            let mut flow = Flow::Yield;
+            let tempo = self.channels[chan].tempo;
+
+            if wrapping_overflow(&mut self.channels[chan].position, tempo) {
+                let channel = &mut self.channels[chan];
+
+                channel.duration = channel.duration.wrapping_sub(1);
+
+                let Channel { duration, spacing1, spacing2, .. } = *channel;
+
+                if duration != 0 {
+                    if duration == spacing2 {
+                        self.note_off(chan);
+                    }
+                    if duration == spacing1 && chan != CONTROL_CHANNEL {
+                        self.note_off(chan);
+                    }
+                } else {
+                    flow = self.parse_program(chan);
+                }
+            }
+
+            if flow == Flow::Yield {
+                match self.channels[chan].primary_effect {
+                    PrimaryEffect::Slide => self.primary_effect_slide(chan),
+                    PrimaryEffect::Vibrato => self.primary_effect_vibrato(chan),
+                    PrimaryEffect::None => {},
+                }
+                if self.channels[chan].secondary_effect {
+                    self.secondary_effect(chan);
+                }
+            }
+        }
+    }
+
+    fn parse_program(
+        &mut self,
+        chan: usize,
+    ) -> Flow {
+        let mut flow = Flow::Yield;
+
+        for _ in 0..MAX_OPCODES_PER_TICK {
+            let Some(dataptr) = self.channels[chan].dataptr else {
+                return flow;
+            };
+
+            let (Some(opcode), Some(param)) = (
+                self.byte_at(dataptr),
+                self.byte_at(dataptr + 1),
+            ) else {
+                return self.stop_channel(chan);
+            };
+
+            if opcode & 0x80 != 0 {
+                let opcode = usize::min((opcode & 0x7f) as usize, OPCODE_PARAMS.len() - 1);
+                let param_count = OPCODE_PARAMS[opcode];
+                let params_end = dataptr + 1 + param_count;
+
+                let Some(params) = self.sound_data.get(dataptr + 1..params_end) else {
+                    return self.stop_channel(chan);
+                };
+
+                self.channels[chan].dataptr = Some(params_end);
+                flow = self.execute_opcode(chan, opcode, params);
+
+                if flow != Flow::Continue {
+                    return flow;
+                }
+            } else {
+                self.channels[chan].dataptr = Some(dataptr + 2);
+
+                self.setup_note(chan, opcode, false);
+                self.note_on(chan);
+                self.setup_duration(chan, param);
+
+                if param != 0 {
+                    return Flow::Yield;
+                }
+            }
+        }
+
+        self.stop_channel(chan)
+    }
+
+    /**************************************************************************
+     * Notes
+     *************************************************************************/
+
+    fn setup_duration(
+        &mut self,
+        chan: usize,
+        duration: u8,
+    ) {
+        if self.channels[chan].duration_randomness != 0 {
+            let randomness = self.random() as u8 & self.channels[chan].duration_randomness;
+            self.channels[chan].duration = duration.wrapping_add(randomness);
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        if channel.fractional_spacing != 0 {
+            channel.spacing2 = (duration >> 3).wrapping_mul(channel.fractional_spacing);
+        }
+        channel.duration = duration;
+    }
+
+    fn setup_note(
+        &mut self,
+        chan: usize,
+        raw_note: u8,
+        force_pitch_bend: bool,
+    ) {
+        if chan >= CONTROL_CHANNEL {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        channel.raw_note = raw_note;
+
+        let mut note = (raw_note & 0x0f) as i32 + channel.base_note as i32;
+        let mut octave = (((raw_note as i32) + channel.base_octave as i32) >> 4) & 0x0f;
+
+        if note >= 12 {
+            note -= 12;
+            octave += 1;
+        } else if note < 0 {
+            note += 12;
+            octave -= 1;
+        }
+
+        let note = note.clamp(0, 11) as usize;
+        let mut freq = FREQUENCIES[note] as i32 + channel.base_freq as i32;
+
+        // Pitch bends are expressed in 1/32 of the distance to the
+        // neighbouring semitone.
+        if channel.pitch_bend != 0 || force_pitch_bend {
+            let bend = channel.pitch_bend as i32;
+            let step = if bend >= 0 {
+                FREQUENCIES[note + 1] as i32 - FREQUENCIES[note] as i32
+            } else if note > 0 {
+                FREQUENCIES[note] as i32 - FREQUENCIES[note - 1] as i32
+            } else {
+                FREQUENCIES[12] as i32 - FREQUENCIES[11] as i32
+            };
+            freq += step*bend/32;
+        }
+
+        channel.reg_ax = (freq & 0xff) as u8;
+        channel.reg_bx = (channel.reg_bx & 0x20)
+            | (((octave & 0x07) as u8) << 2)
+            | (((freq >> 8) & 0x03) as u8);
+
+        let (reg_ax, reg_bx) = (channel.reg_ax, channel.reg_bx);
+
+        self.opl.write(0xa0 + chan as u8, reg_ax);
+        self.opl.write(0xb0 + chan as u8, reg_bx);
+    }
+
+    fn note_on(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL
+            || (self.rhythm_section_bits != 0 && chan >= 6) {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        channel.reg_bx |= 0x20;
+
+        let shift = 9 - u8::min(channel.vibrato_step_range, 9);
+        let freq = (((channel.reg_bx as u16) << 8) | channel.reg_ax as u16) & 0x3ff;
+
+        channel.vibrato_step = ((freq >> shift) & 0xff) as i16;
+        channel.vibrato_delay_countdown = channel.vibrato_delay;
+
+        let reg_bx = channel.reg_bx;
+
+        self.opl.write(0xb0 + chan as u8, reg_bx);
+    }
+
+    fn note_off(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL
+            || (self.rhythm_section_bits != 0 && chan >= 6) {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        channel.reg_bx &= 0xdf;
+
+        let reg_bx = channel.reg_bx;
+
+        self.opl.write(0xb0 + chan as u8, reg_bx);
+    }
+
+    /**************************************************************************
+     * Instruments and volume
+     *************************************************************************/
+
+    fn op_level(
+        channel: &Channel,
+        level: u8,
+        extra: bool,
+    ) -> u8 {
+        let mut value = (level & 0x3f) as i32;
+
+        if extra {
+            value += channel.op_extra_level1 as i32;
+            value += channel.op_extra_level2 as i32;
+
+            let mut level3 = ((channel.op_extra_level3 ^ 0x3f) as u32)
+                *channel.volume_modifier as u32;
+            if level3 != 0 {
+                level3 = (level3 + 0x3f) >> 8;
+            }
+            value += (level3 ^ 0x3f) as i32;
+        }
+
+        let value = if channel.volume_modifier == 0 {
+            0x3f
+        } else {
+            value.clamp(0, 0x3f) as u8
+        };
+
+        // Preserve the key scaling level bits
+        value | (level & 0xc0)
+    }
+
+    fn op_level1(channel: &Channel) -> u8 {
+        Driver::op_level(channel, channel.op_level1, channel.two_chan)
+    }
+
+    fn op_level2(channel: &Channel) -> u8 {
+        Driver::op_level(channel, channel.op_level2, true)
+    }
+
+    fn adjust_volume(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL {
+            return;
+        }
+
+        let channel = self.channels[chan];
+        let offset = REGISTER_OFFSETS[chan];
+
+        self.opl.write(0x43 + offset, Driver::op_level2(&channel));
+        if channel.two_chan {
+            self.opl.write(0x40 + offset, Driver::op_level1(&channel));
+        }
+    }
+
+    fn setup_instrument(
+        &mut self,
+        chan: usize,
+        reg_offset: u8,
+        instrument: usize,
+    ) {
+        if self.cur_channel >= CONTROL_CHANNEL {
+            return;
+        }
+
+        let data = &self.sound_data[instrument..instrument + 11];
+        let channel = &mut self.channels[chan];
+
+        channel.two_chan = data[2] & 0x01 != 0;
+        channel.op_level1 = data[5];
+        channel.op_level2 = data[6];
+
+        let channel = self.channels[chan];
+
+        // AM / Vibrato / Envelope type / KSR / Frequency multiple
+        self.opl.write(0x20 + reg_offset, data[0]);
+        self.opl.write(0x23 + reg_offset, data[1]);
+        // Feedback / Algorithm
+        self.opl.write(0xc0 + self.cur_channel as u8, data[2]);
+        // Waveform select
+        self.opl.write(0xe0 + reg_offset, data[3]);
+        self.opl.write(0xe3 + reg_offset, data[4]);
+        // Key scaling level / Total level
+        self.opl.write(0x40 + reg_offset, Driver::op_level1(&channel));
+        self.opl.write(0x43 + reg_offset, Driver::op_level2(&channel));
+        // Attack / Decay rates
+        self.opl.write(0x60 + reg_offset, data[7]);
+        self.opl.write(0x63 + reg_offset, data[8]);
+        // Sustain level / Release rate
+        self.opl.write(0x80 + reg_offset, data[9]);
+        self.opl.write(0x83 + reg_offset, data[10]);
+    }
+
+    /**************************************************************************
+     * Effects
+     *************************************************************************/
+
+    fn primary_effect_slide(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+        let slide_tempo = channel.slide_tempo;
+
+        if !wrapping_overflow(&mut channel.slide_timer, slide_tempo) {
+            return;
+        }
+
+        let mut freq = ((((channel.reg_bx & 0x03) as i32) << 8) | channel.reg_ax as i32)
+            + channel.slide_step as i32;
+        let mut octave = ((channel.reg_bx >> 2) & 0x07) as i32;
+
+        if channel.slide_step >= 0 && freq >= 734 {
+            // Too high, shift the frequency down and go up one octave
+            freq >>= 1;
+            if freq & 0x3ff == 0 {
+                freq += 1;
+            }
+            octave += 1;
+        } else if channel.slide_step < 0 && freq < 388 {
+            // Too low, shift the frequency up and go down one octave
+            freq <<= 1;
+            if freq & 0x3ff == 0 {
+                freq -= 1;
+            }
+            octave -= 1;
+        }
+
+        let freq = freq & 0x3ff;
+
+        channel.reg_ax = (freq & 0xff) as u8;
+        channel.reg_bx = (channel.reg_bx & 0x20)
+            | (((octave & 0x07) as u8) << 2)
+            | ((freq >> 8) as u8);
+
+        let (reg_ax, reg_bx) = (channel.reg_ax, channel.reg_bx);
+
+        self.opl.write(0xa0 + chan as u8, reg_ax);
+        self.opl.write(0xb0 + chan as u8, reg_bx);
+    }
+
+    fn primary_effect_vibrato(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL
+            || (self.rhythm_section_bits != 0 && chan >= 6) {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+
+        // Vibrato starts after a delay
+        if channel.vibrato_delay_countdown > 0 {
+            channel.vibrato_delay_countdown -= 1;
+            return;
+        }
+
+        let vibrato_tempo = channel.vibrato_tempo;
+
+        if !wrapping_overflow(&mut channel.vibrato_timer, vibrato_tempo) {
+            return;
+        }
+
+        channel.vibrato_steps_countdown = channel.vibrato_steps_countdown.wrapping_sub(1);
+        if channel.vibrato_steps_countdown == 0 {
+            channel.vibrato_step = -channel.vibrato_step;
+            channel.vibrato_steps_countdown = channel.vibrato_num_steps;
+        }
+
+        let freq = ((((channel.reg_bx as i32) << 8) | channel.reg_ax as i32) & 0x3ff)
+            + channel.vibrato_step as i32;
+        let freq = freq & 0x3ff;
+
+        channel.reg_ax = (freq & 0xff) as u8;
+        channel.reg_bx = (channel.reg_bx & 0xfc) | ((freq >> 8) as u8);
+
+        let (reg_ax, reg_bx) = (channel.reg_ax, channel.reg_bx);
+
+        self.opl.write(0xa0 + chan as u8, reg_ax);
+        self.opl.write(0xb0 + chan as u8, reg_bx);
+    }
+
+    fn secondary_effect(
+        &mut self,
+        chan: usize,
+    ) {
+        if chan >= CONTROL_CHANNEL {
+            return;
+        }
+
+        let channel = &mut self.channels[chan];
+        let tempo = channel.secondary_effect_tempo;
+
+        if !wrapping_overflow(&mut channel.secondary_effect_timer, tempo) {
+            return;
+        }
+
+        channel.secondary_effect_pos -= 1;
+        if channel.secondary_effect_pos < 0 {
+            channel.secondary_effect_pos = channel.secondary_effect_size;
+        }
+
+        let offset = channel.secondary_effect_data
+            + channel.secondary_effect_pos.max(0) as usize;
+        let register = channel.secondary_effect_regbase
+            .wrapping_add(self.cur_reg_offset);
+
+        if let Some(value) = self.byte_at(offset) {
+            self.opl.write(register, value);
+        }
+    }
+
+    /**************************************************************************
+     * Opcodes
+     *************************************************************************/
+
+    fn stop_channel(
+        &mut self,
+        chan: usize,
+    ) -> Flow {
+        self.channels[chan].priority = 0;
+        if chan != CONTROL_CHANNEL {
+            self.note_off(chan);
+        }
+        self.channels[chan].dataptr = None;
+        Flow::Stop
+    }
+
+    fn set_rhythm_level(
+        &mut self,
+        bits: u8,
+        value: u8,
+        second_level: bool,
+        relative: bool,
+    ) {
+        for (index, register) in RHYTHM_REGISTERS.iter().enumerate() {
+            if bits & (1 << index) == 0 {
+                continue;
+            }
+
+            let level = if second_level {
+                &mut self.rhythm_levels2[index]
+            } else {
+                &mut self.rhythm_levels1[index]
+            };
+
+            *level = if relative { level.wrapping_add(value) } else { value };
+
+            let total = self.rhythm_base_levels[index] as u32
+                + self.rhythm_levels1[index] as u32
+                + self.rhythm_levels2[index] as u32;
+
+            self.opl.write(*register, u32::min(total, 0x3f) as u8);
+        }
+    }
+
+    fn execute_opcode(
+        &mut self,
+        chan: usize,
+        opcode: usize,
+        params: &[u8],
+    ) -> Flow {
+        let dataptr = self.channels[chan].dataptr.unwrap_or(0);
+        let param = params.first().copied().unwrap_or(0);
+        let param_i16 = || i16::from_le_bytes([params[0], params[1]]);
+
+        match opcode {
+            // setRepeat
+            0x00 => {
+                self.channels[chan].repeat_counter = param;
+            },
+            // checkRepeat
+            0x01 => {
+                let channel = &mut self.channels[chan];
+
+                channel.repeat_counter = channel.repeat_counter.wrapping_sub(1);
+                if channel.repeat_counter != 0 {
+                    let target = dataptr as isize + param_i16() as isize;
+                    channel.dataptr = usize::try_from(target).ok();
+                }
+            },
+            // setupProgram
+            0x02 => {
+                if param != 0xff {
+                    if let Some(program) = self.program(param as usize) {
+                        self.program_start_timeout = 2;
+                        self.start_program(program);
+                    }
+                }
+            },
+            // setNoteSpacing
+            0x03 => {
+                self.channels[chan].spacing1 = param;
+            },
+            // jump
+            0x04 => {
+                self.channels[chan].dataptr = self.absolute_offset(param_i16());
+            },
+            // jumpToSubroutine
+            0x05 => {
+                let target = self.absolute_offset(param_i16());
+                let channel = &mut self.channels[chan];
+
+                if channel.dataptr_stack_pos < channel.dataptr_stack.len() {
+                    channel.dataptr_stack[channel.dataptr_stack_pos] = dataptr;
+                    channel.dataptr_stack_pos += 1;
+                    channel.dataptr = target;
+                }
+            },
+            // returnFromSubroutine
+            0x06 => {
+                let channel = &mut self.channels[chan];
+
+                if channel.dataptr_stack_pos > 0 {
+                    channel.dataptr_stack_pos -= 1;
+                    channel.dataptr = Some(channel.dataptr_stack[channel.dataptr_stack_pos]);
+                } else {
+                    return self.stop_channel(chan);
+                }
+            },
+            // setBaseOctave
+            0x07 => {
+                self.channels[chan].base_octave = param as i8;
+            },
+            // playRest
+            0x09 => {
+                self.setup_duration(chan, param);
+                self.note_off(chan);
+                return if param != 0 { Flow::Yield } else { Flow::Continue };
+            },
+            // writeAdLib
+            0x0a => {
+                self.opl.write(params[0], params[1]);
+            },
+            // setupNoteAndDuration
+            0x0b => {
+                self.setup_note(chan, params[0], false);
+                self.setup_duration(chan, params[1]);
+                return if params[1] != 0 { Flow::Yield } else { Flow::Continue };
+            },
+            // setBaseNote
+            0x0c => {
+                self.channels[chan].base_note = param as i8;
+            },
+            // setupSecondaryEffect1
+            0x0d => {
+                let data = self.absolute_offset(i16::from_le_bytes([params[3], params[4]]));
+                let channel = &mut self.channels[chan];
+
+                channel.secondary_effect_timer = params[0];
+                channel.secondary_effect_tempo = params[0];
+                channel.secondary_effect_size = params[1] as i8;
+                channel.secondary_effect_pos = 0;
+                channel.secondary_effect_regbase = params[2];
+                channel.secondary_effect_data = data.unwrap_or(0);
+                channel.secondary_effect = data.is_some();
+            },
+            // stopOtherChannel
+            0x0e => {
+                if let Some(channel) = self.channels.get_mut(param as usize) {
+                    channel.duration = 0;
+                    channel.priority = 0;
+                    channel.dataptr = None;
+                }
+            },
+            // waitForEndOfProgram
+            0x0f => {
+                let program_channel = self.program(param as usize)
+                    .and_then(|program| self.byte_at(program))
+                    .and_then(|chan| self.channels.get(chan as usize));
+
+                if program_channel.is_some_and(|channel| channel.dataptr.is_some()) {
+                    let channel = &mut self.channels[chan];
+
+                    channel.dataptr = Some(dataptr - 2);
+                    channel.duration = 1;
+                    return Flow::Stop;
+                }
+            },
+            // setupInstrument
+            0x10 => {
+                if let Some(instrument) = self.instrument(param as usize) {
+                    self.setup_instrument(chan, self.cur_reg_offset, instrument);
+                }
+            },
+            // setupPrimaryEffectSlide
+            0x11 => {
+                let channel = &mut self.channels[chan];
+
+                channel.slide_tempo = params[0];
+                channel.slide_step = i16::from_be_bytes([params[1], params[2]]);
+                channel.slide_timer = 0xff;
+                channel.primary_effect = PrimaryEffect::Slide;
+            },
+            // removePrimaryEffectSlide
+            0x12 => {
+                let channel = &mut self.channels[chan];
+
+                channel.primary_effect = PrimaryEffect::None;
+                channel.slide_step = 0;
+            },
+            // setBaseFreq
+            0x13 => {
+                self.channels[chan].base_freq = param;
+            },
+            // setupPrimaryEffectVibrato
+            0x15 => {
+                let channel = &mut self.channels[chan];
+
+                channel.vibrato_tempo = params[0];
+                channel.vibrato_step_range = params[1];
+                channel.vibrato_steps_countdown = params[2].wrapping_add(1);
+                channel.vibrato_num_steps = params[2].wrapping_shl(1);
+                channel.vibrato_delay = params[3];
+                channel.primary_effect = PrimaryEffect::Vibrato;
+            },
+            // setPriority
+            0x1a => {
+                self.channels[chan].priority = param;
+            },
+            // setBeat
+            0x1c => {
+                self.beat_divider = param >> 1;
+                self.beat_div_count = param >> 1;
+                self.callback_timer = 0xff;
+                self.beat_counter = 0;
+                self.beat_waiting = 0;
+            },
+            // waitForNextBeat
+            0x1d => {
+                if self.beat_counter & param != 0 && self.beat_waiting != 0 {
+                    self.beat_waiting = 0;
+                } else {
+                    if self.beat_counter & param == 0 {
+                        self.beat_waiting = self.beat_waiting.wrapping_add(1);
+                    }
+
+                    let channel = &mut self.channels[chan];
+
+                    channel.dataptr = Some(dataptr - 2);
+                    channel.duration = 1;
+                    return Flow::Stop;
+                }
+            },
+            // setExtraLevel1
+            0x1e => {
+                self.channels[chan].op_extra_level1 = param;
+                self.adjust_volume(chan);
+            },
+            // setupDuration
+            0x20 => {
+                self.setup_duration(chan, param);
+                return if param != 0 { Flow::Yield } else { Flow::Continue };
+            },
+            // playNote
+            0x21 => {
+                self.setup_duration(chan, param);
+                self.note_on(chan);
+                return if param != 0 { Flow::Yield } else { Flow::Continue };
+            },
+            // setFractionalNoteSpacing
+            0x24 => {
+                self.channels[chan].fractional_spacing = param & 0x07;
+            },
+            // setTempo
+            0x26 => {
+                self.tempo = param;
+            },
+            // removeSecondaryEffect1
+            0x27 => {
+                self.channels[chan].secondary_effect = false;
+            },
+            // setChannelTempo
+            0x29 => {
+                self.channels[chan].tempo = param;
+            },
+            // setExtraLevel3
+            0x2b => {
+                self.channels[chan].op_extra_level3 = param;
+            },
+            // setExtraLevel2
+            0x2c | 0x2d => {
+                let other = params[0] as usize;
+
+                if let Some(channel) = self.channels.get_mut(other) {
+                    channel.op_extra_level2 = if opcode == 0x2c {
+                        params[1]
+                    } else {
+                        channel.op_extra_level2.wrapping_add(params[1])
+                    };
+                    self.adjust_volume(other);
+                }
+            },
+            // setAMDepth
+            0x2e => {
+                if param & 1 != 0 {
+                    self.vibrato_am_bits |= 0x80;
+                } else {
+                    self.vibrato_am_bits &= 0x7f;
+                }
+                self.opl.write(0xbd, self.vibrato_am_bits | self.rhythm_section_bits);
+            },
+            // setVibratoDepth
+            0x2f => {
+                if param & 1 != 0 {
+                    self.vibrato_am_bits |= 0x40;
+                } else {
+                    self.vibrato_am_bits &= 0xbf;
+                }
+                self.opl.write(0xbd, self.vibrato_am_bits | self.rhythm_section_bits);
+            },
+            // changeExtraLevel1
+            0x30 => {
+                let channel = &mut self.channels[chan];
+
+                channel.op_extra_level1 = channel.op_extra_level1.wrapping_add(param);
+                self.adjust_volume(chan);
+            },
+            // clearChannel
+            0x33 => {
+                let other = param as usize;
+
+                if let Some(channel) = self.channels.get_mut(other) {
+                    channel.duration = 0;
+                    channel.priority = 0;
+                    channel.dataptr = None;
+                    channel.op_extra_level2 = 0;
+
+                    if other != CONTROL_CHANNEL {
+                        let offset = REGISTER_OFFSETS[other];
+
+                        self.opl.write(0xc0 + other as u8, 0x00);
+                        self.opl.write(0x43 + offset, 0x3f);
+                        self.opl.write(0x83 + offset, 0xff);
+                        self.opl.write(0xb0 + other as u8, 0x00);
+                    }
+                }
+            },
+            // changeNoteRandomly
+            0x35 => {
+                if chan < CONTROL_CHANNEL {
+                    let mask = u16::from_be_bytes([params[0], params[1]]);
+                    let change = mask & self.random();
+                    let channel = &self.channels[chan];
+                    let freq = ((((channel.reg_bx & 0x1f) as u16) << 8) | channel.reg_ax as u16)
+                        .wrapping_add(change)
+                        | (((channel.reg_bx & 0x20) as u16) << 8);
+
+                    self.opl.write(0xa0 + chan as u8, (freq & 0xff) as u8);
+                    self.opl.write(0xb0 + chan as u8, (freq >> 8) as u8);
+                }
+            },
+            // removePrimaryEffectVibrato
+            0x36 => {
+                self.channels[chan].primary_effect = PrimaryEffect::None;
+            },
+            // pitchBend
+            0x39 => {
+                self.channels[chan].pitch_bend = param as i8;
+                let raw_note = self.channels[chan].raw_note;
+                self.setup_note(chan, raw_note, true);
+            },
+            // resetToGlobalTempo
+            0x3a => {
+                self.channels[chan].tempo = self.tempo;
+            },
+            // setDurationRandomness
+            0x3c => {
+                self.channels[chan].duration_randomness = param;
+            },
+            // changeChannelTempo
+            0x3d => {
+                let channel = &mut self.channels[chan];
+                let tempo = channel.tempo as i32 + (param as i8) as i32;
+
+                channel.tempo = tempo.clamp(1, 255) as u8;
+            },
+            // setupRhythmSection
+            0x41 => {
+                let cur_channel = self.cur_channel;
+                let instruments = [params[0], params[1], params[2]];
+
+                for (index, instrument) in instruments.iter().enumerate() {
+                    let rhythm_channel = 6 + index;
+
+                    self.cur_channel = rhythm_channel;
+                    if let Some(instrument) = self.instrument(*instrument as usize) {
+                        self.setup_instrument(chan, REGISTER_OFFSETS[rhythm_channel], instrument);
+                    }
+
+                    let channel = &self.channels[chan];
+
+                    match index {
+                        0 => {
+                            self.rhythm_base_levels[4] = channel.op_level2;
+                        },
+                        1 => {
+                            self.rhythm_base_levels[0] = channel.op_level1;
+                            self.rhythm_base_levels[3] = channel.op_level2;
+                        },
+                        _ => {
+                            self.rhythm_base_levels[2] = channel.op_level1;
+                            self.rhythm_base_levels[1] = channel.op_level2;
+                        },
+                    }
+                }
+                self.cur_channel = cur_channel;
+
+                for (index, rhythm_channel) in (6..9).enumerate() {
+                    let reg_bx = params[3 + 2*index] & 0x2f;
+
+                    self.channels[rhythm_channel].reg_bx = reg_bx;
+                    self.opl.write(0xb0 + rhythm_channel as u8, reg_bx);
+                    self.opl.write(0xa0 + rhythm_channel as u8, params[4 + 2*index]);
+                }
+
+                self.rhythm_section_bits = 0x20;
+            },
+            // playRhythmSection
+            0x42 => {
+                // Instruments about to be played are keyed off first so their
+                // envelope restarts.
+                self.opl.write(0xbd, (self.rhythm_section_bits & !(param & 0x1f)) | 0x20);
+                self.rhythm_section_bits |= param;
+                self.opl.write(0xbd, self.vibrato_am_bits | 0x20 | self.rhythm_section_bits);
+            },
+            // removeRhythmSection
+            0x43 => {
+                self.rhythm_section_bits = 0;
+                self.opl.write(0xbd, self.vibrato_am_bits);
+            },
+            // setRhythmLevel2
+            0x44 => {
+                self.set_rhythm_level(params[0], params[1], true, false);
+            },
+            // changeRhythmLevel1
+            0x45 => {
+                self.set_rhythm_level(params[0], params[1], false, true);
+            },
+            // setRhythmLevel1
+            0x46 => {
+                self.set_rhythm_level(params[0], params[1], false, false);
+            },
+            // setTempoReset
+            0x48 => {
+                self.channels[chan].tempo_reset = param != 0;
+            },
+            // nop, setSoundTrigger and unused callbacks
+            0x3b | 0x3f | 0x40 | 0x47 | 0x49 => {},
+            // stopChannel
+            _ => {
+                return self.stop_channel(chan);
+            },
+        }
+
+        Flow::Continue
+    }
+}
+
+/// A rendered subsong
+pub struct ADLSubsong {
+    pub index: usize,
+    pub samples: Vec<i16>,
+}
+
+/// Render every subsong of an ADL file to mono 16-bit PCM. Songs looping
+/// forever are stopped after `max_duration` seconds.
+pub fn render_subsongs(
+    data: &[u8],
+    sample_rate: u32,
+    max_duration: u32,
+) -> Result<Vec<ADLSubsong>> {
+    if data.len() < TRACK_ENTRY_COUNT + 4*PROGRAM_COUNT {
+        return Err(anyhow!("ADL: file is too short"));
+    }
+
+    let (track_entries, sound_data) = data.split_at(TRACK_ENTRY_COUNT);
+    let samples_per_tick = (sample_rate as f64)/CALLBACKS_PER_SECOND;
+    let max_samples = usize::try_from(sample_rate as u64*max_duration as u64)
+        .map_err(|_| anyhow!("ADL: maximum duration is too long"))?;
+    // Let the last notes fade out once all the programs are over
+    let tail_samples = sample_rate as usize;
+
+    let mut subsongs = Vec::new();
+
+    for (index, program_id) in track_entries.iter().enumerate() {
+        if *program_id == 0xff {
+            continue;
+        }
+
+        let mut driver = Driver::new(sound_data, sample_rate);
+
+        if driver.start_sound(*program_id as usize).is_err() {
+            continue;
+        }
+
+        let mut samples = Vec::new();
+        let mut time = 0.;
+        let mut tail = None;
+
+        while samples.len() < max_samples {
+            driver.callback();
+
+            if tail.is_none() && driver.is_idle() {
+                tail = Some(samples.len() + tail_samples);
+            }
+
+            time += samples_per_tick;
+
+            let end = usize::min(time as usize, max_samples);
+            let start = samples.len();
+
+            samples.resize(end, 0);
+            driver.opl.generate(&mut samples[start..]);
+
+            if tail.is_some_and(|tail| samples.len() >= tail) {
+                break;
+            }
+        }
+
+        subsongs.push(ADLSubsong { index, samples });
+    }
+
+    Ok(subsongs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 11025;
+
+    // FNV-1a hash of the samples
+    fn checksum(samples: &[i16]) -> u64 {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    // A song with a single program playing three notes on the first channel
+    fn song() -> Vec<u8> {
+        let mut track_entries = [0xff; TRACK_ENTRY_COUNT];
+        let mut offsets = vec![0u16; 2*PROGRAM_COUNT];
+        let program = [
+            // channel, priority
+            0x00, 0x01,
+            // setupInstrument 0, setTempo, setChannelTempo
+            0x90, 0x00, 0xa6, 0x40, 0xa9, 0x40,
+            // notes and their durations, then a rest
+            0x45, 0x10, 0x47, 0x10, 0x49, 0x20, 0x89, 0x10,
+            // stopChannel
+            0xff, 0x00,
+        ];
+        let instrument = [
+            0x01, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0xf4, 0xf4, 0x44, 0x44,
+        ];
+
+        track_entries[0] = 0;
+        offsets[0] = (4*PROGRAM_COUNT) as u16;
+        offsets[PROGRAM_COUNT] = (4*PROGRAM_COUNT + program.len()) as u16;
+
+        let mut data = track_entries.to_vec();
+
+        data.extend(offsets.iter().flat_map(|offset| offset.to_le_bytes()));
+        data.extend(program);
+        data.extend(instrument);
+        data
+    }
+
+    #[test]
+    fn render_synthetic_song() {
+        let subsongs = render_subsongs(&song(), SAMPLE_RATE, 10).unwrap();
+
+        assert_eq!(subsongs.len(), 1);
+        assert_eq!(subsongs[0].index, 0);
+
+        let samples = &subsongs[0].samples;
+
+        assert!(samples.iter().any(|sample| *sample != 0));
+        // The song ends well before the maximum duration
+        assert!(samples.len() < 10*SAMPLE_RATE as usize);
+        assert_eq!(samples.len(), 59565);
+        assert_eq!(checksum(samples), 0xd4ef9743976dbc45);
+    }
+
+    #[test]
+    fn render_is_stopped_at_the_maximum_duration() {
+        let subsongs = render_subsongs(&song(), SAMPLE_RATE, 1).unwrap();
+
+        assert_eq!(subsongs[0].samples.len(), SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn short_files_are_rejected() {
+        assert!(render_subsongs(&[0; 16], SAMPLE_RATE, 10).is_err());
+    }
+}
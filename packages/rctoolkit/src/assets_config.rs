@@ -1,46 +1,150 @@
 use std::fs;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use image::ImageFormat;
 
 use serde::Deserialize;
 
 use dune2_assets::prelude::{
+    Announcement,
+    ColorMetric,
+    Dithering,
+    Music,
+    MusicFormat,
     Palette,
+    PaletteCycle,
+    Point,
+    RemapTable,
     Size,
+    Sound,
+    StructureInfo,
     Tile,
     TileAnchor,
+    TileLayout,
     Tilemap,
     TileTransformation,
+    UnitInfo,
 };
 
 use crate::{
-    icn, pal, shp
+    exe, icn, pal, sheet, shp, voc, xmi
 };
+use crate::sheet::{SheetRect, SheetSlicing};
 
 
 #[derive(Debug, Deserialize)]
 pub struct PaletteConfig {
     pub path: PathBuf,
+    #[serde(default)]
+    pub transparent_index: usize,
+    #[serde(default)]
+    pub opaque: bool,
 }
 
 #[derive(Debug, Deserialize)]
 pub enum SourceType {
     SHP,
     ICN,
+    PNG,
+    /// Any image format supported by the `image` crate, guessed from the
+    /// file content.
+    Image,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceGridConfig {
+    pub size: Size,
+    pub count: Option<usize>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SourceRectConfig {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Image sources are sliced either by `grid` or by `rects`. Without any of
+/// them the whole image is a single tile. Their pixels are quantized to the
+/// palette according to `metric` and `dithering`.
 #[derive(Debug, Deserialize)]
 pub struct SourceConfig {
     pub path: PathBuf,
     pub kind: SourceType,
+    pub grid: Option<SourceGridConfig>,
+    pub rects: Option<Vec<SourceRectConfig>>,
+    #[serde(default)]
+    pub metric: ColorMetric,
+    #[serde(default)]
+    pub dithering: Dithering,
+}
+
+impl SourceConfig {
+    fn sheet_slicing(&self) -> Result<SheetSlicing> {
+        match (&self.grid, &self.rects) {
+            (None, None) => Ok(SheetSlicing::Whole),
+            (Some(grid), None) => Ok(SheetSlicing::Grid(grid.size, grid.count)),
+            (None, Some(rects)) => Ok(SheetSlicing::Rects(rects
+                .iter()
+                .map(|rect| SheetRect {
+                    x: rect.x,
+                    y: rect.y,
+                    width: rect.width,
+                    height: rect.height,
+                })
+                .collect()
+            )),
+            (Some(_), Some(_)) => Err(anyhow!(
+                "Source '{}' cannot be sliced by both grid and rects",
+                self.path.display(),
+            )),
+        }
+    }
+
+    fn load_sheet(
+        &self,
+        format: Option<ImageFormat>,
+        palette: &Palette,
+    ) -> Result<Vec<Tile>> {
+        sheet::read_tiles_from_file(
+            &self.path,
+            format,
+            &self.sheet_slicing()?,
+            palette,
+            self.metric,
+            self.dithering,
+        )
+    }
+}
+
+/// A single transformation or a list of transformations applied in order.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TileTransformConfig {
+    One(TileTransformation),
+    Many(Vec<TileTransformation>),
+}
+
+impl TileTransformConfig {
+    pub fn transformations(&self) -> &[TileTransformation] {
+        match self {
+            Self::One(transformation) => std::slice::from_ref(transformation),
+            Self::Many(transformations) => transformations,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct TileRef {
     pub index: usize,
-    pub transform: Option<TileTransformation>,
+    pub transform: Option<TileTransformConfig>,
     pub anchor: Option<TileAnchor>,
+    /// Origin of the tile in the source tile coordinates, it follows the
+    /// tile through the resize and the transformations.
+    pub origin: Option<Point>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -48,6 +152,60 @@ pub struct TilesetConfig {
     pub id: String,
     pub size: Size,
     pub tile_refs: Vec<TileRef>,
+    /// Store the tiles trimmed to their opaque pixels.
+    #[serde(default)]
+    pub trim: bool,
+    /// Remove the duplicated tiles, the indices of the tilemaps using the
+    /// tileset are remapped.
+    #[serde(default)]
+    pub compact: bool,
+    /// Arrangement of the tile data, `Atlas` stores the tiles as a texture
+    /// atlas.
+    #[serde(default)]
+    pub layout: TileLayout,
+}
+
+/// Detection of the duplicated tiles of all the tilesets.
+#[derive(Debug, Deserialize)]
+pub struct DedupeConfig {
+    /// Also detect the tiles which are a transformation of another one.
+    #[serde(default)]
+    pub transforms: bool,
+    /// Store the tiles of all the tilesets in a shared pool, identical tiles
+    /// being stored once.
+    #[serde(default)]
+    pub pool: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub enum MusicSourceType {
+    ADL,
+    XMI,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MusicConfig {
+    pub id: String,
+    pub path: PathBuf,
+    pub kind: MusicSourceType,
+}
+
+/// Directory of VOC files, typically the content of one of the voice PAK
+/// files.
+#[derive(Debug, Deserialize)]
+pub struct VoiceConfig {
+    pub path: PathBuf,
+}
+
+/// Location of `DUNE2.EXE`. The unit and structure tables are located in the
+/// executable, the offsets override the detection for unusual versions.
+#[derive(Debug, Deserialize)]
+pub struct ExecutableConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub unit_table_offset: Option<usize>,
+    #[serde(default)]
+    pub structure_table_offset: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,6 +214,18 @@ pub struct Config {
     pub sources: Vec<SourceConfig>,
     pub tilesets: Vec<TilesetConfig>,
     pub tilemaps: Vec<Tilemap>,
+    #[serde(default)]
+    pub musics: Vec<MusicConfig>,
+    pub executable: Option<ExecutableConfig>,
+    #[serde(default)]
+    pub voices: Vec<VoiceConfig>,
+    #[serde(default)]
+    pub announcements: Vec<Announcement>,
+    #[serde(default)]
+    pub remap_tables: Vec<RemapTable>,
+    #[serde(default)]
+    pub palette_cycles: Vec<PaletteCycle>,
+    pub dedupe: Option<DedupeConfig>,
 }
 
 impl Config {
@@ -86,17 +256,50 @@ impl Config {
             }
         }
 
+        // if music paths are relative, make them absolute by joining them
+        // with the config file's directory
+        for music in config.musics.iter_mut() {
+            if !music.path.is_absolute() {
+                music.path = data_dir.join(&music.path);
+            }
+        }
+
+        // if voice paths are relative, make them absolute by joining them
+        // with the config file's directory
+        for voice in config.voices.iter_mut() {
+            if !voice.path.is_absolute() {
+                voice.path = data_dir.join(&voice.path);
+            }
+        }
+
+        // if executable path is relative, make it absolute by joining it
+        // with the config file's directory
+        if let Some(executable) = config.executable.as_mut() {
+            if !executable.path.is_absolute() {
+                executable.path = data_dir.join(&executable.path);
+            }
+        }
+
         Ok(config)
     }
 
     pub fn load_palette(
         &self,
     ) -> Result<Palette> {
-        pal::read_palette_from_file(&self.palette.path)
+        let mut palette = pal::read_palette_from_file(&self.palette.path)?;
+        let transparent_index = if self.palette.opaque {
+            None
+        } else {
+            Some(self.palette.transparent_index)
+        };
+
+        palette.set_transparent_index(transparent_index)?;
+        Ok(palette)
     }
 
     pub fn load_sources(
         &self,
+        palette: &Palette,
     ) -> Result<Vec<Tile>> {
         let mut tiles = Vec::new();
 
@@ -104,8 +307,77 @@ impl Config {
             tiles.append(&mut match source.kind {
                 SourceType::ICN => icn::read_tiles_from_file(&source.path)?,
                 SourceType::SHP => shp::read_tiles_from_file(&source.path)?,
+                SourceType::PNG => source.load_sheet(Some(ImageFormat::Png), palette)?,
+                SourceType::Image => source.load_sheet(None, palette)?,
             });
         }
         Ok(tiles)
     }
+    pub fn load_music(
+        &self,
+        music_config: &MusicConfig,
+    ) -> Result<Music> {
+        let (format, sequences) = match music_config.kind {
+            MusicSourceType::ADL => (
+                MusicFormat::Adlib,
+                vec![fs::read(&music_config.path)?],
+            ),
+            MusicSourceType::XMI => (
+                MusicFormat::Midi,
+                xmi::read_sequences_from_file(&music_config.path)?,
+            ),
+        };
+        let mut music = Music::new(&music_config.id, format);
+
+        for sequence in sequences.iter() {
+            music.add(sequence);
+        }
+        Ok(music)
+    }
+    pub fn load_units(
+        &self,
+    ) -> Result<Vec<UnitInfo>> {
+        match &self.executable {
+            Some(executable) => exe::read_units_from_file(
+                &executable.path,
+                executable.unit_table_offset,
+            ),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn load_structures(
+        &self,
+    ) -> Result<Vec<StructureInfo>> {
+        match &self.executable {
+            Some(executable) => exe::read_structures_from_file(
+                &executable.path,
+                executable.structure_table_offset,
+            ),
+            None => Ok(Vec::new()),
+        }
+    }
+    /// Load all the VOC files of a voice directory. Sounds are identified by
+    /// their uppercased file stem.
+    pub fn load_voice(
+        &self,
+        voice_config: &VoiceConfig,
+    ) -> Result<Vec<(String, Sound)>> {
+        let mut sounds = Vec::new();
+
+        for entry in fs::read_dir(&voice_config.path)? {
+            let path = entry?.path();
+            let is_voc = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("voc"));
+
+            if let (true, Some(stem)) = (is_voc, path.file_stem()) {
+                let sound_id = stem.to_string_lossy().to_uppercase();
+                sounds.push((sound_id, voc::read_sound_from_file(&path)?));
+            }
+        }
+
+        sounds.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(sounds)
+    }
 }
@@ -1,8 +1,14 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use anyhow::Result;
 
+use dune2_assets::prelude::{
+    Point,
+    TileAnchor,
+    TileTransformation,
+};
+
 use crate::assets_config::*;
 
 pub fn check_tileset(
@@ -12,7 +18,7 @@ pub fn check_tileset(
     let tileset_id = &tileset_config.id;
     let mut tile_indexes = HashSet::<usize>::from_iter(0..tileset_config.tile_refs.len());
 
-    print!("tilset '{tileset_id}' unused tiles:");
+    print!("tileset '{tileset_id}' unused tiles:");
 
     config.tilemaps.iter()
         .filter(|tilemap| tilemap.tileset.as_ref() == tileset_id)
@@ -27,6 +33,63 @@ pub fn check_tileset(
     print!("\n");
 }
 
+/// Fields identifying the tile produced by a tile reference.
+type TileRefKey<'a> = (
+    usize,
+    &'a [TileTransformation],
+    Option<TileAnchor>,
+    Option<Point>,
+);
+
+fn tile_ref_key(
+    tile_ref: &TileRef,
+) -> TileRefKey<'_> {
+    let transformations = tile_ref.transform
+        .as_ref()
+        .map(|transform| transform.transformations())
+        .unwrap_or_default();
+
+    (tile_ref.index, transformations, tile_ref.anchor, tile_ref.origin)
+}
+
+pub fn check_tileset_duplicates(
+    config: &Config,
+    tileset_config: &TilesetConfig,
+) {
+    let tileset_id = &tileset_config.id;
+
+    // The first occurrence of each tile, in this tileset or a previous one
+    let mut firsts = HashMap::<TileRefKey, (&str, usize)>::new();
+
+    for other_config in config.tilesets.iter() {
+        for (index, tile_ref) in other_config.tile_refs.iter().enumerate() {
+            firsts.entry(tile_ref_key(tile_ref)).or_insert((&other_config.id, index));
+        }
+    }
+
+    let duplicates = tileset_config.tile_refs.iter()
+        .enumerate()
+        .filter_map(|(tile_index, tile_ref)| {
+            let (first_tileset_id, first_index) = firsts[&tile_ref_key(tile_ref)];
+
+            (first_tileset_id != tileset_id || first_index != tile_index)
+                .then_some((tile_index, first_tileset_id, first_index))
+        })
+        .collect::<Vec<_>>();
+
+    if duplicates.is_empty() {
+        return;
+    }
+
+    print!("tileset '{tileset_id}' duplicated tiles:");
+
+    for (tile_index, first_tileset_id, first_index) in duplicates {
+        print!(" {tile_index} ({first_tileset_id}:{first_index})");
+    }
+
+    println!();
+}
+
 /******************************************************************************
  * Create command run
  *****************************************************************************/
@@ -47,9 +110,11 @@ pub fn run(args: &Args) -> Result<()> {
         if let Some(tileset_id) = &args.tileset_id {
             if tileset_id == &tileset_config.id {
                 check_tileset(&config, tileset_config);
+                check_tileset_duplicates(&config, tileset_config);
             }
         } else {
             check_tileset(&config, tileset_config);
+            check_tileset_duplicates(&config, tileset_config);
         }
     }
 
@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::{
+    BufReader,
+    BufWriter,
+};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::Assets;
+
+use crate::create::ArgCompression;
+
+
+/******************************************************************************
+ * Convert formats
+ *****************************************************************************/
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ConvertFormat {
+    Rc,
+    Json,
+    Ron,
+}
+
+impl ConvertFormat {
+    fn from_path(
+        filepath: &Path,
+    ) -> Result<Self> {
+        let extension = filepath
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        match extension.as_deref() {
+            Some("rc") => Ok(Self::Rc),
+            Some("json") => Ok(Self::Json),
+            Some("ron") => Ok(Self::Ron),
+            _ => Err(anyhow!(
+                "Unsupported file format {:?}, expected a .rc, .json or .ron file.",
+                filepath,
+            )),
+        }
+    }
+}
+
+fn read_assets(
+    filepath: &Path,
+    format: ConvertFormat,
+) -> Result<Assets> {
+    let mut reader = BufReader::new(fs::File::open(filepath)?);
+
+    let mut assets: Assets = match format {
+        ConvertFormat::Rc => return Ok(Assets::read_from(&mut reader)?),
+        ConvertFormat::Json => serde_json::from_reader(reader)?,
+        ConvertFormat::Ron => ron::de::from_reader(reader)?,
+    };
+
+    assets.link_tile_pool()?;
+    Ok(assets)
+}
+
+fn write_assets(
+    assets: &Assets,
+    filepath: &Path,
+    format: ConvertFormat,
+    compression: ArgCompression,
+) -> Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(filepath)?);
+
+    match format {
+        ConvertFormat::Rc => assets.write_with_compression(&mut writer, compression.into())?,
+        ConvertFormat::Json => serde_json::to_writer_pretty(&mut writer, assets)?,
+        ConvertFormat::Ron => {
+            ron::ser::to_writer_pretty(&mut writer, assets, ron::ser::PrettyConfig::default())?
+        },
+    }
+    Ok(())
+}
+
+/******************************************************************************
+ * Convert command run
+ *****************************************************************************/
+
+/// Convert an assets file to or from a human readable format, the format
+/// of each file is given by its extension: .rc, .json or .ron
+#[derive(clap::Args)]
+pub struct Args {
+    /// Input file path
+    pub input_file: PathBuf,
+
+    /// Output file path
+    pub output_file: PathBuf,
+
+    /// Overwrite existing files
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
+    pub force_overwrite: bool,
+
+    /// Compression of .rc output files
+    #[arg(long, value_enum, default_value = "deflate")]
+    pub compression: ArgCompression,
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    let input_format = ConvertFormat::from_path(&args.input_file)?;
+    let output_format = ConvertFormat::from_path(&args.output_file)?;
+
+    if args.output_file.exists() && !args.force_overwrite {
+        return Err(anyhow!(
+            "Output file already exists. Use --force-overwrite to overwrite."
+        ));
+    }
+
+    let assets = read_assets(&args.input_file, input_format)?;
+
+    write_assets(&assets, &args.output_file, output_format, args.compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use dune2_assets::prelude::{
+        default_remap_tables,
+        Color,
+        Palette,
+        Shape,
+        Size,
+        Tile,
+        Tilemap,
+        TilePool,
+        Tileset,
+    };
+
+    const TILE_SIZE: Size = Size { width: 2, height: 2 };
+
+    // Owned tileset and a pooled one, so that reading the human readable
+    // formats has to link the pool
+    fn assets() -> Assets {
+        let mut palette = Palette::new();
+        let mut owned = Tileset::new("owned", TILE_SIZE);
+        let mut pooled = Tileset::new("pooled", TILE_SIZE);
+        let mut tile_pool = TilePool::new();
+
+        palette.set_color(1, &Color::new(200, 40, 40)).unwrap();
+
+        for index in 0..3 {
+            owned.add(Tile::new(&[index, 1, 2, 3], TILE_SIZE)).unwrap();
+            pooled.add(Tile::new(&[index%2, 10, 11, 12], TILE_SIZE)).unwrap();
+        }
+
+        pooled.move_to_pool(&mut tile_pool).unwrap();
+        pooled.link_tile_pool(&tile_pool).unwrap();
+
+        Assets {
+            palette,
+            tilesets: HashMap::from([
+                (owned.get_id().into(), owned),
+                (pooled.get_id().into(), pooled),
+            ]),
+            tilemaps: vec![Tilemap {
+                class: "map".into(),
+                shape: Shape { rows: 1, columns: 3 },
+                tiles: [0, 2, 1].into(),
+                tileset: "pooled".into(),
+            }],
+            musics: HashMap::new(),
+            units: Vec::new(),
+            structures: Vec::new(),
+            sounds: HashMap::new(),
+            announcements: Vec::new(),
+            remap_tables: default_remap_tables(),
+            palette_cycles: Vec::new(),
+            tile_pool,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("convert-{}-{name}", std::process::id()))
+    }
+
+    fn convert(
+        input_file: &Path,
+        output_file: &Path,
+    ) {
+        run(&Args {
+            input_file: input_file.into(),
+            output_file: output_file.into(),
+            force_overwrite: true,
+            compression: ArgCompression::Deflate,
+        }).unwrap();
+    }
+
+    #[test]
+    fn human_readable_round_trip() {
+        let rc_filepath = temp_path("assets.rc");
+
+        assets().write_to(&mut fs::File::create(&rc_filepath).unwrap()).unwrap();
+
+        for extension in ["json", "ron"] {
+            let filepath = temp_path(&format!("assets.{extension}"));
+            let output_filepath = temp_path(&format!("{extension}.rc"));
+
+            convert(&rc_filepath, &filepath);
+            convert(&filepath, &output_filepath);
+
+            let expected = Assets::read_from(&mut fs::File::open(&rc_filepath).unwrap()).unwrap();
+            let assets = Assets::read_from(&mut fs::File::open(&output_filepath).unwrap()).unwrap();
+
+            // Maps of the binary format are not sorted, the assets are
+            // compared by value
+            assert_eq!(
+                serde_json::to_value(&assets).unwrap(),
+                serde_json::to_value(&expected).unwrap(),
+                "{extension}",
+            );
+
+            let assets = read_assets(&filepath, ConvertFormat::from_path(&filepath).unwrap()).unwrap();
+            let tiles = assets.tilesets["pooled"]
+                .tile_iter()
+                .map(|tile| tile.to_tile())
+                .collect::<Vec<Tile>>();
+
+            assert_eq!(tiles[2], Tile::new(&[0, 10, 11, 12], TILE_SIZE), "{extension}");
+
+            fs::remove_file(filepath).unwrap();
+            fs::remove_file(output_filepath).unwrap();
+        }
+
+        fs::remove_file(rc_filepath).unwrap();
+    }
+
+    #[test]
+    fn json_lists_readable_values() {
+        let rc_filepath = temp_path("readable.rc");
+        let json_filepath = temp_path("readable.json");
+
+        assets().write_to(&mut fs::File::create(&rc_filepath).unwrap()).unwrap();
+        convert(&rc_filepath, &json_filepath);
+
+        let json = serde_json::from_reader::<_, serde_json::Value>(
+            fs::File::open(&json_filepath).unwrap(),
+        ).unwrap();
+
+        fs::remove_file(rc_filepath).unwrap();
+        fs::remove_file(json_filepath).unwrap();
+
+        assert_eq!(json["palette"]["colors"].as_array().unwrap().len(), 256);
+        assert_eq!(json["palette"]["colors"][1], serde_json::to_value(Color::new(200, 40, 40)).unwrap());
+
+        // Owned tiles are listed in the tileset, pooled ones in the pool
+        let owned = &json["tilesets"]["owned"];
+        let pooled = &json["tilesets"]["pooled"];
+
+        assert_eq!(owned["tiles"][2]["rows"], serde_json::json!(["0201", "0203"]));
+        assert_eq!(pooled["tiles"], serde_json::json!([]));
+        assert_eq!(pooled["pool_refs"], serde_json::json!([0, 1, 0]));
+        assert_eq!(json["tile_pool"][1]["rows"], serde_json::json!(["010a", "0b0c"]));
+    }
+}
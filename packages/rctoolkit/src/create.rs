@@ -7,13 +7,23 @@ use std::path::PathBuf;
 use anyhow::{anyhow, Result};
 
 use dune2_assets::prelude::{
+    default_remap_tables,
     Assets,
+    AssetsCompression,
+    Music,
+    Palette,
+    PaletteCycle,
+    RemapTable,
+    Sound,
     Tile,
     Tilemap,
+    TilePool,
     Tileset,
+    DEFAULT_TRANSPARENT_INDEX,
 };
 
 use crate::assets_config::*;
+use crate::dedupe;
 
 
 /******************************************************************************
@@ -22,6 +32,10 @@ use crate::assets_config::*;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CreateError {
+    MusicDuplicateId(String),
+    PaletteCycleDuplicateId(String),
+    RemapTableDuplicateId(String),
+    SoundDuplicateId(String),
     TilesetDuplicateId(String),
     TilesetInvalidId(String),
     TilesetInvalidTileIndex(String, usize),
@@ -33,6 +47,18 @@ impl fmt::Display for CreateError {
         f: &mut fmt::Formatter,
     ) -> fmt::Result {
         match self {
+            Self::MusicDuplicateId(id) => {
+                write!(f, "Duplicate music '{id}'")
+            },
+            Self::PaletteCycleDuplicateId(id) => {
+                write!(f, "Duplicate palette cycle '{id}'")
+            },
+            Self::RemapTableDuplicateId(id) => {
+                write!(f, "Duplicate remap table '{id}'")
+            },
+            Self::SoundDuplicateId(id) => {
+                write!(f, "Duplicate sound '{id}'")
+            },
             Self::TilesetDuplicateId(id) => {
                 write!(f, "Duplicate tileset '{id}'")
             },
@@ -52,9 +78,13 @@ impl fmt::Display for CreateError {
 
 fn load_tilesets(
     config: &Config,
+    palette: &Palette,
     sources: &[Tile],
 ) -> Result<HashMap<String, Tileset>> {
     let mut tilesets = HashMap::<String, Tileset>::new();
+    let transparent_index = palette
+        .transparent_index()
+        .unwrap_or(DEFAULT_TRANSPARENT_INDEX) as u8;
 
     for tileset_config in config.tilesets.iter() {
         let tileset_id = tileset_config.id.clone();
@@ -63,7 +93,8 @@ fn load_tilesets(
             return Err(anyhow!(CreateError::TilesetDuplicateId(tileset_id)));
         }
 
-        let mut tileset = Tileset::new(&tileset_id, tileset_config.size);
+        let mut tileset = Tileset::new(&tileset_id, tileset_config.size)
+            .with_layout(tileset_config.layout);
         let tile_size = tileset_config.size;
 
         for tile_ref in tileset_config.tile_refs.iter() {
@@ -77,10 +108,28 @@ fn load_tilesets(
                 )));
             }
 
-            tileset.add(tile
-                .resize(tile_size, tile_ref.anchor)
-                .transform(tile_ref.transform)
-            )?;
+            let transforms = tile_ref.transform
+                .as_ref()
+                .map(|transform| transform.transformations())
+                .unwrap_or_default();
+
+            // Quarter turns swap the tile dimensions, the tile is resized so
+            // that it fits the tileset once transformed
+            let size = transforms
+                .iter()
+                .fold(tile_size, |size, transform| transform.transformed_size(size));
+
+            let tile = tile
+                .clone()
+                .with_origin(tile_ref.origin.or(tile.origin()))
+                .resize(size, tile_ref.anchor)
+                .transform_all(transforms);
+
+            if tileset_config.trim {
+                tileset.add(tile.trim(transparent_index))?;
+            } else {
+                tileset.add(tile)?;
+            }
         }
 
         tilesets.insert(tileset_id, tileset);
@@ -89,6 +138,98 @@ fn load_tilesets(
     return Ok(tilesets)
 }
 
+/******************************************************************************
+ * Musics creation
+ *****************************************************************************/
+
+fn load_musics(
+    config: &Config,
+) -> Result<HashMap<String, Music>> {
+    let mut musics = HashMap::<String, Music>::new();
+
+    for music_config in config.musics.iter() {
+        let music_id = music_config.id.clone();
+
+        if musics.contains_key(&music_id) {
+            return Err(anyhow!(CreateError::MusicDuplicateId(music_id)));
+        }
+
+        musics.insert(music_id, config.load_music(music_config)?);
+    }
+
+    Ok(musics)
+}
+
+/******************************************************************************
+ * Sounds creation
+ *****************************************************************************/
+
+fn load_sounds(
+    config: &Config,
+) -> Result<HashMap<String, Sound>> {
+    let mut sounds = HashMap::<String, Sound>::new();
+
+    for voice_config in config.voices.iter() {
+        for (sound_id, sound) in config.load_voice(voice_config)? {
+            if sounds.contains_key(&sound_id) {
+                return Err(anyhow!(CreateError::SoundDuplicateId(sound_id)));
+            }
+
+            sounds.insert(sound_id, sound);
+        }
+    }
+
+    Ok(sounds)
+}
+
+/******************************************************************************
+ * Remap tables creation
+ *****************************************************************************/
+
+fn load_remap_tables(
+    config: &Config,
+) -> Result<HashMap<String, RemapTable>> {
+    let mut remap_tables = HashMap::<String, RemapTable>::new();
+
+    for remap_table in config.remap_tables.iter() {
+        let remap_table_id = remap_table.id.clone();
+
+        if remap_tables.contains_key(&remap_table_id) {
+            return Err(anyhow!(CreateError::RemapTableDuplicateId(remap_table_id)));
+        }
+
+        remap_table.validate()?;
+        remap_tables.insert(remap_table_id, remap_table.clone());
+    }
+
+    // Built-in factions tables can be overridden by the configuration
+    let mut tables = default_remap_tables();
+    tables.extend(remap_tables);
+
+    Ok(tables)
+}
+
+/******************************************************************************
+ * Palette cycles creation
+ *****************************************************************************/
+
+fn load_palette_cycles(
+    config: &Config,
+) -> Result<Vec<PaletteCycle>> {
+    let mut palette_cycles = Vec::<PaletteCycle>::new();
+
+    for palette_cycle in config.palette_cycles.iter() {
+        if palette_cycles.iter().any(|cycle| cycle.id == palette_cycle.id) {
+            return Err(anyhow!(CreateError::PaletteCycleDuplicateId(palette_cycle.id.clone())));
+        }
+
+        palette_cycle.check()?;
+        palette_cycles.push(palette_cycle.clone());
+    }
+
+    Ok(palette_cycles)
+}
+
 /******************************************************************************
  * Check tilemaps
  *****************************************************************************/
@@ -109,10 +250,88 @@ fn check_tilemaps(
     Ok(())
 }
 
+/******************************************************************************
+ * Tiles deduplication
+ *****************************************************************************/
+fn compact_tilesets(
+    tileset_configs: &[TilesetConfig],
+    tilesets: &mut HashMap<String, Tileset>,
+    tilemaps: &mut [Tilemap],
+) -> Result<()> {
+    let compact_tilesets = tileset_configs
+        .iter()
+        .filter(|tileset_config| tileset_config.compact);
+
+    for tileset_config in compact_tilesets {
+        let tileset_id = &tileset_config.id;
+        let tileset = tilesets.get_mut(tileset_id).ok_or(
+            anyhow!(CreateError::TilesetInvalidId(tileset_id.clone()))
+        )?;
+        let remap = tileset.dedup();
+
+        for tilemap in tilemaps.iter_mut() {
+            if tilemap.tileset.as_ref() == tileset_id {
+                tilemap.remap_tiles(&remap)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dedupe_tilesets(
+    dedupe_config: &DedupeConfig,
+    tilesets: &mut HashMap<String, Tileset>,
+) -> Result<TilePool> {
+    let duplicates = dedupe::find_duplicates(tilesets, dedupe_config.transforms);
+
+    for duplicate in duplicates.iter() {
+        println!("{duplicate}");
+    }
+
+    let mut tile_pool = TilePool::new();
+
+    if dedupe_config.pool {
+        let mut tileset_ids = tilesets.keys().cloned().collect::<Vec<String>>();
+        tileset_ids.sort();
+
+        for tileset_id in tileset_ids.iter() {
+            if let Some(tileset) = tilesets.get_mut(tileset_id) {
+                tileset.move_to_pool(&mut tile_pool)?;
+            }
+        }
+
+        // Link the tilesets once the pool is complete so that they all share
+        // its storage
+        for tileset in tilesets.values_mut() {
+            tileset.link_tile_pool(&tile_pool)?;
+        }
+    }
+    Ok(tile_pool)
+}
+
 /******************************************************************************
  * Create command run
  *****************************************************************************/
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum ArgCompression {
+    None,
+    Deflate,
+    Zstd,
+    Lz4,
+}
+
+impl From<ArgCompression> for AssetsCompression {
+    fn from(compression: ArgCompression) -> Self {
+        match compression {
+            ArgCompression::None => Self::None,
+            ArgCompression::Deflate => Self::Deflate,
+            ArgCompression::Zstd => Self::Zstd,
+            ArgCompression::Lz4 => Self::Lz4,
+        }
+    }
+}
+
 #[derive(clap::Args)]
 pub struct Args {
     /// Input file path
@@ -125,23 +344,48 @@ pub struct Args {
     /// Output folder path
     #[arg(long, short, default_value = "dune2.rc")]
     pub output_file: PathBuf,
+
+    /// Compression of the output file, lz4 is the fastest to decode
+    #[arg(long, value_enum, default_value = "deflate")]
+    pub compression: ArgCompression,
 }
 
 pub fn run(args: &Args) -> Result<()> {
     let config = Config::try_read_from_file(&args.config_filepath)?;
 
     let palette = config.load_palette()?;
-    let sources = config.load_sources()?;
-
-    let tilesets = load_tilesets(&config, sources.as_slice())?;
-    let tilemaps = config.tilemaps;
+    let sources = config.load_sources(&palette)?;
+
+    let mut tilesets = load_tilesets(&config, &palette, sources.as_slice())?;
+    let musics = load_musics(&config)?;
+    let units = config.load_units()?;
+    let structures = config.load_structures()?;
+    let sounds = load_sounds(&config)?;
+    let remap_tables = load_remap_tables(&config)?;
+    let palette_cycles = load_palette_cycles(&config)?;
+    let announcements = config.announcements;
+    let mut tilemaps = config.tilemaps;
 
     check_tilemaps(&tilemaps, &tilesets)?;
+    compact_tilesets(&config.tilesets, &mut tilesets, &mut tilemaps)?;
+
+    let tile_pool = match config.dedupe.as_ref() {
+        Some(dedupe_config) => dedupe_tilesets(dedupe_config, &mut tilesets)?,
+        None => TilePool::new(),
+    };
 
     let rc = Assets {
         palette,
         tilesets,
         tilemaps,
+        musics,
+        units,
+        structures,
+        sounds,
+        announcements,
+        remap_tables,
+        palette_cycles,
+        tile_pool,
     };
 
     if args.output_file.exists() && !args.force_overwrite {
@@ -151,7 +395,7 @@ pub fn run(args: &Args) -> Result<()> {
     }
 
     let mut output = fs::File::create(&args.output_file)?;
-    rc.write_to(&mut output)?;
+    rc.write_with_compression(&mut output, args.compression.into())?;
 
     Ok(())
 }
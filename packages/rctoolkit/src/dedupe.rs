@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use dune2_assets::prelude::{
+    Tile,
+    Tileset,
+    TileTransformation,
+};
+
+
+/// Position of a tile in the tilesets.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TileLocation {
+    pub tileset_id: String,
+    pub index: usize,
+}
+
+impl fmt::Display for TileLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tileset '{}' tile #{}", self.tileset_id, self.index)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TileDuplicate {
+    /// The second tile is identical to the first one.
+    Exact(TileLocation, TileLocation),
+    /// The second tile is the first one transformed.
+    Transformed(TileLocation, TileLocation, TileTransformation),
+}
+
+impl fmt::Display for TileDuplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(first, second) => {
+                write!(f, "{second} duplicates {first}")
+            },
+            Self::Transformed(first, second, transform) => {
+                write!(f, "{second} duplicates {first} transformed by {transform:?}")
+            },
+        }
+    }
+}
+
+/// Find the duplicated tiles of the given tilesets. Tiles are compared by
+/// content, trimmed tiles being compared to their untrimmed version. Each
+/// duplicate refers to the first occurrence of the tile, tilesets being
+/// visited by id.
+pub fn find_duplicates(
+    tilesets: &HashMap<String, Tileset>,
+    transforms: bool,
+) -> Vec<TileDuplicate> {
+    let mut tileset_ids = tilesets.keys().collect::<Vec<&String>>();
+    tileset_ids.sort();
+
+    let mut duplicates = Vec::new();
+    let mut firsts = HashMap::<Tile, TileLocation>::new();
+    let mut uniques = Vec::<(Tile, TileLocation)>::new();
+
+    for tileset_id in tileset_ids {
+        for (index, tile) in tilesets[tileset_id].tile_iter().enumerate() {
+            let tile = tile.untrim();
+            let location = TileLocation {
+                tileset_id: tileset_id.clone(),
+                index,
+            };
+
+            if let Some(first) = firsts.get(&tile) {
+                duplicates.push(TileDuplicate::Exact(first.clone(), location));
+            } else {
+                firsts.insert(tile.clone(), location.clone());
+                uniques.push((tile, location));
+            }
+        }
+    }
+
+    if transforms {
+        for (tile, location) in uniques.iter() {
+            let mut matches = BTreeMap::<&TileLocation, TileTransformation>::new();
+
+            for transform in TileTransformation::ALL {
+                let transformed = tile.transform(Some(transform));
+
+                // Pairs are reported once, from their first tile
+                if let Some(other) = firsts.get(&transformed) {
+                    if other > location {
+                        matches.entry(other).or_insert(transform);
+                    }
+                }
+            }
+
+            for (other, transform) in matches {
+                duplicates.push(TileDuplicate::Transformed(
+                    location.clone(),
+                    other.clone(),
+                    transform,
+                ));
+            }
+        }
+    }
+
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dune2_assets::prelude::Size;
+
+    const SIZE: Size = Size { width: 2, height: 2 };
+
+    fn tileset(
+        tileset_id: &str,
+        tiles: &[[u8; 4]],
+    ) -> (String, Tileset) {
+        let mut tileset = Tileset::new(tileset_id, SIZE);
+
+        for data in tiles {
+            tileset.add(Tile::new(data, SIZE)).unwrap();
+        }
+        (tileset_id.into(), tileset)
+    }
+
+    fn location(
+        tileset_id: &str,
+        index: usize,
+    ) -> TileLocation {
+        TileLocation {
+            tileset_id: tileset_id.into(),
+            index,
+        }
+    }
+
+    #[test]
+    fn exact_duplicates_refer_to_the_first_tile() {
+        let tilesets = HashMap::from([
+            tileset("b", &[[1, 2, 3, 4], [5, 5, 5, 5]]),
+            tileset("a", &[[5, 5, 5, 5], [1, 2, 3, 4], [5, 5, 5, 5]]),
+        ]);
+        let duplicates = find_duplicates(&tilesets, false)
+            .iter()
+            .map(|duplicate| match duplicate {
+                TileDuplicate::Exact(first, second) => (first.clone(), second.clone()),
+                TileDuplicate::Transformed(..) => panic!("{duplicate}"),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(duplicates, vec![
+            (location("a", 0), location("a", 2)),
+            (location("a", 1), location("b", 0)),
+            (location("a", 0), location("b", 1)),
+        ]);
+    }
+
+    #[test]
+    fn transformed_duplicates_are_reported_once() {
+        // 1 2    2 1
+        // 3 4    4 3
+        let tilesets = HashMap::from([
+            tileset("a", &[[1, 2, 3, 4]]),
+            tileset("b", &[[2, 1, 4, 3]]),
+        ]);
+
+        assert!(find_duplicates(&tilesets, false).is_empty());
+
+        let duplicates = find_duplicates(&tilesets, true);
+
+        assert_eq!(duplicates.len(), 1);
+        assert!(matches!(
+            &duplicates[0],
+            TileDuplicate::Transformed(first, second, TileTransformation::FlipX)
+                if *first == location("a", 0) && *second == location("b", 0)
+        ));
+    }
+}
@@ -0,0 +1,488 @@
+use std::fs;
+use std::io::{Cursor, Seek, SeekFrom};
+use std::path;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::{
+    StructureInfo,
+    StructureLayout,
+    UnitInfo,
+    STRUCTURE_NAMES,
+    UNIT_NAMES,
+};
+
+use crate::io::*;
+
+
+// Both tables records start with the same object info fields. Pointers to
+// strings and animations are 4 bytes far pointers.
+const OBJECT_INFO_SIZE: usize = 50;
+const UNIT_INFO_SIZE: usize = 86;
+const STRUCTURE_INFO_SIZE: usize = 79;
+
+// Upper bounds of the table values, well above the values of the game.
+// Distances are in tiles of the 64x64 map.
+const MAX_HITPOINTS: u16 = 5000;
+const MAX_CREDITS: u16 = 10000;
+const MAX_BUILD_TIME: u16 = 1000;
+const MAX_DISTANCE: u16 = 64;
+const MAX_POWER: u16 = 1000;
+
+// Releases of the executable whose table offsets were checked, identified by
+// their size, each entry is commented with the name of the release. Other
+// releases fall back to scanning the executable.
+struct KnownExecutable {
+    file_size: usize,
+    unit_table_offset: usize,
+    structure_table_offset: usize,
+}
+
+const KNOWN_EXECUTABLES: [KnownExecutable; 0] = [];
+
+struct ObjectInfo {
+    hitpoints: u16,
+    fog_uncover_radius: u16,
+    sprite_id: u16,
+    build_credits: u16,
+    build_time: u16,
+}
+
+fn skip(
+    reader: &mut Cursor<&[u8]>,
+    count: i64,
+) -> Result<()> {
+    reader.seek(SeekFrom::Current(count))?;
+    Ok(())
+}
+
+fn records(
+    data: &[u8],
+    table_offset: usize,
+    record_size: usize,
+    record_count: usize,
+) -> Result<impl Iterator<Item = Cursor<&[u8]>>> {
+    let table = record_size
+        .checked_mul(record_count)
+        .and_then(|table_size| table_offset.checked_add(table_size))
+        .and_then(|table_end| data.get(table_offset..table_end))
+        .ok_or(anyhow!("EXE: table at {table_offset:#x} is out of bounds"))?;
+
+    Ok(table.chunks_exact(record_size).map(Cursor::new))
+}
+
+fn read_object_info(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<ObjectInfo> {
+    // abbreviated name string, name, full name string, animation and flags
+    skip(reader, 14)?;
+    skip(reader, 2)?; // spawn chance
+    let hitpoints = u16::try_read_from::<LSB>(reader)?;
+    let fog_uncover_radius = u16::try_read_from::<LSB>(reader)?;
+    let sprite_id = u16::try_read_from::<LSB>(reader)?;
+    let build_credits = u16::try_read_from::<LSB>(reader)?;
+    let build_time = u16::try_read_from::<LSB>(reader)?;
+
+    reader.seek(SeekFrom::Start(OBJECT_INFO_SIZE as u64))?;
+
+    Ok(ObjectInfo {
+        hitpoints,
+        fog_uncover_radius,
+        sprite_id,
+        build_credits,
+        build_time,
+    })
+}
+
+fn read_unit_info(
+    name: &str,
+    reader: &mut Cursor<&[u8]>,
+) -> Result<UnitInfo> {
+    let object = read_object_info(reader)?;
+
+    // sprite indices, flags, dimension, movement type and animation speed
+    skip(reader, 12)?;
+    let moving_speed = u16::try_read_from::<LSB>(reader)?;
+    skip(reader, 1)?; // turning speed
+    let ground_sprite_id = u16::try_read_from::<LSB>(reader)?;
+    let turret_sprite_id = u16::try_read_from::<LSB>(reader)?;
+    // action, display mode, destroyed sprite and fire delay
+    skip(reader, 8)?;
+    let fire_distance = u16::try_read_from::<LSB>(reader)?;
+    let damage = u16::try_read_from::<LSB>(reader)?;
+
+    Ok(UnitInfo {
+        name: name.into(),
+        hitpoints: object.hitpoints,
+        build_credits: object.build_credits,
+        build_time: object.build_time,
+        sprite_id: object.sprite_id,
+        fog_uncover_radius: object.fog_uncover_radius,
+        moving_speed,
+        fire_distance,
+        damage,
+        ground_sprite_id,
+        turret_sprite_id,
+    })
+}
+
+fn read_structure_info(
+    name: &str,
+    reader: &mut Cursor<&[u8]>,
+) -> Result<StructureInfo> {
+    let object = read_object_info(reader)?;
+
+    skip(reader, 4)?; // enter filter
+    let credits_storage = u16::try_read_from::<LSB>(reader)?;
+    let power_usage = i16::try_read_from::<LSB>(reader)?;
+    let layout = u16::try_read_from::<LSB>(reader)?;
+
+    Ok(StructureInfo {
+        name: name.into(),
+        hitpoints: object.hitpoints,
+        build_credits: object.build_credits,
+        build_time: object.build_time,
+        sprite_id: object.sprite_id,
+        fog_uncover_radius: object.fog_uncover_radius,
+        credits_storage,
+        power_usage,
+        layout: StructureLayout::try_from_index(layout as usize)?,
+    })
+}
+
+fn read_executable(
+    path: &path::Path,
+) -> Result<Vec<u8>> {
+    let data = fs::read(path)?;
+
+    if data.get(0..2) != Some(b"MZ") {
+        return Err(anyhow!("EXE: invalid signature"));
+    }
+    Ok(data)
+}
+
+// Records start with the abbreviated name string id followed by a far pointer
+// to the name. All the names live in the same data segment, so a table is
+// recognized by records sharing one non null segment with distinct offsets.
+fn is_table(
+    table: &[u8],
+    record_size: usize,
+    is_record: impl Fn(&[u8]) -> bool,
+) -> bool {
+    let name_pointer = |record: &[u8]| (
+        u16::from_le_bytes([record[2], record[3]]),
+        u16::from_le_bytes([record[4], record[5]]),
+    );
+    let (_, segment) = name_pointer(table);
+
+    if segment == 0 {
+        return false;
+    }
+
+    let mut offsets = Vec::new();
+
+    for record in table.chunks_exact(record_size) {
+        let (offset, record_segment) = name_pointer(record);
+
+        if record_segment != segment || offsets.contains(&offset) || !is_record(record) {
+            return false;
+        }
+        offsets.push(offset);
+    }
+    true
+}
+
+fn known_table_offsets(
+    data: &[u8],
+    table_offset: fn(&KnownExecutable) -> usize,
+) -> impl Iterator<Item = usize> + '_ {
+    KNOWN_EXECUTABLES
+        .iter()
+        .filter(move |executable| executable.file_size == data.len())
+        .map(table_offset)
+}
+
+// The tables do not move within a version of the executable but differ
+// between versions. The offsets of the known versions are checked first,
+// the tables of the other versions are located by scanning the whole file.
+fn locate_table(
+    data: &[u8],
+    mut known_offsets: impl Iterator<Item = usize>,
+    record_size: usize,
+    record_count: usize,
+    is_record: impl Fn(&[u8]) -> bool,
+) -> Result<usize> {
+    let table_size = record_size*record_count;
+    let known_offset = known_offsets.find(|offset| {
+        offset
+            .checked_add(table_size)
+            .and_then(|table_end| data.get(*offset..table_end))
+            .is_some_and(|table| is_table(table, record_size, &is_record))
+    });
+
+    if let Some(offset) = known_offset {
+        return Ok(offset);
+    }
+
+    let mut offsets = data
+        .windows(table_size)
+        .enumerate()
+        .filter(|(_, table)| is_table(table, record_size, &is_record))
+        .map(|(offset, _)| offset);
+
+    match (offsets.next(), offsets.next()) {
+        (Some(offset), None) => Ok(offset),
+        (None, _) => Err(anyhow!("EXE: table not found, unknown executable version")),
+        (Some(first), Some(second)) => Err(anyhow!(
+            "EXE: table found at both {first:#x} and {second:#x}, set its offset explicitly"
+        )),
+    }
+}
+
+fn is_unit_record(
+    record: &[u8],
+) -> bool {
+    read_unit_info("", &mut Cursor::new(record))
+        .is_ok_and(|unit| {
+            unit.hitpoints <= MAX_HITPOINTS
+                && unit.build_credits <= MAX_CREDITS
+                && unit.build_time <= MAX_BUILD_TIME
+                && unit.fog_uncover_radius <= MAX_DISTANCE
+                && unit.fire_distance <= MAX_DISTANCE
+                && unit.damage <= MAX_HITPOINTS
+        })
+}
+
+fn is_structure_record(
+    record: &[u8],
+) -> bool {
+    read_structure_info("", &mut Cursor::new(record))
+        .is_ok_and(|structure| {
+            structure.hitpoints > 0
+                && structure.hitpoints <= MAX_HITPOINTS
+                && structure.build_credits <= MAX_CREDITS
+                && structure.build_time <= MAX_BUILD_TIME
+                && structure.fog_uncover_radius <= MAX_DISTANCE
+                && structure.credits_storage <= MAX_CREDITS
+                && structure.power_usage.unsigned_abs() <= MAX_POWER
+        })
+}
+
+/// Offset of the unit table in the data of a `DUNE2.EXE` file, the offset
+/// depends on the version of the executable.
+pub fn locate_unit_table(
+    data: &[u8],
+) -> Result<usize> {
+    locate_table(
+        data,
+        known_table_offsets(data, |executable| executable.unit_table_offset),
+        UNIT_INFO_SIZE,
+        UNIT_NAMES.len(),
+        is_unit_record,
+    )
+}
+
+/// Offset of the structure table in the data of a `DUNE2.EXE` file, the
+/// offset depends on the version of the executable.
+pub fn locate_structure_table(
+    data: &[u8],
+) -> Result<usize> {
+    locate_table(
+        data,
+        known_table_offsets(data, |executable| executable.structure_table_offset),
+        STRUCTURE_INFO_SIZE,
+        STRUCTURE_NAMES.len(),
+        is_structure_record,
+    )
+}
+
+fn read_units(
+    data: &[u8],
+    table_offset: Option<usize>,
+) -> Result<Vec<UnitInfo>> {
+    let table_offset = match table_offset {
+        Some(table_offset) => table_offset,
+        None => locate_unit_table(data)?,
+    };
+
+    records(data, table_offset, UNIT_INFO_SIZE, UNIT_NAMES.len())?
+        .zip(UNIT_NAMES.iter())
+        .map(|(mut reader, name)| read_unit_info(name, &mut reader))
+        .collect()
+}
+
+fn read_structures(
+    data: &[u8],
+    table_offset: Option<usize>,
+) -> Result<Vec<StructureInfo>> {
+    let table_offset = match table_offset {
+        Some(table_offset) => table_offset,
+        None => locate_structure_table(data)?,
+    };
+
+    records(data, table_offset, STRUCTURE_INFO_SIZE, STRUCTURE_NAMES.len())?
+        .zip(STRUCTURE_NAMES.iter())
+        .map(|(mut reader, name)| read_structure_info(name, &mut reader))
+        .collect()
+}
+
+/// Read the unit table of a `DUNE2.EXE` file. The table is located in the
+/// executable unless `table_offset` is given.
+pub fn read_units_from_file<P>(
+    path: P,
+    table_offset: Option<usize>,
+) -> Result<Vec<UnitInfo>> where P: AsRef<path::Path> {
+    read_units(&read_executable(path.as_ref())?, table_offset)
+}
+
+/// Read the structure table of a `DUNE2.EXE` file. The table is located in
+/// the executable unless `table_offset` is given.
+pub fn read_structures_from_file<P>(
+    path: P,
+    table_offset: Option<usize>,
+) -> Result<Vec<StructureInfo>> where P: AsRef<path::Path> {
+    read_structures(&read_executable(path.as_ref())?, table_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNIT_TABLE_OFFSET: usize = 0x1234;
+    const STRUCTURE_TABLE_OFFSET: usize = 0x2345;
+
+    fn write_u16(
+        data: &mut [u8],
+        offset: usize,
+        value: u16,
+    ) {
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_table(
+        data: &mut [u8],
+        table_offset: usize,
+        record_size: usize,
+        record_count: usize,
+        name_offset: u16,
+    ) {
+        for index in 0..record_count {
+            let record = table_offset + index*record_size;
+
+            data[record..record + record_size].fill(0);
+            write_u16(data, record + 2, name_offset + 16*index as u16);
+            write_u16(data, record + 4, 0x2b1e);
+            write_u16(data, record + 16, 100 + index as u16); // hitpoints
+        }
+    }
+
+    // Pseudo random filler standing for the code and the other data
+    fn executable() -> Vec<u8> {
+        let mut state = 0x12345678u32;
+        let mut data = (0..0x4000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect::<Vec<u8>>();
+
+        data[0..2].copy_from_slice(b"MZ");
+        write_table(&mut data, UNIT_TABLE_OFFSET, UNIT_INFO_SIZE, UNIT_NAMES.len(), 0x100);
+        write_table(&mut data, STRUCTURE_TABLE_OFFSET, STRUCTURE_INFO_SIZE, STRUCTURE_NAMES.len(), 0x400);
+        data
+    }
+
+    #[test]
+    fn tables_are_located() {
+        let data = executable();
+
+        assert_eq!(locate_unit_table(&data).unwrap(), UNIT_TABLE_OFFSET);
+        assert_eq!(locate_structure_table(&data).unwrap(), STRUCTURE_TABLE_OFFSET);
+
+        let units = read_units(&data, None).unwrap();
+        let structures = read_structures(&data, None).unwrap();
+
+        assert_eq!(units[1].hitpoints, 101);
+        assert_eq!(structures[2].hitpoints, 102);
+    }
+
+    #[test]
+    fn offsets_override_the_detection() {
+        let mut data = executable();
+
+        write_u16(&mut data, UNIT_TABLE_OFFSET + 16, 1000);
+        data.copy_within(UNIT_TABLE_OFFSET..UNIT_TABLE_OFFSET + 2*UNIT_INFO_SIZE, 0x20);
+
+        let units = read_units(&data, Some(0x20)).unwrap();
+
+        assert_eq!(units[0].hitpoints, 1000);
+    }
+
+    #[test]
+    fn unknown_executables_are_rejected() {
+        let mut data = executable();
+
+        // Break the name pointer segment of one record
+        write_u16(&mut data, STRUCTURE_TABLE_OFFSET + 5*STRUCTURE_INFO_SIZE + 4, 0);
+
+        assert!(read_structures(&data, None).is_err());
+    }
+
+    #[test]
+    fn ambiguous_tables_are_rejected() {
+        let mut data = executable();
+
+        data.extend_from_within(UNIT_TABLE_OFFSET..UNIT_TABLE_OFFSET + UNIT_INFO_SIZE*UNIT_NAMES.len());
+
+        assert!(locate_unit_table(&data).is_err());
+    }
+
+    #[test]
+    fn known_offsets_come_first() {
+        let mut data = executable();
+        let table_size = UNIT_INFO_SIZE*UNIT_NAMES.len();
+
+        data.extend_from_within(UNIT_TABLE_OFFSET..UNIT_TABLE_OFFSET + table_size);
+
+        let copy_offset = data.len() - table_size;
+        let locate = |known_offsets: &[usize]| locate_table(
+            &data,
+            known_offsets.iter().copied(),
+            UNIT_INFO_SIZE,
+            UNIT_NAMES.len(),
+            is_unit_record,
+        );
+
+        // The known offset disambiguates the two copies of the table
+        assert_eq!(locate(&[copy_offset]).unwrap(), copy_offset);
+        assert_eq!(locate(&[usize::MAX, 0x10, UNIT_TABLE_OFFSET]).unwrap(), UNIT_TABLE_OFFSET);
+        // Wrong known offsets fall back to the scan
+        assert!(locate(&[0x10]).is_err());
+    }
+
+    #[test]
+    fn out_of_range_records_are_rejected() {
+        let mut data = executable();
+
+        assert!(is_unit_record(&data[UNIT_TABLE_OFFSET..UNIT_TABLE_OFFSET + UNIT_INFO_SIZE]));
+
+        // Hitpoints, fire distance and power usage out of the game ranges
+        write_u16(&mut data, UNIT_TABLE_OFFSET + 16, 60000);
+        write_u16(&mut data, UNIT_TABLE_OFFSET + UNIT_INFO_SIZE + 77, 300);
+        write_u16(&mut data, STRUCTURE_TABLE_OFFSET + 56, (-5000i16) as u16);
+
+        assert!(!is_unit_record(&data[UNIT_TABLE_OFFSET..UNIT_TABLE_OFFSET + UNIT_INFO_SIZE]));
+        assert!(!is_unit_record(&data[UNIT_TABLE_OFFSET + UNIT_INFO_SIZE..]));
+        assert!(!is_structure_record(&data[STRUCTURE_TABLE_OFFSET..]));
+        assert!(locate_unit_table(&data).is_err());
+        assert!(locate_structure_table(&data).is_err());
+    }
+
+    #[test]
+    fn table_offsets_do_not_overflow() {
+        let data = executable();
+
+        assert!(read_units(&data, Some(usize::MAX - 10)).is_err());
+        assert!(read_structures(&data, Some(usize::MAX)).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::{
+    Assets,
+    Dune2Faction,
+};
+
+use crate::wav;
+
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Faction to export. If not specified all voiced factions are exported.
+    #[arg(short = 'F', long)]
+    pub faction: Option<super::cli_config::ArgExtractDune2Faction>,
+
+    /// Sample rate of the rendered announcements.
+    #[arg(long, default_value = "22050")]
+    pub sample_rate: u32,
+
+    /// Silence inserted between the fragments, in seconds.
+    #[arg(long, default_value = "0")]
+    pub gap: f32,
+
+    /// Overwrite existing files.
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
+    pub force_overwrite: bool,
+
+    /// Output folder path.
+    #[arg(short = 'd', long)]
+    pub output_dir: Option<PathBuf>,
+}
+
+pub fn extract(
+    rc: &Assets,
+    args: &Args,
+) -> Result<()> {
+    let output_dir = args.output_dir.clone().unwrap_or(PathBuf::from_str("announcements")?);
+    let factions = match args.faction {
+        Some(faction) => vec![faction.into()],
+        None => vec![
+            Dune2Faction::Atreides,
+            Dune2Faction::Harkonnen,
+            Dune2Faction::Ordos,
+        ],
+    };
+
+    for faction in factions {
+        let faction_dir = output_dir.join(format!("{faction:?}").to_lowercase());
+
+        fs::create_dir_all(&faction_dir)?;
+
+        for announcement in rc.announcements.iter() {
+            let output_filepath = faction_dir.join(format!("{}.wav", announcement.id));
+
+            if output_filepath.exists() && !args.force_overwrite {
+                return Err(anyhow!(
+                    "Output file already exists. Use --force-overwrite to overwrite."
+                ));
+            }
+
+            let sound = rc.announce(
+                faction,
+                &announcement.fragments,
+                args.sample_rate,
+                args.gap,
+            )?;
+            let mut output = BufWriter::new(fs::File::create(output_filepath)?);
+
+            wav::write_pcm16_mono(&mut output, sound.sample_rate(), sound.samples())?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use dune2_assets::constants;
+use dune2_assets::prelude::ScaleFilter;
 
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
@@ -23,3 +24,26 @@ impl Into<constants::Dune2Faction> for ArgExtractDune2Faction {
         }
     }
 }
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum ArgScaleFilter {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Scale4x,
+    Epx,
+    Xbr,
+}
+
+impl From<ArgScaleFilter> for ScaleFilter {
+    fn from(filter: ArgScaleFilter) -> Self {
+        match filter {
+            ArgScaleFilter::Nearest => Self::Nearest,
+            ArgScaleFilter::Scale2x => Self::Scale2x,
+            ArgScaleFilter::Scale3x => Self::Scale3x,
+            ArgScaleFilter::Scale4x => Self::Scale4x,
+            ArgScaleFilter::Epx => Self::EPX,
+            ArgScaleFilter::Xbr => Self::XBR,
+        }
+    }
+}
@@ -1,4 +1,6 @@
+mod announcements;
 mod cli_config;
+mod music;
 mod palette;
 mod tilemaps;
 mod tilesets;
@@ -13,6 +15,10 @@ use dune2_assets::prelude::Assets;
 
 #[derive(clap::Subcommand)]
 pub enum Commands {
+    /// Render voice announcements as WAV files
+    Announcements(announcements::Args),
+    /// Extract musics as Standard MIDI Files or rendered AdLib WAV files
+    Music(music::Args),
     /// Extract palette
     Palette(palette::Args),
     /// Extract tilesets
@@ -35,6 +41,8 @@ pub fn run(args: &Args) -> Result<()> {
     let rc = Assets::read_from(&mut reader)?;
 
     match &args.command {
+        Commands::Announcements(args) => announcements::extract(&rc, args),
+        Commands::Music(args) => music::extract(&rc, args),
         Commands::Palette(args) => palette::extract(&rc, args),
         Commands::Tilemaps(args) => tilemaps::extract(&rc, args),
         Commands::Tilesets(args) => tilesets::extract(&rc, args),
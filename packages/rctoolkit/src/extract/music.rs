@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{
+    BufWriter,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::{
+    Assets,
+    Music,
+    MusicFormat,
+};
+
+use crate::adl;
+use crate::wav;
+
+
+#[derive(clap::Args)]
+pub struct Args {
+    /// Music id to extract. If not specified all musics will be extracted.
+    pub music_id: Option<String>,
+
+    /// Render AdLib musics to WAV files instead of extracting MIDI files.
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
+    pub adlib: bool,
+
+    /// Sample rate of the rendered AdLib musics.
+    #[arg(long, default_value = "44100")]
+    pub sample_rate: u32,
+
+    /// Maximum duration in seconds of the rendered AdLib subsongs.
+    #[arg(long, default_value = "300")]
+    pub max_duration: u32,
+
+    /// Overwrite existing files.
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
+    pub force_overwrite: bool,
+
+    /// Output folder path.
+    #[arg(short = 'd', long)]
+    pub output_dir: Option<PathBuf>,
+}
+
+fn index_width(count: usize) -> usize {
+    if count > 0 {
+        f32::log10(count as f32) as usize + 1
+    } else {
+        1
+    }
+}
+
+fn check_output_filepath(
+    output_filepath: &Path,
+    force_overwrite: bool,
+) -> Result<()> {
+    if output_filepath.exists() && !force_overwrite {
+        return Err(anyhow!(
+            "Output file already exists. Use --force-overwrite to overwrite."
+        ));
+    }
+    Ok(())
+}
+
+fn extract_midi_music(
+    music: &Music,
+    force_overwrite: bool,
+    output_dir: &Path,
+) -> Result<()> {
+    let sequence_index_width = index_width(music.sequence_count());
+
+    for (sequence_index, sequence) in music.sequence_iter().enumerate() {
+        let filename = format!("{:01$}.mid", sequence_index, sequence_index_width);
+        let output_filepath = output_dir.join(filename);
+
+        check_output_filepath(&output_filepath, force_overwrite)?;
+        fs::write(output_filepath, sequence)?;
+    }
+
+    Ok(())
+}
+
+fn extract_adlib_music(
+    music: &Music,
+    args: &Args,
+    output_dir: &Path,
+) -> Result<()> {
+    for sequence in music.sequence_iter() {
+        let subsongs = adl::render_subsongs(
+            sequence,
+            args.sample_rate,
+            args.max_duration,
+        )?;
+        let subsong_index_width = subsongs
+            .last()
+            .map(|subsong| index_width(subsong.index + 1))
+            .unwrap_or(1);
+
+        for subsong in subsongs.iter() {
+            let filename = format!("{:01$}.wav", subsong.index, subsong_index_width);
+            let output_filepath = output_dir.join(filename);
+
+            check_output_filepath(&output_filepath, args.force_overwrite)?;
+
+            let mut output = BufWriter::new(fs::File::create(output_filepath)?);
+
+            wav::write_pcm16_mono(&mut output, args.sample_rate, &subsong.samples)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_music(
+    rc: &Assets,
+    music_id: &str,
+    args: &Args,
+    base_output_dir: &Path,
+) -> Result<()> {
+    let output_dir = base_output_dir.join(music_id);
+
+    let music = rc.get_music(music_id)?;
+
+    match (music.format(), args.adlib) {
+        (MusicFormat::Midi, false) => {
+            fs::create_dir_all(&output_dir)?;
+            extract_midi_music(music, args.force_overwrite, &output_dir)
+        },
+        (MusicFormat::Adlib, true) => {
+            fs::create_dir_all(&output_dir)?;
+            extract_adlib_music(music, args, &output_dir)
+        },
+        (MusicFormat::Midi, true) => Err(anyhow!(
+            "Music '{music_id}' is not an AdLib music."
+        )),
+        (MusicFormat::Adlib, false) => Err(anyhow!(
+            "Music '{music_id}' is an AdLib music. Use --adlib to render it."
+        )),
+    }
+}
+
+pub fn extract(
+    rc: &Assets,
+    args: &Args,
+) -> Result<()> {
+    let base_output_dir = if let Some(dir) = args.output_dir.as_ref() {
+        PathBuf::clone(dir)
+    } else {
+        PathBuf::from_str("musics")?
+    };
+
+    if let Some(music_id) = &args.music_id {
+        extract_music(rc, music_id, args, &base_output_dir)?;
+    } else {
+        let format = if args.adlib {
+            MusicFormat::Adlib
+        } else {
+            MusicFormat::Midi
+        };
+
+        for music in rc.musics.values().filter(|music| music.format() == format) {
+            extract_music(rc, music.get_id(), args, &base_output_dir)?;
+        }
+    }
+
+    Ok(())
+}
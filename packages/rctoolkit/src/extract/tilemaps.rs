@@ -1,14 +1,18 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::str::FromStr;
 
 use anyhow::Result;
 
 use dune2_assets::prelude::{
     bitmap_blit,
+    BlitSizePolicy,
     Color,
     Bitmap,
     Assets,
+    ScaleFilter,
     TilemapBitmap,
 };
 
@@ -21,9 +25,19 @@ pub struct Args {
     #[arg(short = 'b', long, value_parser = clap::value_parser!(Color), default_value = "black")]
     pub background_color: Color,
 
-    /// Faction to export.
-    #[arg(short = 'F', long)]
-    pub faction: Option<super::cli_config::ArgExtractDune2Faction>,
+    /// Faction to export. FACTION can be any remap table id, built-in
+    /// factions ids are their lowercase names.
+    #[arg(short = 'F', long, conflicts_with = "team_color")]
+    pub faction: Option<String>,
+
+    /// Team color replacing the faction colors. TEAM_COLOR can be any valid
+    /// css color string.
+    #[arg(long, value_parser = clap::value_parser!(Color))]
+    pub team_color: Option<Color>,
+
+    /// Pixel-art scaling filter, applied before the scale factor
+    #[arg(long, value_enum, default_value = "nearest")]
+    pub filter: super::cli_config::ArgScaleFilter,
 
     /// Scale factor
     #[arg(short = 's', long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
@@ -42,15 +56,33 @@ pub fn extract(
     rc: &Assets,
     args: &Args,
 ) -> Result<()> {
-    let faction = args.faction.map(|f| f.into());
+    let palette = Rc::new(super::tilesets::faction_palette(
+        rc,
+        args.faction.as_deref(),
+        args.team_color.as_ref(),
+        0,
+    )?);
+    let filter: ScaleFilter = args.filter.into();
     let output_dir = args.output_dir.clone().unwrap_or(PathBuf::from_str("tilemaps")?);
 
     fs::create_dir_all(&output_dir)?;
 
+    // Tilesets are scaled once, however many tilemaps use them
+    let mut tilesets = HashMap::new();
+
+    for tilemap in rc.tilemaps.iter() {
+        if !tilesets.contains_key(&tilemap.tileset) {
+            let tileset = filter.apply_tileset(rc.get_tileset(&tilemap.tileset)?, &rc.palette)?;
+
+            tilesets.insert(tilemap.tileset.clone(), tileset);
+        }
+    }
+
     for (i, tilemap) in rc.tilemaps.iter().enumerate() {
         let output_filepath = output_dir.join(format!("{:03}.bmp", i));
 
-        let bitmap = TilemapBitmap::try_with_assets(tilemap, faction, rc)?;
+        let tileset = &tilesets[&tilemap.tileset];
+        let bitmap = TilemapBitmap::try_with_tileset(tilemap, tileset, palette.clone())?;
         let src_rect = bitmap.rect();
 
         let mut image = BMPImageBuilder::new(
@@ -58,7 +90,7 @@ pub fn extract(
         ).with_background_color(args.background_color).build();
         let dst_rect = image.rect();
 
-        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect);
+        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect, BlitSizePolicy::Stretch);
         image.save(output_filepath)?;
     }
 
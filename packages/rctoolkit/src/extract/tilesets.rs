@@ -3,23 +3,36 @@ use std::path::{
     Path,
     PathBuf,
 };
+use std::rc::Rc;
 use std::str::FromStr;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use image::{Delay, DynamicImage, Frame};
+use image::codecs::gif::{GifEncoder, Repeat};
 
 use dune2_assets::prelude::{
     bitmap_blit,
+    BlitSizePolicy,
     Bitmap,
     Color,
-    Dune2Faction,
+    FactionPalette,
+    palette_cycles_length,
+    palette_cycles_step,
+    PALETTE_CYCLE_TICK_RATE,
     Point,
     Assets,
     Rect,
+    ScaleFilter,
     Size,
     TileBitmap,
+    Tileset,
 };
 
-use crate::image::BMPImageBuilder;
+use crate::image::{BMPImage, BMPImageBuilder};
+
+
+const GIF_MAX_FRAMES: u32 = 1024;
 
 
 #[derive(clap::Args)]
@@ -31,9 +44,19 @@ pub struct Args {
     #[arg(short = 'b', long, value_parser = clap::value_parser!(Color), default_value = "black")]
     pub background_color: Color,
 
-    /// Faction to export.
-    #[arg(short = 'F', long)]
-    pub faction: Option<super::cli_config::ArgExtractDune2Faction>,
+    /// Faction to export. FACTION can be any remap table id, built-in
+    /// factions ids are their lowercase names.
+    #[arg(short = 'F', long, conflicts_with = "team_color")]
+    pub faction: Option<String>,
+
+    /// Team color replacing the faction colors. TEAM_COLOR can be any valid
+    /// css color string.
+    #[arg(long, value_parser = clap::value_parser!(Color))]
+    pub team_color: Option<Color>,
+
+    /// Pixel-art scaling filter, applied before the scale factor.
+    #[arg(long, value_enum, default_value = "nearest")]
+    pub filter: super::cli_config::ArgScaleFilter,
 
     /// Scale factor.
     #[arg(short = 's', long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
@@ -43,6 +66,10 @@ pub struct Args {
     #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
     pub multiple: bool,
 
+    /// Export an animated GIF of the palette cycles.
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue, conflicts_with = "multiple")]
+    pub gif: bool,
+
     /// Overwrite existing files.
     #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
     pub force_overwrite: bool,
@@ -52,23 +79,34 @@ pub struct Args {
     pub output_dir: Option<PathBuf>,
 }
 
-fn extract_tileset_image(
+/// Palette of the given team color or faction at the given palette cycles
+/// tick, the faction must have a remap table.
+pub fn faction_palette(
     rc: &Assets,
-    tileset_id: &str,
-    faction: Option<Dune2Faction>,
+    faction_id: Option<&str>,
+    team_color: Option<&Color>,
+    tick: u32,
+) -> Result<FactionPalette> {
+    if let Some(team_color) = team_color {
+        return Ok(FactionPalette::with_team_color(&rc.palette_at(tick)?, team_color));
+    }
+    if let Some(faction_id) = faction_id {
+        rc.get_remap_table(faction_id)?;
+    }
+    Ok(rc.faction_palette_at(faction_id, tick)?)
+}
+
+fn render_tileset_image(
+    tileset: &Tileset,
+    palette: &Rc<FactionPalette>,
     scale: u32,
     background_color: Color,
-    base_output_dir: &Path,
-) -> Result<()> {
-    let output_file = base_output_dir.join(format!("{tileset_id}.bmp"));
-
-    let tileset = rc.get_tileset(tileset_id)?;
-
+) -> BMPImage {
     let tile_count = tileset.tile_count();
     let tile_size = tileset.tile_size();
 
     let cols = 16;
-    let rows = if tile_count%16 == 0 {
+    let rows = if tile_count.is_multiple_of(16) {
         tile_count/16
     } else {
         tile_count/16 + 1
@@ -87,7 +125,7 @@ fn extract_tileset_image(
         let col = (index%16) as i32;
         let row = (index/16) as i32;
 
-        let bitmap = TileBitmap::with_assets(tile, faction, rc);
+        let bitmap = TileBitmap::with_faction_palette(tile, palette.clone());
         let src_rect = bitmap.rect();
 
         let dst_rect = Rect::from_point_and_size(
@@ -101,25 +139,82 @@ fn extract_tileset_image(
             },
         );
 
-        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect);
+        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect, BlitSizePolicy::Stretch);
     }
 
+    image
+}
+
+fn extract_tileset_image(
+    tileset: &Tileset,
+    palette: &Rc<FactionPalette>,
+    scale: u32,
+    background_color: Color,
+    base_output_dir: &Path,
+) -> Result<()> {
+    let output_file = base_output_dir.join(format!("{}.bmp", tileset.get_id()));
+    let image = render_tileset_image(tileset, palette, scale, background_color);
+
     image.save(output_file)?;
 
     Ok(())
 }
 
-fn extract_tileset_tiles(
+fn extract_tileset_gif(
     rc: &Assets,
-    tileset_id: &str,
-    faction: Option<Dune2Faction>,
+    args: &Args,
+    tileset: &Tileset,
+    base_output_dir: &Path,
+) -> Result<()> {
+    let output_file = base_output_dir.join(format!("{}.gif", tileset.get_id()));
+
+    let length = palette_cycles_length(&rc.palette_cycles)?;
+    let step = palette_cycles_step(&rc.palette_cycles);
+    let delay_ms = step
+        .checked_mul(1000)
+        .ok_or(anyhow!("Palette cycles step of {step} ticks is too long"))?;
+    let delay = Delay::from_numer_denom_ms(delay_ms, PALETTE_CYCLE_TICK_RATE);
+
+    if length/step > GIF_MAX_FRAMES {
+        return Err(anyhow!(
+            "Palette cycles animation is too long ({} frames)",
+            length/step,
+        ));
+    }
+
+    let mut encoder = GifEncoder::new(fs::File::create(output_file)?);
+
+    encoder.set_repeat(Repeat::Infinite)?;
+
+    for tick in (0..length).step_by(step as usize) {
+        let palette = Rc::new(faction_palette(
+            rc,
+            args.faction.as_deref(),
+            args.team_color.as_ref(),
+            tick,
+        )?);
+        let image = render_tileset_image(
+            tileset,
+            &palette,
+            args.scale,
+            args.background_color,
+        );
+        let buffer = DynamicImage::ImageRgb8(image.buffer).into_rgba8();
+
+        encoder.encode_frame(Frame::from_parts(buffer, 0, 0, delay))?;
+    }
+
+    Ok(())
+}
+
+fn extract_tileset_tiles(
+    tileset: &Tileset,
+    palette: &Rc<FactionPalette>,
     scale: u32,
     background_color: Color,
     base_output_dir: &Path,
 ) -> Result<()> {
-    let output_dir = base_output_dir.join(tileset_id);
-
-    let tileset = rc.get_tileset(tileset_id)?;
+    let output_dir = base_output_dir.join(tileset.get_id());
 
     let tile_count = tileset.tile_count();
     let tile_index_width = if tile_count > 0 {
@@ -133,7 +228,7 @@ fn extract_tileset_tiles(
     for (tile_index, tile) in tileset.tile_iter().enumerate() {
         let filename = format!("{:01$}.bmp", tile_index, tile_index_width);
 
-        let bitmap = TileBitmap::with_assets(tile, faction, rc);
+        let bitmap = TileBitmap::with_faction_palette(tile, palette.clone());
         let src_rect = bitmap.rect();
 
         let mut image = BMPImageBuilder::new(
@@ -141,7 +236,7 @@ fn extract_tileset_tiles(
         ).with_background_color(background_color).build();
         let dst_rect = image.rect();
 
-        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect);
+        bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect, BlitSizePolicy::Stretch);
         image.save(output_dir.join(filename))?;
     }
 
@@ -153,21 +248,30 @@ fn extract_tileset(
     args: &Args,
     tileset_id: &str,
 ) ->  Result<()> {
-    let faction = args.faction.map(|f| f.into());
+    let palette = Rc::new(faction_palette(
+        rc,
+        args.faction.as_deref(),
+        args.team_color.as_ref(),
+        0,
+    )?);
     let base_output_dir = if let Some(dir) = args.output_dir.as_ref() {
         PathBuf::clone(dir)
     } else {
         PathBuf::from_str("tilesets")?
     };
+    let filter: ScaleFilter = args.filter.into();
+    let tileset = filter.apply_tileset(rc.get_tileset(tileset_id)?, &rc.palette)?;
     let scale = args.scale;
     let background_color = args.background_color;
 
     fs::create_dir_all(&base_output_dir)?;
 
-    if args.multiple {
-        extract_tileset_tiles(rc, tileset_id, faction, scale, background_color, &base_output_dir)
+    if args.gif {
+        extract_tileset_gif(rc, args, &tileset, &base_output_dir)
+    } else if args.multiple {
+        extract_tileset_tiles(&tileset, &palette, scale, background_color, &base_output_dir)
     } else {
-        extract_tileset_image(rc, tileset_id, faction, scale, background_color, &base_output_dir)
+        extract_tileset_image(&tileset, &palette, scale, background_color, &base_output_dir)
     }
 }
 
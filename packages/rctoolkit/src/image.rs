@@ -3,6 +3,7 @@ use image::{Rgb, RgbImage};
 use dune2_assets::prelude::{
     Color,
     Bitmap,
+    BitmapGetPixel,
     BitmapPutPixel,
     Point,
     Size,
@@ -41,6 +42,22 @@ impl Bitmap for BMPImage {
     }
 }
 
+impl BitmapGetPixel for BMPImage {
+    fn get_pixel(&self, p: Point) -> Option<Color> {
+        if p.x < 0 || (p.x as u32) >= self.width() {
+            return None;
+        }
+
+        if p.y < 0 || (p.y as u32) >= self.height() {
+            return None;
+        }
+
+        let Rgb([red, green, blue]) = *self.buffer.get_pixel(p.x as u32, p.y as u32);
+
+        Some(Color { red, green, blue })
+    }
+}
+
 impl BitmapPutPixel for BMPImage {
     fn put_pixel(&mut self, p: Point, color: Option<Color>) -> &mut Self {
         if p.x < 0 || (p.x as u32) >= self.width() {
@@ -7,6 +7,8 @@ use anyhow::Result;
 use dune2_assets::prelude::{
     Color,
     Assets,
+    AssetsHeader,
+    LazyAssets,
 };
 
 
@@ -84,6 +86,10 @@ fn info_tilesets(
         println!("{}:", name);
         println!("  size: {}", tileset.tile_size(),);
         println!("  count: {}", tileset.tile_count());
+        println!("  layout: {:?}", tileset.layout());
+        if let Some(atlas_size) = tileset.atlas_size() {
+            println!("  atlas: {}", atlas_size);
+        }
     });
     Ok(())
 }
@@ -119,6 +125,106 @@ fn info_tilemaps(
 }
 
 
+/******************************************************************************
+ * Info Musics
+ *****************************************************************************/
+fn info_musics(
+    rc: &Assets,
+) -> Result<()> {
+    rc.musics.iter().for_each(|(name, music)| {
+        println!("{}:", name);
+        println!("  sequences: {}", music.sequence_count());
+    });
+    Ok(())
+}
+
+
+/******************************************************************************
+ * Info Units
+ *****************************************************************************/
+fn info_units(
+    rc: &Assets,
+) -> Result<()> {
+    rc.units.iter().enumerate().for_each(|(index, unit)| {
+        println!("- [{}] {}", index, unit.name);
+        println!("    hitpoints: {}", unit.hitpoints);
+        println!("    cost: {}", unit.build_credits);
+        println!("    build time: {}", unit.build_time);
+        println!("    speed: {}", unit.moving_speed);
+        println!("    range: {}", unit.fire_distance);
+        println!("    damage: {}", unit.damage);
+        println!("    sprites: {}, {}, {}", unit.sprite_id, unit.ground_sprite_id, unit.turret_sprite_id);
+    });
+    Ok(())
+}
+
+
+/******************************************************************************
+ * Info Structures
+ *****************************************************************************/
+fn info_structures(
+    rc: &Assets,
+) -> Result<()> {
+    rc.structures.iter().enumerate().for_each(|(index, structure)| {
+        println!("- [{}] {}", index, structure.name);
+        println!("    hitpoints: {}", structure.hitpoints);
+        println!("    cost: {}", structure.build_credits);
+        println!("    build time: {}", structure.build_time);
+        println!("    power: {}", structure.power_usage);
+        println!("    storage: {}", structure.credits_storage);
+        println!("    footprint: {}", structure.layout.size());
+        println!("    sprite: {}", structure.sprite_id);
+    });
+    Ok(())
+}
+
+
+/******************************************************************************
+ * Info Remap Tables
+ *****************************************************************************/
+fn info_remap_tables(
+    rc: &Assets,
+) -> Result<()> {
+    let mut remap_tables = rc.remap_tables.values().collect::<Vec<_>>();
+
+    remap_tables.sort_by(|a, b| a.id.cmp(&b.id));
+    remap_tables.iter().for_each(|remap_table| {
+        println!("{}:", remap_table.id);
+        remap_table.ranges.iter().for_each(|range| {
+            println!("  - {} -> {} ({})", range.source, range.target, range.count);
+        });
+    });
+    Ok(())
+}
+
+
+/******************************************************************************
+ * Info Format
+ *****************************************************************************/
+fn info_format(
+    input_rc_file: &PathBuf,
+) -> Result<()> {
+    let data = std::fs::read(input_rc_file)?;
+
+    match AssetsHeader::parse(&data)? {
+        Some(header) => {
+            println!("version: {}", header.version);
+            println!("compression: {:?}", header.compression);
+            println!("payload size: {}", header.payload_size);
+            println!("checksum: {:08x}", header.checksum);
+        },
+        None => println!("version: 0 (unversioned)"),
+    }
+
+    let assets = LazyAssets::from_bytes(data)?;
+
+    for entry in assets.chunks() {
+        println!(" - {:?}: {} bytes, checksum {:08x}", entry.chunk, entry.size, entry.checksum);
+    }
+    Ok(())
+}
+
+
 /******************************************************************************
  * Info run
  *****************************************************************************/
@@ -127,6 +233,12 @@ pub enum Commands {
     Palette(PaletteCommandArgs),
     Tilesets,
     Tilemaps,
+    Musics,
+    Units,
+    Structures,
+    RemapTables,
+    /// File format version, compression and chunks
+    Format,
 }
 
 #[derive(clap::Args)]
@@ -139,12 +251,19 @@ pub struct Args {
 }
 
 pub fn run(args: &Args) -> Result<()> {
-    let mut reader = File::open(&args.input_rc_file)?;
-    let rc: Assets = Assets::read_from(&mut reader)?;
+    let read_rc = || -> Result<Assets> {
+        let mut reader = File::open(&args.input_rc_file)?;
+        Ok(Assets::read_from(&mut reader)?)
+    };
 
     match &args.command {
-        Commands::Palette(args) => info_palette(&rc, &args),
-        Commands::Tilesets => info_tilesets(&rc),
-        Commands::Tilemaps => info_tilemaps(&rc),
+        Commands::Palette(args) => info_palette(&read_rc()?, &args),
+        Commands::Tilesets => info_tilesets(&read_rc()?),
+        Commands::Tilemaps => info_tilemaps(&read_rc()?),
+        Commands::Musics => info_musics(&read_rc()?),
+        Commands::Units => info_units(&read_rc()?),
+        Commands::Structures => info_structures(&read_rc()?),
+        Commands::RemapTables => info_remap_tables(&read_rc()?),
+        Commands::Format => info_format(&args.input_rc_file),
     }
 }
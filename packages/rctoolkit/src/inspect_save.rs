@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::{
+    bitmap_blit,
+    BlitSizePolicy,
+    default_remap_tables,
+    Assets,
+    Bitmap,
+    Color,
+    Savegame,
+    SavegameObject,
+    STRUCTURE_NAMES,
+    Tile,
+    TilePool,
+    TilemapBitmap,
+    Tileset,
+    UNIT_NAMES,
+    DEFAULT_TRANSPARENT_INDEX,
+};
+
+use crate::assets_config::*;
+use crate::icn;
+use crate::image::BMPImageBuilder;
+use crate::sav;
+
+
+const MAP_TILESET_ID: &str = "icons";
+
+
+/// Print the houses, units and structures of a savegame. The map render is
+/// limited to the tiles stored in the savegame, the landscape generated
+/// from the map seed is not rebuilt.
+#[derive(clap::Args)]
+pub struct Args {
+    /// Savegame file path
+    pub savegame_filepath: PathBuf,
+
+    /// Assets config file path. Required to render the map.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Render the map tiles stored in the savegame to this BMP file. This is
+    /// a diff view of the map: savegames only store the tiles which differ
+    /// from the landscape generated from the map seed, the other ones are
+    /// left to the background color.
+    #[arg(short = 'm', long, requires = "config")]
+    pub map_diff_output: Option<PathBuf>,
+
+    /// Background color. BACKGROUND_COLOR can be any valid css color string
+    #[arg(short = 'b', long, value_parser = clap::value_parser!(Color), default_value = "black")]
+    pub background_color: Color,
+
+    /// Scale factor
+    #[arg(short = 's', long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub scale: u32,
+
+    /// Overwrite existing files
+    #[arg(long, default_value = "false", action = clap::ArgAction::SetTrue)]
+    pub force_overwrite: bool,
+}
+
+fn faction_name(object: &SavegameObject) -> String {
+    object.faction()
+        .map(|faction| format!("{faction:?}"))
+        .unwrap_or_else(|_| format!("#{}", object.house))
+}
+
+fn kind_name(names: &[&str], kind: u8) -> String {
+    names.get(kind as usize)
+        .map(|name| String::from(*name))
+        .unwrap_or_else(|| format!("#{kind}"))
+}
+
+fn position_string(object: &SavegameObject) -> String {
+    let position = object.tile_position();
+    format!("({}, {})", position.x, position.y)
+}
+
+/******************************************************************************
+ * Print savegame
+ *****************************************************************************/
+
+fn print_savegame(
+    savegame: &Savegame,
+) {
+    println!("description: {}", savegame.description);
+    println!("version: {:#06x}", savegame.version);
+
+    println!("houses:");
+    for house in savegame.houses.iter() {
+        let name = house.faction()
+            .map(|faction| format!("{faction:?}"))
+            .unwrap_or_else(|_| format!("#{}", house.house));
+
+        println!("- {}{}", name, if house.is_human() { " (human)" } else { "" });
+        println!("    credits: {}/{}", house.credits, house.credits_storage);
+        println!("    quota: {}", house.credits_quota);
+        println!("    power: {}/{}", house.power_usage, house.power_production);
+        println!("    units: {}/{}", house.unit_count, house.unit_count_max);
+    }
+
+    println!("units:");
+    for unit in savegame.units.iter() {
+        let object = &unit.object;
+
+        println!("- [{}] {} {}", object.index, faction_name(object), kind_name(&UNIT_NAMES, object.kind));
+        println!("    position: {}", position_string(object));
+        println!("    hitpoints: {}", object.hitpoints);
+    }
+
+    println!("structures:");
+    for structure in savegame.structures.iter() {
+        let object = &structure.object;
+
+        println!("- [{}] {} {}", object.index, faction_name(object), kind_name(&STRUCTURE_NAMES, object.kind));
+        println!("    position: {}", position_string(object));
+        println!("    hitpoints: {}/{}", object.hitpoints, structure.hitpoints_max);
+        println!("    upgrade level: {}", structure.upgrade_level);
+    }
+
+    println!("map:");
+    // Tiles equal to the generated landscape are not saved
+    println!("  changed tiles: {}", savegame.tiles.len());
+}
+
+/******************************************************************************
+ * Render savegame map diff
+ *****************************************************************************/
+
+fn render_map_diff(
+    savegame: &Savegame,
+    config: &Config,
+    args: &Args,
+    output_filepath: &PathBuf,
+) -> Result<()> {
+    if output_filepath.exists() && !args.force_overwrite {
+        return Err(anyhow!(
+            "Output file already exists. Use --force-overwrite to overwrite."
+        ));
+    }
+
+    // Savegame ground tiles are indices in ICON.ICN
+    let icn_source = config.sources
+        .iter()
+        .find(|source| matches!(source.kind, SourceType::ICN))
+        .ok_or(anyhow!("No ICN source in config"))?;
+
+    let tiles = icn::read_tiles_from_file(&icn_source.path)?;
+    let tile_size = tiles
+        .first()
+        .map(|tile| tile.size())
+        .ok_or(anyhow!("No tiles in ICN source"))?;
+    let mut tileset = Tileset::new(MAP_TILESET_ID, tile_size);
+
+    for tile in tiles {
+        tileset.add(tile)?;
+    }
+
+    let palette = config.load_palette()?;
+
+    // Tiles missing from the savegame are the ones of the landscape, which
+    // is not rebuilt. They are drawn with a transparent tile.
+    let default_tile = tileset.tile_count();
+    let transparent_index = palette.transparent_index().unwrap_or(DEFAULT_TRANSPARENT_INDEX);
+
+    let tile_size = tileset.tile_size();
+    let pixel_count = (tile_size.width*tile_size.height) as usize;
+
+    tileset.add(Tile::new(&vec![transparent_index as u8; pixel_count], tile_size))?;
+
+    let assets = Assets {
+        palette,
+        tilesets: HashMap::from([(MAP_TILESET_ID.into(), tileset)]),
+        tilemaps: Vec::new(),
+        musics: HashMap::new(),
+        units: Vec::new(),
+        structures: Vec::new(),
+        sounds: HashMap::new(),
+        announcements: Vec::new(),
+        remap_tables: default_remap_tables(),
+        palette_cycles: Vec::new(),
+        tile_pool: TilePool::new(),
+    };
+
+    let tilemap = savegame.tilemap(MAP_TILESET_ID, default_tile);
+    let bitmap = TilemapBitmap::try_with_assets(&tilemap, None, &assets)?;
+    let src_rect = bitmap.rect();
+
+    let mut image = BMPImageBuilder::new(args.scale*bitmap.size())
+        .with_background_color(args.background_color)
+        .build();
+    let dst_rect = image.rect();
+
+    bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect, BlitSizePolicy::Stretch);
+    image.save(output_filepath)?;
+
+    println!(
+        "map diff: {} tiles stored in the savegame drawn, the generated landscape is not rebuilt",
+        savegame.tiles.len(),
+    );
+
+    Ok(())
+}
+
+/******************************************************************************
+ * Inspect savegame run
+ *****************************************************************************/
+
+pub fn run(args: &Args) -> Result<()> {
+    let savegame = sav::read_savegame_from_file(&args.savegame_filepath)?;
+
+    print_savegame(&savegame);
+
+    if let (Some(output_filepath), Some(config_filepath)) = (&args.map_diff_output, &args.config) {
+        let config = Config::try_read_from_file(config_filepath)?;
+        render_map_diff(&savegame, &config, args, output_filepath)?;
+    }
+
+    Ok(())
+}
@@ -1,14 +1,25 @@
+mod adl;
 mod assets_config;
 mod check;
+mod convert;
 mod create;
+mod dedupe;
+mod exe;
 mod extract;
 mod icn;
 mod image;
 mod info;
+mod inspect_save;
 mod io;
+mod opl;
 mod pal;
+mod sav;
+mod sheet;
 mod shp;
 mod source;
+mod voc;
+mod wav;
+mod xmi;
 
 
 use clap::{Parser, Subcommand};
@@ -16,10 +27,12 @@ use clap::{Parser, Subcommand};
 #[derive(Subcommand)]
 pub enum Commands {
     Check(check::Args),
+    Convert(convert::Args),
     Create(create::Args),
     Source(source::Args),
     Extract(extract::Args),
     Info(info::Args),
+    InspectSave(inspect_save::Args),
 }
 
 #[derive(Parser)]
@@ -35,10 +48,12 @@ fn main() {
 
     let res = match &args.command {
         Commands::Check(args) => check::run(args),
+        Commands::Convert(args) => convert::run(args),
         Commands::Create(args) => create::run(args),
         Commands::Source(args) => source::run(args),
         Commands::Extract(args) => extract::run(args),
         Commands::Info(args) => info::run(args),
+        Commands::InspectSave(args) => inspect_save::run(args),
     };
 
     if let Err(err) = res {
@@ -0,0 +1,553 @@
+use std::f64::consts::PI;
+
+// The YM3812 runs at 3.579545MHz and produces one sample every 72 clocks.
+const OPL_SAMPLE_RATE: f64 = 49716.0;
+
+// Operator output at full level, as a signed 13-bit value.
+const OPERATOR_MAX_OUTPUT: f64 = 4096.0;
+
+// Attack and decay times in seconds for an effective rate of 4 (rate 1
+// without key scaling). Each increment of the effective rate divides those
+// times by 2^(1/4).
+const ATTACK_TIME: f64 = 2.82624;
+const DECAY_TIME: f64 = 39.28064;
+
+// Attenuation in dB under which an operator is considered silent.
+const MAX_ATTENUATION: f64 = 96.0;
+
+const FREQUENCY_MULTIPLIERS: [f64; 16] = [
+    0.5, 1., 2., 3., 4., 5., 6., 7., 8., 9., 10., 10., 12., 12., 15., 15.,
+];
+
+// Key scale attenuation in dB for block 7 at 6dB/octave, indexed by the four
+// most significant bits of the F-Number.
+const KEY_SCALE_LEVELS: [f64; 16] = [
+     0.00, 18.00, 24.00, 27.75, 30.00, 32.25, 33.75, 35.25,
+    36.00, 37.50, 38.25, 39.00, 39.75, 40.50, 41.25, 42.00,
+];
+
+// Key scale level register values are 0, 3, 1.5 and 6dB/octave.
+const KEY_SCALE_FACTORS: [f64; 4] = [0.0, 0.5, 0.25, 1.0];
+
+const AM_LFO_FREQUENCY: f64 = 3.7;
+const VIB_LFO_FREQUENCY: f64 = 6.1;
+
+const RHYTHM_BASS_DRUM: u8 = 0x10;
+const RHYTHM_SNARE_DRUM: u8 = 0x08;
+const RHYTHM_TOM_TOM: u8 = 0x04;
+const RHYTHM_CYMBAL: u8 = 0x02;
+const RHYTHM_HI_HAT: u8 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Operator {
+    am: bool,
+    vib: bool,
+    sustained: bool,
+    ksr: bool,
+    mult: u8,
+    ksl: u8,
+    total_level: u8,
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8,
+    waveform: u8,
+
+    key: bool,
+    phase: f64,
+    attenuation: f64,
+    stage: EnvelopeStage,
+    outputs: [f64; 2],
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Channel {
+    f_number: u16,
+    block: u8,
+    key: bool,
+    feedback: u8,
+    additive: bool,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            am: false,
+            vib: false,
+            sustained: false,
+            ksr: false,
+            mult: 0,
+            ksl: 0,
+            total_level: 0,
+            attack_rate: 0,
+            decay_rate: 0,
+            sustain_level: 0,
+            release_rate: 0,
+            waveform: 0,
+
+            key: false,
+            phase: 0.,
+            attenuation: MAX_ATTENUATION,
+            stage: EnvelopeStage::Off,
+            outputs: [0.; 2],
+        }
+    }
+}
+
+impl Operator {
+    fn set_key(
+        &mut self,
+        key: bool,
+    ) {
+        if key && !self.key {
+            self.phase = 0.;
+            self.stage = EnvelopeStage::Attack;
+        } else if !key && self.key && self.stage != EnvelopeStage::Off {
+            self.stage = EnvelopeStage::Release;
+        }
+        self.key = key;
+    }
+
+    fn effective_rate(
+        &self,
+        rate: u8,
+        key_code: u8,
+    ) -> Option<f64> {
+        if rate == 0 {
+            return None;
+        }
+
+        let offset = if self.ksr { key_code } else { key_code >> 2 };
+
+        Some(u8::min(4*rate + offset, 63) as f64)
+    }
+
+    fn update_envelope(
+        &mut self,
+        key_code: u8,
+        dt: f64,
+    ) {
+        let sustain_level = if self.sustain_level == 15 {
+            93.
+        } else {
+            3.*(self.sustain_level as f64)
+        };
+
+        match self.stage {
+            EnvelopeStage::Attack => {
+                match self.effective_rate(self.attack_rate, key_code) {
+                    Some(rate) if rate >= 60. => {
+                        self.attenuation = 0.;
+                    },
+                    Some(rate) => {
+                        // The attack curve is exponential, it is steep at
+                        // high attenuations and slows down near full level.
+                        let time = ATTACK_TIME/f64::powf(2., (rate - 4.)/4.);
+                        let k = f64::ln(9.)/(8.*time);
+                        let a = self.attenuation/MAX_ATTENUATION;
+
+                        self.attenuation -= MAX_ATTENUATION*dt*k*(8.*a + 1.);
+                    },
+                    None => {},
+                }
+                if self.attenuation <= 0. {
+                    self.attenuation = 0.;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            },
+            EnvelopeStage::Decay => {
+                if let Some(rate) = self.effective_rate(self.decay_rate, key_code) {
+                    let time = DECAY_TIME/f64::powf(2., (rate - 4.)/4.);
+                    self.attenuation += MAX_ATTENUATION*dt/time;
+                }
+                if self.attenuation >= sustain_level {
+                    self.attenuation = sustain_level;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            },
+            EnvelopeStage::Sustain => {
+                // Percussive sounds go straight to release once the sustain
+                // level is reached.
+                if !self.sustained {
+                    self.stage = EnvelopeStage::Release;
+                }
+            },
+            EnvelopeStage::Release => {
+                if let Some(rate) = self.effective_rate(self.release_rate, key_code) {
+                    let time = DECAY_TIME/f64::powf(2., (rate - 4.)/4.);
+                    self.attenuation += MAX_ATTENUATION*dt/time;
+                }
+                if self.attenuation >= MAX_ATTENUATION {
+                    self.attenuation = MAX_ATTENUATION;
+                    self.stage = EnvelopeStage::Off;
+                }
+            },
+            EnvelopeStage::Off => {},
+        }
+    }
+
+    fn level(
+        &self,
+        channel: &Channel,
+        am_attenuation: f64,
+    ) -> f64 {
+        if self.stage == EnvelopeStage::Off {
+            return 0.;
+        }
+
+        let ksl = KEY_SCALE_LEVELS[(channel.f_number >> 6) as usize]
+            - 6.*(7 - channel.block) as f64;
+        let ksl = f64::max(ksl, 0.)*KEY_SCALE_FACTORS[self.ksl as usize];
+        let am = if self.am { am_attenuation } else { 0. };
+        let attenuation = self.attenuation
+            + 0.75*(self.total_level as f64)
+            + ksl
+            + am;
+
+        if attenuation >= MAX_ATTENUATION {
+            0.
+        } else {
+            f64::powf(10., -attenuation/20.)
+        }
+    }
+
+    fn wave(
+        &self,
+        phase: f64,
+        waveform_enabled: bool,
+    ) -> f64 {
+        let phase = phase - phase.floor();
+        let value = f64::sin(2.*PI*phase);
+
+        match if waveform_enabled { self.waveform } else { 0 } {
+            1 => f64::max(value, 0.),
+            2 => value.abs(),
+            3 if phase < 0.25 || (0.5..0.75).contains(&phase) => value.abs(),
+            3 => 0.,
+            _ => value,
+        }
+    }
+
+    fn advance(
+        &mut self,
+        frequency: f64,
+        dt: f64,
+    ) {
+        let increment = frequency*FREQUENCY_MULTIPLIERS[self.mult as usize]*dt;
+        self.phase = (self.phase + increment).fract();
+    }
+}
+
+/// Software emulation of a Yamaha YM3812 (OPL2) FM synthesis chip.
+pub struct OPL2 {
+    sample_rate: f64,
+    operators: [Operator; 18],
+    channels: [Channel; 9],
+    waveform_enabled: bool,
+    note_select: bool,
+    am_depth: bool,
+    vib_depth: bool,
+    rhythm: u8,
+    am_phase: f64,
+    vib_phase: f64,
+    noise: u32,
+}
+
+fn operator_index(
+    offset: u8,
+) -> Option<usize> {
+    let group = (offset >> 3) as usize;
+    let index = (offset & 0x07) as usize;
+
+    if group < 3 && index < 6 {
+        Some(6*group + index)
+    } else {
+        None
+    }
+}
+
+fn channel_operators(
+    channel: usize,
+) -> (usize, usize) {
+    let modulator = 6*(channel/3) + channel%3;
+    (modulator, modulator + 3)
+}
+
+impl OPL2 {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            operators: [Operator::default(); 18],
+            channels: [Channel::default(); 9],
+            waveform_enabled: false,
+            note_select: false,
+            am_depth: false,
+            vib_depth: false,
+            rhythm: 0,
+            am_phase: 0.,
+            vib_phase: 0.,
+            noise: 1,
+        }
+    }
+
+    fn rhythm_enabled(&self) -> bool {
+        self.rhythm & 0x20 != 0
+    }
+
+    fn update_channel_key(
+        &mut self,
+        channel: usize,
+    ) {
+        let (modulator, carrier) = channel_operators(channel);
+        let key = self.channels[channel].key;
+        let rhythm = if self.rhythm_enabled() { self.rhythm } else { 0 };
+
+        let (modulator_key, carrier_key) = match channel {
+            6 => (
+                key || rhythm & RHYTHM_BASS_DRUM != 0,
+                key || rhythm & RHYTHM_BASS_DRUM != 0,
+            ),
+            7 => (
+                key || rhythm & RHYTHM_HI_HAT != 0,
+                key || rhythm & RHYTHM_SNARE_DRUM != 0,
+            ),
+            8 => (
+                key || rhythm & RHYTHM_TOM_TOM != 0,
+                key || rhythm & RHYTHM_CYMBAL != 0,
+            ),
+            _ => (key, key),
+        };
+
+        self.operators[modulator].set_key(modulator_key);
+        self.operators[carrier].set_key(carrier_key);
+    }
+
+    /// Write a value to a chip register.
+    pub fn write(
+        &mut self,
+        register: u8,
+        value: u8,
+    ) {
+        match register {
+            0x01 => {
+                self.waveform_enabled = value & 0x20 != 0;
+            },
+            0x08 => {
+                self.note_select = value & 0x40 != 0;
+            },
+            0x20..=0x35 => if let Some(op) = operator_index(register - 0x20) {
+                let op = &mut self.operators[op];
+                op.am = value & 0x80 != 0;
+                op.vib = value & 0x40 != 0;
+                op.sustained = value & 0x20 != 0;
+                op.ksr = value & 0x10 != 0;
+                op.mult = value & 0x0f;
+            },
+            0x40..=0x55 => if let Some(op) = operator_index(register - 0x40) {
+                let op = &mut self.operators[op];
+                op.ksl = value >> 6;
+                op.total_level = value & 0x3f;
+            },
+            0x60..=0x75 => if let Some(op) = operator_index(register - 0x60) {
+                let op = &mut self.operators[op];
+                op.attack_rate = value >> 4;
+                op.decay_rate = value & 0x0f;
+            },
+            0x80..=0x95 => if let Some(op) = operator_index(register - 0x80) {
+                let op = &mut self.operators[op];
+                op.sustain_level = value >> 4;
+                op.release_rate = value & 0x0f;
+            },
+            0xa0..=0xa8 => {
+                let channel = &mut self.channels[(register - 0xa0) as usize];
+                channel.f_number = (channel.f_number & 0x300) | value as u16;
+            },
+            0xb0..=0xb8 => {
+                let index = (register - 0xb0) as usize;
+                let channel = &mut self.channels[index];
+                channel.f_number = (channel.f_number & 0xff) | (((value & 0x03) as u16) << 8);
+                channel.block = (value >> 2) & 0x07;
+                channel.key = value & 0x20 != 0;
+                self.update_channel_key(index);
+            },
+            0xbd => {
+                self.am_depth = value & 0x80 != 0;
+                self.vib_depth = value & 0x40 != 0;
+                self.rhythm = value & 0x3f;
+                for channel in 6..9 {
+                    self.update_channel_key(channel);
+                }
+            },
+            0xc0..=0xc8 => {
+                let channel = &mut self.channels[(register - 0xc0) as usize];
+                channel.feedback = (value >> 1) & 0x07;
+                channel.additive = value & 0x01 != 0;
+            },
+            0xe0..=0xf5 => if let Some(op) = operator_index(register - 0xe0) {
+                self.operators[op].waveform = value & 0x03;
+            },
+            _ => {},
+        }
+    }
+
+    fn key_code(
+        &self,
+        channel: &Channel,
+    ) -> u8 {
+        let bit = if self.note_select {
+            (channel.f_number >> 8) & 1
+        } else {
+            (channel.f_number >> 9) & 1
+        } as u8;
+
+        2*channel.block + bit
+    }
+
+    fn channel_frequency(
+        channel: &Channel,
+    ) -> f64 {
+        (channel.f_number as f64)
+            *f64::powf(2., channel.block as f64)
+            *OPL_SAMPLE_RATE/f64::powf(2., 20.)
+    }
+
+    fn step_noise(&mut self) -> bool {
+        if self.noise & 1 != 0 {
+            self.noise ^= 0x800302;
+        }
+        self.noise >>= 1;
+        self.noise & 1 != 0
+    }
+
+    fn melodic_channel_output(
+        &mut self,
+        channel_index: usize,
+        vib_factor: f64,
+        am_attenuation: f64,
+        dt: f64,
+    ) -> f64 {
+        let channel = self.channels[channel_index];
+        let key_code = self.key_code(&channel);
+        let frequency = OPL2::channel_frequency(&channel);
+        let (modulator, carrier) = channel_operators(channel_index);
+        let waveform_enabled = self.waveform_enabled;
+
+        let modulator_output = {
+            let op = &mut self.operators[modulator];
+            let feedback = if channel.feedback > 0 {
+                (op.outputs[0] + op.outputs[1])
+                    *f64::powf(2., channel.feedback as f64 - 7.)
+            } else { 0. };
+            let output = op.wave(op.phase + feedback, waveform_enabled)
+                *op.level(&channel, am_attenuation);
+
+            op.outputs = [op.outputs[1], output];
+            op.update_envelope(key_code, dt);
+            op.advance(frequency*if op.vib { vib_factor } else { 1. }, dt);
+            output
+        };
+
+        let carrier_output = {
+            let op = &mut self.operators[carrier];
+            // A full level modulator shifts the carrier phase by 4 cycles.
+            let modulation = if channel.additive { 0. } else { 4.*modulator_output };
+            let output = op.wave(op.phase + modulation, waveform_enabled)
+                *op.level(&channel, am_attenuation);
+
+            op.update_envelope(key_code, dt);
+            op.advance(frequency*if op.vib { vib_factor } else { 1. }, dt);
+            output
+        };
+
+        if channel.additive {
+            modulator_output + carrier_output
+        } else {
+            carrier_output
+        }
+    }
+
+    // Rhythm instruments are approximated: the snare drum, hi-hat and cymbal
+    // get their noisy timbre from the chip noise generator gating the
+    // operators waveforms.
+    fn rhythm_output(
+        &mut self,
+        vib_factor: f64,
+        am_attenuation: f64,
+        dt: f64,
+    ) -> f64 {
+        let bass_drum = self.melodic_channel_output(6, vib_factor, am_attenuation, dt);
+        let noise = self.step_noise();
+        let waveform_enabled = self.waveform_enabled;
+
+        let mut output = 2.*bass_drum;
+
+        for channel_index in [7, 8] {
+            let channel = self.channels[channel_index];
+            let key_code = self.key_code(&channel);
+            let frequency = OPL2::channel_frequency(&channel);
+            let (modulator, carrier) = channel_operators(channel_index);
+
+            for (op_index, gated) in [(modulator, channel_index == 7), (carrier, true)] {
+                let op = &mut self.operators[op_index];
+                let level = op.level(&channel, am_attenuation);
+                let value = if gated {
+                    let square = if op.phase < 0.5 { 1. } else { -1. };
+                    if noise { square } else { -0.5*square }
+                } else {
+                    op.wave(op.phase, waveform_enabled)
+                };
+
+                output += 2.*value*level;
+                op.update_envelope(key_code, dt);
+                op.advance(frequency*if op.vib { vib_factor } else { 1. }, dt);
+            }
+        }
+
+        output
+    }
+
+    /// Render samples at the configured sample rate.
+    pub fn generate(
+        &mut self,
+        output: &mut [i16],
+    ) {
+        let dt = 1./self.sample_rate;
+
+        for sample in output.iter_mut() {
+            let am_depth = if self.am_depth { 4.8 } else { 1. };
+            let vib_depth = if self.vib_depth { 14. } else { 7. };
+
+            let am_attenuation = am_depth*0.5*(1. - f64::cos(2.*PI*self.am_phase));
+            let vib_factor = f64::powf(
+                2.,
+                vib_depth*f64::sin(2.*PI*self.vib_phase)/1200.,
+            );
+
+            self.am_phase = (self.am_phase + AM_LFO_FREQUENCY*dt).fract();
+            self.vib_phase = (self.vib_phase + VIB_LFO_FREQUENCY*dt).fract();
+
+            let melodic_channels = if self.rhythm_enabled() { 6 } else { 9 };
+            let mut value = 0.;
+
+            for channel in 0..melodic_channels {
+                value += self.melodic_channel_output(channel, vib_factor, am_attenuation, dt);
+            }
+
+            if self.rhythm_enabled() {
+                value += self.rhythm_output(vib_factor, am_attenuation, dt);
+            }
+
+            *sample = (value*OPERATOR_MAX_OUTPUT)
+                .clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        }
+    }
+}
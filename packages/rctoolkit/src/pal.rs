@@ -7,6 +7,7 @@ use anyhow::{anyhow, Result};
 use dune2_assets::prelude::{
     Color,
     Palette,
+    PALETTE_SIZE,
 };
 
 
@@ -16,15 +17,20 @@ fn read_palette_from_reader(
     let mut palette = Palette::new();
     let mut buf = [0; 3];
 
-    loop {
+    for index in 0.. {
         let color = match reader.read(&mut buf)? {
             0 => break,
             3 => Color::from(&buf),
             _ => return Err(anyhow!("Invalid palette file")),
         };
+
+        if index >= PALETTE_SIZE {
+            return Err(anyhow!("Invalid palette file: more than {PALETTE_SIZE} colors"));
+        }
+
         // We have to multiply each channel by 4 because the palette is 6
         // bits per channel
-        palette.push(&(4*color));
+        palette.set_color(index, &(4*color))?;
     }
 
     Ok(palette)
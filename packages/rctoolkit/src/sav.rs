@@ -0,0 +1,391 @@
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::{
+    Point,
+    Savegame,
+    SavegameHouse,
+    SavegameObject,
+    SavegameStructure,
+    SavegameTile,
+    SavegameUnit,
+};
+
+use crate::io::*;
+
+
+const SAVEGAME_VERSION: u16 = 0x0290;
+
+// Size of the entries of the PLYR, UNIT, BLDG and MAP chunks
+const HOUSE_ENTRY_SIZE: usize = 66;
+const UNIT_ENTRY_SIZE: usize = 126;
+const STRUCTURE_ENTRY_SIZE: usize = 88;
+const TILE_ENTRY_SIZE: usize = 6;
+
+struct IFFChunk {
+    id: [u8; 4],
+    size: u64,
+}
+
+impl IFFChunk {
+    fn read_from(
+        reader: &mut impl Read,
+    ) -> Result<IFFChunk> {
+        let mut id = [0; 4];
+
+        reader.read_exact(&mut id)?;
+
+        let size = u32::try_read_from::<MSB>(reader)? as u64;
+
+        Ok(Self { id, size })
+    }
+
+    fn padded_size(&self) -> u64 {
+        self.size + (self.size & 1)
+    }
+}
+
+fn skip(
+    reader: &mut Cursor<&[u8]>,
+    count: i64,
+) -> Result<()> {
+    reader.seek(SeekFrom::Current(count))?;
+    Ok(())
+}
+
+fn entries<'a>(
+    chunk_id: &str,
+    data: &'a [u8],
+    entry_size: usize,
+) -> Result<impl Iterator<Item = Cursor<&'a [u8]>>> {
+    if !data.len().is_multiple_of(entry_size) {
+        return Err(anyhow!("SAV: invalid {chunk_id} chunk size"));
+    }
+    Ok(data.chunks_exact(entry_size).map(Cursor::new))
+}
+
+/******************************************************************************
+ * Chunks
+ *****************************************************************************/
+
+fn read_name(
+    data: &[u8],
+) -> String {
+    let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into()
+}
+
+fn read_house(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<SavegameHouse> {
+    let house = u16::try_read_from::<LSB>(reader)?;
+    skip(reader, 2)?; // harvesters incoming
+    let flags = u16::try_read_from::<LSB>(reader)?;
+    let unit_count = u16::try_read_from::<LSB>(reader)?;
+    let unit_count_max = u16::try_read_from::<LSB>(reader)?;
+    skip(reader, 8)?; // enemy and allied unit counts, structures built
+    let credits = u16::try_read_from::<LSB>(reader)?;
+    let credits_storage = u16::try_read_from::<LSB>(reader)?;
+    let power_production = u16::try_read_from::<LSB>(reader)?;
+    let power_usage = u16::try_read_from::<LSB>(reader)?;
+    skip(reader, 2)?; // windtrap count
+    let credits_quota = u16::try_read_from::<LSB>(reader)?;
+
+    Ok(SavegameHouse {
+        house,
+        flags,
+        credits,
+        credits_storage,
+        credits_quota,
+        power_production,
+        power_usage,
+        unit_count,
+        unit_count_max,
+    })
+}
+
+fn read_object(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<SavegameObject> {
+    let index = u16::try_read_from::<LSB>(reader)?;
+    let kind = u8::try_read_from::<LSB>(reader)?;
+    skip(reader, 1)?; // linked object
+    let flags = u32::try_read_from::<LSB>(reader)?;
+    let house = u8::try_read_from::<LSB>(reader)?;
+    skip(reader, 1)?; // seen by houses
+    let x = u16::try_read_from::<LSB>(reader)? as i32;
+    let y = u16::try_read_from::<LSB>(reader)? as i32;
+    let hitpoints = u16::try_read_from::<LSB>(reader)?;
+
+    // The object script state ends the common fields
+    skip(reader, 55)?;
+
+    Ok(SavegameObject {
+        index,
+        kind,
+        flags,
+        house,
+        position: Point { x, y },
+        hitpoints,
+    })
+}
+
+fn read_unit(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<SavegameUnit> {
+    let object = read_object(reader)?;
+
+    Ok(SavegameUnit { object })
+}
+
+fn read_structure(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<SavegameStructure> {
+    let object = read_object(reader)?;
+
+    // creator house, rotation sprite, unused byte and structure type
+    skip(reader, 7)?;
+    let upgrade_level = u8::try_read_from::<LSB>(reader)?;
+    // upgrade time, countdown, build cost remainder and state
+    skip(reader, 7)?;
+    let hitpoints_max = u16::try_read_from::<LSB>(reader)?;
+
+    Ok(SavegameStructure {
+        object,
+        upgrade_level,
+        hitpoints_max,
+    })
+}
+
+fn read_tile(
+    reader: &mut Cursor<&[u8]>,
+) -> Result<SavegameTile> {
+    let index = u16::try_read_from::<LSB>(reader)?;
+    let bits = u32::try_read_from::<LSB>(reader)?;
+
+    Ok(SavegameTile {
+        index,
+        ground_tile: (bits & 0x01ff) as u16,
+        overlay_tile: ((bits >> 9) & 0x7f) as u8,
+        house: ((bits >> 16) & 0x07) as u8,
+        is_unveiled: bits & (1 << 19) != 0,
+        has_unit: bits & (1 << 20) != 0,
+        has_structure: bits & (1 << 21) != 0,
+    })
+}
+
+/******************************************************************************
+ * Savegame
+ *****************************************************************************/
+
+fn read_savegame_from_data(
+    data: &[u8],
+) -> Result<Savegame> {
+    let mut reader = Cursor::new(data);
+    let mut savegame = Savegame::default();
+
+    let form = IFFChunk::read_from(&mut reader)?;
+    let mut form_type = [0; 4];
+
+    reader.read_exact(&mut form_type)?;
+    if &form.id != b"FORM" || &form_type != b"SCEN" {
+        return Err(anyhow!("SAV: invalid chunk ID"));
+    }
+
+    let form_end = u64::min(8 + form.size, data.len() as u64);
+
+    while reader.position() + 8 <= form_end {
+        let chunk = IFFChunk::read_from(&mut reader)?;
+        let start = reader.position() as usize;
+        let chunk_data = data
+            .get(start..start + chunk.size as usize)
+            .ok_or(anyhow!("SAV: truncated chunk"))?;
+
+        match &chunk.id {
+            b"NAME" => {
+                savegame.description = read_name(chunk_data);
+            },
+            b"INFO" => {
+                let version = u16::try_read_from::<LSB>(&mut Cursor::new(chunk_data))?;
+
+                if version != SAVEGAME_VERSION {
+                    return Err(anyhow!("SAV: unsupported version {version:#06x}"));
+                }
+                savegame.version = version;
+            },
+            b"PLYR" => {
+                for mut entry in entries("PLYR", chunk_data, HOUSE_ENTRY_SIZE)? {
+                    savegame.houses.push(read_house(&mut entry)?);
+                }
+            },
+            b"UNIT" => {
+                for mut entry in entries("UNIT", chunk_data, UNIT_ENTRY_SIZE)? {
+                    savegame.units.push(read_unit(&mut entry)?);
+                }
+            },
+            b"BLDG" => {
+                for mut entry in entries("BLDG", chunk_data, STRUCTURE_ENTRY_SIZE)? {
+                    savegame.structures.push(read_structure(&mut entry)?);
+                }
+            },
+            b"MAP " => {
+                for mut entry in entries("MAP", chunk_data, TILE_ENTRY_SIZE)? {
+                    savegame.tiles.push(read_tile(&mut entry)?);
+                }
+            },
+            // TEAM and ODUN chunks are not part of the model
+            _ => {},
+        }
+
+        reader.seek(SeekFrom::Start(start as u64 + chunk.padded_size()))?;
+    }
+
+    Ok(savegame)
+}
+
+/// Read a `_SAVE00x.DAT` savegame file.
+pub fn read_savegame_from_file<P>(
+    path: P,
+) -> Result<Savegame> where P: AsRef<path::Path> {
+    let data = fs::read(path)?;
+    read_savegame_from_data(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(entry: &mut [u8], offset: usize, value: u16) {
+        entry[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+
+        chunk.extend(id);
+        chunk.extend((data.len() as u32).to_be_bytes());
+        chunk.extend(data);
+        if !data.len().is_multiple_of(2) {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn savegame(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut data = Vec::new();
+
+        data.extend(b"FORM");
+        data.extend((body.len() as u32 + 4).to_be_bytes());
+        data.extend(b"SCEN");
+        data.extend(body);
+        data
+    }
+
+    fn info() -> Vec<u8> {
+        chunk(b"INFO", &SAVEGAME_VERSION.to_le_bytes())
+    }
+
+    fn object_entry(size: usize) -> Vec<u8> {
+        let mut entry = vec![0xee; size];
+
+        put_u16(&mut entry, 0, 7); // index
+        entry[2] = 3; // kind
+        entry[4..8].copy_from_slice(&0x0102_0304u32.to_le_bytes());
+        entry[8] = 2; // house
+        put_u16(&mut entry, 10, 0x1280);
+        put_u16(&mut entry, 12, 0x0940);
+        put_u16(&mut entry, 14, 150); // hitpoints
+        entry
+    }
+
+    #[test]
+    fn chunks_are_read() {
+        let mut house = vec![0; HOUSE_ENTRY_SIZE];
+
+        put_u16(&mut house, 0, 1);
+        put_u16(&mut house, 4, 0x0010);
+        put_u16(&mut house, 6, 12);
+        put_u16(&mut house, 8, 25);
+        put_u16(&mut house, 18, 1500);
+        put_u16(&mut house, 20, 2000);
+        put_u16(&mut house, 22, 100);
+        put_u16(&mut house, 24, 80);
+        put_u16(&mut house, 28, 3000);
+
+        let unit = object_entry(UNIT_ENTRY_SIZE);
+        let mut structure = object_entry(STRUCTURE_ENTRY_SIZE);
+
+        structure[78] = 2; // upgrade level
+        put_u16(&mut structure, 86, 400); // max hitpoints, last field
+
+        let mut tile = vec![0; TILE_ENTRY_SIZE];
+
+        put_u16(&mut tile, 0, 130);
+        tile[2..6].copy_from_slice(&(0x10u32 | (5 << 9) | (3 << 16) | (1 << 19) | (1 << 21)).to_le_bytes());
+
+        let data = savegame(&[
+            // Odd sized chunk, the next one starts after a padding byte
+            chunk(b"NAME", b"Dune\0"),
+            info(),
+            chunk(b"PLYR", &house),
+            chunk(b"UNIT", &unit),
+            chunk(b"BLDG", &structure),
+            chunk(b"TEAM", &[0; 16]),
+            chunk(b"MAP ", &tile),
+        ]);
+        let savegame = read_savegame_from_data(&data).unwrap();
+
+        assert_eq!(savegame.description, "Dune");
+        assert_eq!(savegame.version, SAVEGAME_VERSION);
+
+        let house = &savegame.houses[0];
+
+        assert_eq!(savegame.houses.len(), 1);
+        assert_eq!(
+            (house.house, house.flags, house.unit_count, house.unit_count_max),
+            (1, 0x0010, 12, 25),
+        );
+        assert_eq!((house.credits, house.credits_storage, house.credits_quota), (1500, 2000, 3000));
+        assert_eq!((house.power_production, house.power_usage), (100, 80));
+
+        for object in [&savegame.units[0].object, &savegame.structures[0].object] {
+            assert_eq!((object.index, object.kind, object.flags, object.house), (7, 3, 0x0102_0304, 2));
+            assert_eq!(object.position, Point { x: 0x1280, y: 0x0940 });
+            assert_eq!(object.hitpoints, 150);
+        }
+        assert_eq!(savegame.structures[0].upgrade_level, 2);
+        assert_eq!(savegame.structures[0].hitpoints_max, 400);
+
+        let tile = &savegame.tiles[0];
+
+        assert_eq!((tile.index, tile.ground_tile, tile.overlay_tile, tile.house), (130, 0x10, 5, 3));
+        assert!(tile.is_unveiled && !tile.has_unit && tile.has_structure);
+        assert_eq!(tile.position(), Point { x: 2, y: 2 });
+    }
+
+    #[test]
+    fn invalid_savegames_are_rejected() {
+        let invalid = [
+            // Not a scenario form
+            b"FORM\0\0\0\x04SCEX".to_vec(),
+            savegame(&[chunk(b"INFO", &0x0100u16.to_le_bytes())]),
+            // Entries of the wrong size
+            savegame(&[info(), chunk(b"UNIT", &[0; UNIT_ENTRY_SIZE - 1])]),
+            savegame(&[info(), chunk(b"MAP ", &[0; TILE_ENTRY_SIZE + 1])]),
+        ];
+
+        for data in invalid {
+            assert!(read_savegame_from_data(&data).is_err());
+        }
+
+        // Chunk size past the end of the file
+        let mut data = savegame(&[info(), chunk(b"BLDG", &[0; STRUCTURE_ENTRY_SIZE])]);
+
+        data.truncate(data.len() - 1);
+        assert!(read_savegame_from_data(&data).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use std::path;
+use std::rc::Rc;
+
+use anyhow::{anyhow, Result};
+
+use image::{imageops, ImageFormat, ImageReader, RgbaImage};
+
+use dune2_assets::prelude::{
+    ColorMatcher,
+    ColorMetric,
+    Dithering,
+    Palette,
+    RgbaBitmap,
+    Size,
+    Tile,
+};
+
+
+/// Region of a sprite sheet.
+#[derive(Clone, Copy, Debug)]
+pub struct SheetRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How a sprite sheet is sliced into tiles.
+#[derive(Clone, Debug)]
+pub enum SheetSlicing {
+    /// The whole sheet is a single tile.
+    Whole,
+    /// Tiles of the given size laid out left to right, top to bottom. If
+    /// specified only the first `count` tiles are read.
+    Grid(Size, Option<usize>),
+    /// Explicit list of tiles regions.
+    Rects(Vec<SheetRect>),
+}
+
+fn sheet_rects(
+    sheet_size: Size,
+    slicing: &SheetSlicing,
+) -> Result<Vec<SheetRect>> {
+    let rects = match slicing {
+        SheetSlicing::Whole => vec![SheetRect {
+            x: 0,
+            y: 0,
+            width: sheet_size.width,
+            height: sheet_size.height,
+        }],
+        SheetSlicing::Grid(size, count) => {
+            if size.width == 0 || size.height == 0 {
+                return Err(anyhow!("SHEET: invalid grid size {size}"));
+            }
+
+            let cols = sheet_size.width/size.width;
+            let rows = sheet_size.height/size.height;
+            let count = count.unwrap_or((cols*rows) as usize);
+
+            if count > (cols*rows) as usize {
+                return Err(anyhow!(
+                    "SHEET: grid of {cols}x{rows} tiles cannot hold {count} tiles"
+                ));
+            }
+
+            (0..count as u32).map(|index| SheetRect {
+                x: (index%cols)*size.width,
+                y: (index/cols)*size.height,
+                width: size.width,
+                height: size.height,
+            }).collect()
+        },
+        SheetSlicing::Rects(rects) => rects.clone(),
+    };
+
+    let fits = |start: u32, length: u32, bound: u32| {
+        start.checked_add(length).is_some_and(|end| end <= bound)
+    };
+
+    for rect in rects.iter() {
+        if !fits(rect.x, rect.width, sheet_size.width) || !fits(rect.y, rect.height, sheet_size.height) {
+            return Err(anyhow!(
+                "SHEET: rect {}x{} at ({}, {}) is out of the sheet bounds",
+                rect.width,
+                rect.height,
+                rect.x,
+                rect.y,
+            ));
+        }
+    }
+
+    Ok(rects)
+}
+
+fn read_tile(
+    sheet: &RgbaImage,
+    rect: &SheetRect,
+    palette: &Rc<Palette>,
+    matcher: &mut ColorMatcher,
+    dithering: Dithering,
+) -> Result<Tile> {
+    let size = Size {
+        width: rect.width,
+        height: rect.height,
+    };
+    let data = imageops::crop_imm(sheet, rect.x, rect.y, rect.width, rect.height)
+        .to_image()
+        .into_raw();
+
+    let bitmap = RgbaBitmap::from_data(size, data)?
+        .to_indexed_with_matcher(palette.clone(), matcher, dithering)?;
+
+    Ok(bitmap.to_tile())
+}
+
+/// Read a truecolor sprite sheet and quantize its tiles to the given
+/// palette. If no format is given it is guessed from the file content.
+pub fn read_tiles_from_file<P>(
+    path: P,
+    format: Option<ImageFormat>,
+    slicing: &SheetSlicing,
+    palette: &Palette,
+    metric: ColorMetric,
+    dithering: Dithering,
+) -> Result<Vec<Tile>> where P: AsRef<path::Path> {
+    let mut reader = ImageReader::open(path)?;
+
+    match format {
+        Some(format) => reader.set_format(format),
+        None => reader = reader.with_guessed_format()?,
+    };
+
+    let sheet = reader.decode()?.to_rgba8();
+    let sheet_size = Size {
+        width: sheet.width(),
+        height: sheet.height(),
+    };
+
+    let palette = Rc::new(palette.clone());
+    // Shared by the tiles, which mostly use the same colors
+    let mut matcher = palette.color_matcher(metric);
+
+    sheet_rects(sheet_size, slicing)?
+        .iter()
+        .map(|rect| read_tile(&sheet, rect, &palette, &mut matcher, dithering))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use dune2_assets::prelude::{Color, Point};
+
+    use super::*;
+
+    const SHEET_SIZE: Size = Size { width: 5, height: 4 };
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> SheetRect {
+        SheetRect { x, y, width, height }
+    }
+
+    fn positions(rects: &[SheetRect]) -> Vec<(u32, u32, u32, u32)> {
+        rects.iter().map(|rect| (rect.x, rect.y, rect.width, rect.height)).collect()
+    }
+
+    fn palette() -> Palette {
+        let mut palette = Palette::new();
+
+        palette.set_color(1, &Color::new(255, 0, 0)).unwrap();
+        palette.set_color(2, &Color::new(0, 255, 0)).unwrap();
+        palette.set_color(3, &Color::new(0, 0, 255)).unwrap();
+        palette
+    }
+
+    #[test]
+    fn slicing() {
+        let whole = sheet_rects(SHEET_SIZE, &SheetSlicing::Whole).unwrap();
+        let tile_size = Size { width: 2, height: 2 };
+        let grid = sheet_rects(SHEET_SIZE, &SheetSlicing::Grid(tile_size, None)).unwrap();
+        let first = sheet_rects(SHEET_SIZE, &SheetSlicing::Grid(tile_size, Some(3))).unwrap();
+        let rects = vec![rect(4, 0, 1, 4), rect(0, 1, 5, 3)];
+
+        assert_eq!(positions(&whole), [(0, 0, 5, 4)]);
+        // The last column of the sheet is not a whole tile
+        assert_eq!(positions(&grid), [(0, 0, 2, 2), (2, 0, 2, 2), (0, 2, 2, 2), (2, 2, 2, 2)]);
+        assert_eq!(positions(&first), positions(&grid[..3]));
+        assert_eq!(
+            positions(&sheet_rects(SHEET_SIZE, &SheetSlicing::Rects(rects.clone())).unwrap()),
+            positions(&rects),
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_frames_are_rejected() {
+        let invalid = [
+            SheetSlicing::Grid(Size { width: 2, height: 2 }, Some(5)),
+            SheetSlicing::Grid(Size { width: 0, height: 2 }, None),
+            SheetSlicing::Rects(vec![rect(0, 0, 2, 2), rect(4, 0, 2, 1)]),
+            SheetSlicing::Rects(vec![rect(0, 3, 1, 2)]),
+            SheetSlicing::Rects(vec![rect(u32::MAX, 0, 2, 1)]),
+            SheetSlicing::Rects(vec![rect(0, 1, 1, u32::MAX)]),
+        ];
+
+        for slicing in invalid {
+            assert!(sheet_rects(SHEET_SIZE, &slicing).is_err(), "{slicing:?}");
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_use_the_transparent_index() {
+        let mut palette = palette();
+
+        palette.set_transparent_index(Some(7)).unwrap();
+
+        let palette = Rc::new(palette);
+        let mut matcher = palette.color_matcher(ColorMetric::Euclidean);
+        // Second column of the second row is transparent red, the matching
+        // of the hidden color must not leak in the tile
+        let sheet = RgbaImage::from_raw(3, 2, vec![
+            0, 0, 0, 0, 255, 0, 0, 255, 0, 250, 0, 255,
+            0, 0, 250, 255, 255, 0, 0, 0, 255, 0, 0, 255,
+        ]).unwrap();
+
+        for dithering in [Dithering::None, Dithering::FloydSteinberg] {
+            let tile = read_tile(&sheet, &rect(0, 0, 3, 2), &palette, &mut matcher, dithering).unwrap();
+            let indices = (0..6)
+                .map(|i| tile.index_at(Point { x: i%3, y: i/3 }).unwrap())
+                .collect::<Vec<u8>>();
+
+            assert_eq!(indices, [7, 1, 2, 3, 7, 1]);
+        }
+    }
+
+    #[test]
+    fn sheet_file() {
+        let filepath = std::env::temp_dir().join(format!("sheet-{}.png", std::process::id()));
+        let mut sheet = RgbaImage::new(4, 2);
+
+        // Red tile then a blue tile with a transparent bottom row
+        for (x, y, pixel) in sheet.enumerate_pixels_mut() {
+            *pixel = image::Rgba(match (x, y) {
+                (0..=1, _) => [255, 0, 0, 255],
+                (_, 0) => [0, 0, 255, 255],
+                _ => [0; 4],
+            });
+        }
+        sheet.save(&filepath).unwrap();
+
+        let tiles = read_tiles_from_file(
+            &filepath,
+            None,
+            &SheetSlicing::Grid(Size { width: 2, height: 2 }, None),
+            &palette(),
+            ColorMetric::Euclidean,
+            Dithering::None,
+        );
+
+        std::fs::remove_file(&filepath).unwrap();
+
+        let tiles = tiles.unwrap();
+
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(tiles[0].index_at(Point { x: 1, y: 1 }), Some(1));
+        assert_eq!(tiles[1].index_at(Point { x: 0, y: 0 }), Some(3));
+        assert_eq!(tiles[1].index_at(Point { x: 1, y: 1 }), Some(0));
+    }
+}
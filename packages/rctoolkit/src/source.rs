@@ -5,6 +5,7 @@ use anyhow::{anyhow, Result};
 
 use dune2_assets::prelude::{
     Bitmap,
+    BlitSizePolicy,
     Color,
     TileBitmap
 };
@@ -39,7 +40,7 @@ pub fn run(args: &Args) -> Result<()> {
     let scale = args.scale.unwrap_or(1);
 
     let palette = config.load_palette()?;
-    let sources = config.load_sources()?;
+    let sources = config.load_sources(&palette)?;
 
     if args.output_dir.exists() {
         return Err(anyhow!("Output file already exists."));
@@ -58,7 +59,7 @@ pub fn run(args: &Args) -> Result<()> {
         let tile_size = tile.size();
         let filename = format!("{:01$}_{tile_size}.bmp", tile_index, tile_index_width);
 
-        let bitmap = TileBitmap::with_palette(tile, None, &palette);
+        let bitmap = TileBitmap::with_palette(tile.view(), None, &palette);
 
         let src_rect = bitmap.rect();
 
@@ -67,7 +68,7 @@ pub fn run(args: &Args) -> Result<()> {
             .build();
         let dst_rect = image.rect();
 
-        dune2_assets::bitmap::bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect);
+        dune2_assets::bitmap::bitmap_blit(&bitmap, &src_rect, &mut image, &dst_rect, BlitSizePolicy::Stretch);
         image.save(args.output_dir.join(filename))?;
     }
 
@@ -0,0 +1,219 @@
+use std::fs;
+use std::path;
+
+use anyhow::{anyhow, Result};
+
+use dune2_assets::prelude::Sound;
+
+
+const VOC_SIGNATURE: &[u8] = b"Creative Voice File\x1a";
+
+fn rate_from_divisor(divisor: u8) -> u32 {
+    1000000/(256 - divisor as u32)
+}
+
+fn u8_samples(
+    data: &[u8],
+) -> Vec<i16> {
+    data.iter().map(|sample| ((*sample as i16) - 128) << 8).collect()
+}
+
+fn i16_samples(
+    data: &[u8],
+) -> Vec<i16> {
+    data
+        .chunks_exact(2)
+        .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect()
+}
+
+// The sound takes the rate of its first block, blocks at other rates are
+// resampled to it.
+fn push_samples(
+    samples: &mut Vec<i16>,
+    sample_rate: &mut Option<u32>,
+    block_rate: u32,
+    block_samples: Vec<i16>,
+) -> Result<()> {
+    if block_rate == 0 {
+        return Err(anyhow!("VOC: invalid sample rate"));
+    }
+
+    let sample_rate = *sample_rate.get_or_insert(block_rate);
+
+    if block_rate == sample_rate {
+        samples.extend(block_samples);
+    } else {
+        let block = Sound::new(block_rate, &block_samples).resample(sample_rate);
+
+        samples.extend_from_slice(block.samples());
+    }
+    Ok(())
+}
+
+fn read_sound_from_data(
+    data: &[u8],
+) -> Result<Sound> {
+    if !data.starts_with(VOC_SIGNATURE) || data.len() < 26 {
+        return Err(anyhow!("VOC: invalid signature"));
+    }
+
+    let mut position = u16::from_le_bytes([data[20], data[21]]) as usize;
+    let mut sample_rate = None;
+    let mut extended_rate = None;
+    let mut block_rate = None;
+    let mut sixteen_bits = false;
+    let mut samples = Vec::new();
+
+    while let Some(kind) = data.get(position).copied() {
+        if kind == 0 {
+            break;
+        }
+
+        let header = data
+            .get(position + 1..position + 4)
+            .ok_or(anyhow!("VOC: truncated block"))?;
+        let size = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        let block = data
+            .get(position + 4..position + 4 + size)
+            .ok_or(anyhow!("VOC: truncated block"))?;
+
+        position += 4 + size;
+
+        match kind {
+            // Sound data
+            1 if block.len() >= 2 => {
+                if block[1] != 0 {
+                    return Err(anyhow!("VOC: unsupported codec {}", block[1]));
+                }
+
+                let rate = extended_rate.take().unwrap_or(rate_from_divisor(block[0]));
+
+                block_rate = Some(rate);
+                sixteen_bits = false;
+                push_samples(&mut samples, &mut sample_rate, rate, u8_samples(&block[2..]))?;
+            },
+            // Sound continuation, at the rate of the previous sound data
+            2 => {
+                let rate = block_rate.ok_or(anyhow!("VOC: continuation without sound data"))?;
+                let block_samples = if sixteen_bits {
+                    i16_samples(block)
+                } else {
+                    u8_samples(block)
+                };
+
+                push_samples(&mut samples, &mut sample_rate, rate, block_samples)?;
+            },
+            // Silence
+            3 if block.len() >= 3 => {
+                let count = u16::from_le_bytes([block[0], block[1]]) as usize + 1;
+                let rate = rate_from_divisor(block[2]);
+
+                push_samples(&mut samples, &mut sample_rate, rate, vec![0; count])?;
+            },
+            // Extended, sets the rate of the next sound data block
+            8 if block.len() >= 4 => {
+                let time_constant = u16::from_le_bytes([block[0], block[1]]) as u32;
+                let channels = block[3] as u32 + 1;
+
+                extended_rate = Some(256000000/(channels*(65536 - time_constant)));
+            },
+            // Sound data in the new format
+            9 if block.len() >= 12 => {
+                let rate = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+                let bits = block[4];
+                let channels = block[5];
+                let codec = u16::from_le_bytes([block[6], block[7]]);
+
+                if channels != 1 {
+                    return Err(anyhow!("VOC: unsupported channel count {channels}"));
+                }
+
+                let block_samples = match (bits, codec) {
+                    (8, 0) => {
+                        sixteen_bits = false;
+                        u8_samples(&block[12..])
+                    },
+                    (16, 4) => {
+                        sixteen_bits = true;
+                        i16_samples(&block[12..])
+                    },
+                    _ => {
+                        return Err(anyhow!("VOC: unsupported codec {codec}"));
+                    },
+                };
+
+                block_rate = Some(rate);
+                push_samples(&mut samples, &mut sample_rate, rate, block_samples)?;
+            },
+            // Markers, text, repeat and invalid blocks are ignored
+            _ => {},
+        }
+    }
+
+    let sample_rate = sample_rate.ok_or(anyhow!("VOC: no sound data"))?;
+
+    Ok(Sound::new(sample_rate, &samples))
+}
+
+/// Read and decode a Creative Voice File.
+pub fn read_sound_from_file<P>(
+    path: P,
+) -> Result<Sound> where P: AsRef<path::Path> {
+    let data = fs::read(path)?;
+    read_sound_from_data(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voc(
+        blocks: &[(u8, &[u8])],
+    ) -> Vec<u8> {
+        let mut data = VOC_SIGNATURE.to_vec();
+
+        data.extend_from_slice(&[26, 0, 0x0a, 0x01, 0x29, 0x11]);
+        for (kind, block) in blocks {
+            data.push(*kind);
+            data.extend_from_slice(&(block.len() as u32).to_le_bytes()[..3]);
+            data.extend_from_slice(block);
+        }
+        data.push(0);
+        data
+    }
+
+    #[test]
+    fn blocks_are_concatenated() {
+        // 256 - 1000000/10000 = 156
+        let data = voc(&[
+            (1, &[156, 0, 128, 192]),
+            (2, &[64]),
+            (3, &[1, 0, 156]),
+        ]);
+        let sound = read_sound_from_data(&data).unwrap();
+
+        assert_eq!(sound.sample_rate(), 10000);
+        assert_eq!(sound.samples(), &[0, 64 << 8, -64 << 8, 0, 0]);
+    }
+
+    #[test]
+    fn blocks_at_other_rates_are_resampled() {
+        // 256 - 1000000/5000 = 56
+        let data = voc(&[
+            (1, &[156, 0, 128, 128]),
+            (1, &[56, 0, 160, 160]),
+            (2, &[160]),
+        ]);
+        let sound = read_sound_from_data(&data).unwrap();
+
+        assert_eq!(sound.sample_rate(), 10000);
+        assert_eq!(sound.samples().len(), 2 + 6);
+        assert!(sound.samples()[2..].iter().all(|sample| *sample == 32 << 8));
+    }
+
+    #[test]
+    fn continuation_without_sound_data_is_rejected() {
+        assert!(read_sound_from_data(&voc(&[(2, &[128])])).is_err());
+    }
+}
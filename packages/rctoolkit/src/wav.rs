@@ -0,0 +1,34 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+
+/// Write mono 16-bit PCM samples as a RIFF WAVE stream.
+pub fn write_pcm16_mono(
+    writer: &mut impl Write,
+    sample_rate: u32,
+    samples: &[i16],
+) -> Result<()> {
+    let data_size = 2*samples.len() as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(2*sample_rate).to_le_bytes())?; // byte rate
+    writer.write_all(&2u16.to_le_bytes())?; // block align
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples.iter() {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,485 @@
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path;
+
+use anyhow::{anyhow, Result};
+
+use crate::io::*;
+
+
+// XMI sequences are played by the AIL driver at a fixed rate of 120 ticks per
+// second. Using a 60 PPQN division with a 500000µs quarter note gives the
+// exact same tick duration in the generated Standard MIDI File.
+const SMF_DIVISION: u16 = 60;
+const SMF_TEMPO: u32 = 500000;
+
+struct IFFChunk {
+    id: [u8; 4],
+    size: u64,
+}
+
+impl IFFChunk {
+    fn read_from(
+        reader: &mut impl Read,
+    ) -> Result<IFFChunk> {
+        let mut id = [0; 4];
+
+        reader.read_exact(&mut id)?;
+
+        let size = u32::try_read_from::<MSB>(reader)? as u64;
+
+        Ok(Self { id, size })
+    }
+
+    fn padded_size(&self) -> u64 {
+        self.size + (self.size & 1)
+    }
+}
+
+fn check_form_type(
+    reader: &mut impl Read,
+    value: &[u8],
+) -> Result<()> {
+    let mut buf = [0; 4];
+
+    reader.read_exact(&mut buf)?;
+    if buf != value {
+        return Err(anyhow!("XMI: invalid form type"));
+    }
+    Ok(())
+}
+
+/******************************************************************************
+ * XMI events
+ *****************************************************************************/
+
+struct MidiEvent {
+    time: u32,
+    data: Vec<u8>,
+    // Order of the event among the events scheduled at the same time
+    rank: u8,
+}
+
+impl MidiEvent {
+    fn new(
+        time: u32,
+        data: Vec<u8>,
+    ) -> Self {
+        Self { time, data, rank: 1 }
+    }
+
+    // Note-off events are sent before any other event scheduled at the same
+    // time so a note retriggered right after its end is not cut, except the
+    // note-off of a zero duration note which must follow its own note-on.
+    fn note_off(
+        time: u32,
+        channel: u8,
+        note: u8,
+        duration: u32,
+    ) -> Self {
+        Self {
+            time: time + duration,
+            data: vec![0x80 | channel, note, 0x40],
+            rank: if duration == 0 { 2 } else { 0 },
+        }
+    }
+}
+
+fn read_xmi_delay(
+    reader: &mut Cursor<&[u8]>,
+) -> u32 {
+    let data = *reader.get_ref();
+    let mut position = reader.position() as usize;
+    let mut delay = 0;
+
+    // XMI delays are the sum of all the bytes preceding the next status byte
+    while let Some(value) = data.get(position).filter(|value| *value & 0x80 == 0) {
+        delay += *value as u32;
+        position += 1;
+    }
+
+    reader.set_position(position as u64);
+    delay
+}
+
+fn read_vlq(
+    reader: &mut impl Read,
+) -> Result<u32> {
+    let mut value = 0;
+
+    for _ in 0..4 {
+        let byte = u8::try_read_from::<LSB>(reader)?;
+
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(anyhow!("XMI: invalid variable length quantity"))
+}
+
+fn write_vlq(
+    output: &mut Vec<u8>,
+    value: u32,
+) {
+    let mut buf = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        buf.push(((value & 0x7f) as u8) | 0x80);
+        value >>= 7;
+    }
+
+    output.extend(buf.iter().rev());
+}
+
+fn read_bytes(
+    reader: &mut Cursor<&[u8]>,
+    count: usize,
+) -> Result<Vec<u8>> {
+    // Sizes come from the file, check them before allocating anything
+    let remaining = reader.get_ref().len().saturating_sub(reader.position() as usize);
+
+    if count > remaining {
+        return Err(anyhow!("XMI: unexpected end of data"));
+    }
+
+    let mut buf = vec![0; count];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_events(
+    evnt_data: &[u8],
+) -> Result<Vec<MidiEvent>> {
+    let mut reader = Cursor::new(evnt_data);
+    let mut events = Vec::new();
+    let mut time = 0;
+
+    while (reader.position() as usize) < evnt_data.len() {
+        time += read_xmi_delay(&mut reader);
+
+        if reader.position() as usize >= evnt_data.len() {
+            break;
+        }
+
+        let status = u8::try_read_from::<LSB>(&mut reader)?;
+
+        match status & 0xf0 {
+            0x80 | 0xa0 | 0xb0 | 0xe0 => {
+                let mut data = vec![status];
+
+                data.append(&mut read_bytes(&mut reader, 2)?);
+                events.push(MidiEvent::new(time, data));
+            },
+            0xc0 | 0xd0 => {
+                let mut data = vec![status];
+
+                data.append(&mut read_bytes(&mut reader, 1)?);
+                events.push(MidiEvent::new(time, data));
+            },
+            0x90 => {
+                // XMI note-on events carry their duration, there is no
+                // explicit note-off event in the stream.
+                let note = u8::try_read_from::<LSB>(&mut reader)?;
+                let velocity = u8::try_read_from::<LSB>(&mut reader)?;
+                let duration = read_vlq(&mut reader)?;
+
+                events.push(MidiEvent::new(time, vec![status, note, velocity]));
+                events.push(MidiEvent::note_off(time, status & 0x0f, note, duration));
+            },
+            _ if status == 0xff => {
+                let kind = u8::try_read_from::<LSB>(&mut reader)?;
+                let size = read_vlq(&mut reader)?;
+                let payload = read_bytes(&mut reader, size as usize)?;
+
+                match kind {
+                    // End of track, the SMF writer emits its own.
+                    0x2f => break,
+                    // Tempo changes are ignored by the AIL driver.
+                    0x51 => {},
+                    _ => {
+                        let mut data = vec![status, kind];
+
+                        write_vlq(&mut data, size);
+                        data.extend(payload);
+                        events.push(MidiEvent::new(time, data));
+                    },
+                }
+            },
+            _ if status == 0xf0 || status == 0xf7 => {
+                let size = read_vlq(&mut reader)?;
+                let mut data = vec![status];
+
+                write_vlq(&mut data, size);
+                data.append(&mut read_bytes(&mut reader, size as usize)?);
+                events.push(MidiEvent::new(time, data));
+            },
+            _ => {
+                return Err(anyhow!("XMI: invalid event status {status:#04x}"));
+            },
+        }
+    }
+
+    // Stable sort, events of the same rank keep the order of the stream
+    events.sort_by_key(|event| (event.time, event.rank));
+
+    Ok(events)
+}
+
+/******************************************************************************
+ * Standard MIDI File
+ *****************************************************************************/
+
+fn write_smf(
+    events: &[MidiEvent],
+) -> Vec<u8> {
+    let mut track = Vec::new();
+
+    // Initial tempo
+    write_vlq(&mut track, 0);
+    track.extend([0xff, 0x51, 0x03]);
+    track.extend(&SMF_TEMPO.to_be_bytes()[1..]);
+
+    let mut time = 0;
+
+    for event in events.iter() {
+        write_vlq(&mut track, event.time - time);
+        track.extend(&event.data);
+        time = event.time;
+    }
+
+    // End of track
+    write_vlq(&mut track, 0);
+    track.extend([0xff, 0x2f, 0x00]);
+
+    let mut smf = Vec::with_capacity(track.len() + 22);
+
+    smf.extend(b"MThd");
+    smf.extend(6u32.to_be_bytes());
+    smf.extend(0u16.to_be_bytes()); // format 0
+    smf.extend(1u16.to_be_bytes()); // one track
+    smf.extend(SMF_DIVISION.to_be_bytes());
+
+    smf.extend(b"MTrk");
+    smf.extend((track.len() as u32).to_be_bytes());
+    smf.extend(track);
+
+    smf
+}
+
+/******************************************************************************
+ * XMI sequences
+ *****************************************************************************/
+
+fn read_xmid_form(
+    reader: &mut Cursor<&[u8]>,
+    form_size: u64,
+) -> Result<Vec<u8>> {
+    let form_end = reader.position() + form_size;
+
+    check_form_type(reader, b"XMID")?;
+
+    while reader.position() < form_end {
+        let chunk = IFFChunk::read_from(reader)?;
+
+        if &chunk.id == b"EVNT" {
+            let evnt_data = read_bytes(reader, chunk.size as usize)?;
+            let events = read_events(&evnt_data)?;
+
+            reader.seek(SeekFrom::Start(form_end))?;
+
+            return Ok(write_smf(&events));
+        }
+
+        // TIMB and RBRN chunks are not needed by the SMF output
+        reader.seek(SeekFrom::Current(chunk.padded_size() as i64))?;
+    }
+
+    Err(anyhow!("XMI: missing EVNT chunk"))
+}
+
+fn read_sequences_from_data(
+    data: &[u8],
+) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Cursor::new(data);
+    let mut sequences = Vec::new();
+
+    let form = IFFChunk::read_from(&mut reader)?;
+
+    if &form.id != b"FORM" {
+        return Err(anyhow!("XMI: invalid chunk ID"));
+    }
+
+    // Files holding a single sequence may omit the XDIR header
+    if data.get(8..12) == Some(b"XMID") {
+        sequences.push(read_xmid_form(&mut reader, form.padded_size())?);
+        return Ok(sequences);
+    }
+
+    check_form_type(&mut reader, b"XDIR")?;
+
+    let info = IFFChunk::read_from(&mut reader)?;
+
+    if &info.id != b"INFO" {
+        return Err(anyhow!("XMI: invalid chunk ID"));
+    }
+
+    let sequence_count = u16::try_read_from::<LSB>(&mut reader)? as usize;
+
+    reader.seek(SeekFrom::Start(8 + form.padded_size()))?;
+
+    let cat = IFFChunk::read_from(&mut reader)?;
+
+    if &cat.id != b"CAT " {
+        return Err(anyhow!("XMI: invalid chunk ID"));
+    }
+
+    check_form_type(&mut reader, b"XMID")?;
+
+    for _ in 0..sequence_count {
+        let form = IFFChunk::read_from(&mut reader)?;
+
+        if &form.id != b"FORM" {
+            return Err(anyhow!("XMI: invalid chunk ID"));
+        }
+
+        sequences.push(read_xmid_form(&mut reader, form.padded_size())?);
+    }
+
+    Ok(sequences)
+}
+
+/// Read all the sequences of an XMI file and convert each one of them to a
+/// Standard MIDI File.
+pub fn read_sequences_from_file<P>(
+    path: P,
+) -> Result<Vec<Vec<u8>>> where P: AsRef<path::Path> {
+    let data = fs::read(path)?;
+    read_sequences_from_data(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smf_track(smf: &[u8]) -> &[u8] {
+        assert_eq!(&smf[0..4], b"MThd");
+        assert_eq!(&smf[14..18], b"MTrk");
+        &smf[22..]
+    }
+
+    fn xmi_form(evnt: &[u8]) -> Vec<u8> {
+        let mut form = Vec::new();
+
+        form.extend(b"XMID");
+        form.extend(b"EVNT");
+        form.extend((evnt.len() as u32).to_be_bytes());
+        form.extend(evnt);
+        if !evnt.len().is_multiple_of(2) {
+            form.push(0);
+        }
+
+        let mut data = Vec::new();
+
+        data.extend(b"FORM");
+        data.extend((form.len() as u32).to_be_bytes());
+        data.extend(form);
+        data
+    }
+
+    #[test]
+    fn delays_are_summed() {
+        // Three delay bytes before a program change
+        let events = read_events(&[0x10, 0x20, 0x7f, 0xc0, 0x05]).unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].time, 0x10 + 0x20 + 0x7f);
+        assert_eq!(events[0].data, [0xc0, 0x05]);
+    }
+
+    #[test]
+    fn note_on_durations_produce_sorted_note_offs() {
+        let events = read_events(&[
+            // t=48, channel 0, duration 128 as a two bytes VLQ
+            0x10, 0x20, 0x90, 0x3c, 0x64, 0x81, 0x00,
+            // t=53, channel 1, duration 10
+            0x05, 0x91, 0x40, 0x50, 0x0a,
+        ]).unwrap();
+        let events = events
+            .iter()
+            .map(|event| (event.time, event.data.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(events, [
+            (48, vec![0x90, 0x3c, 0x64]),
+            (53, vec![0x91, 0x40, 0x50]),
+            (63, vec![0x81, 0x40, 0x40]),
+            (176, vec![0x80, 0x3c, 0x40]),
+        ]);
+    }
+
+    #[test]
+    fn note_offs_come_first_at_the_same_time() {
+        let events = read_events(&[
+            0x90, 0x3c, 0x64, 0x0a,
+            0x0a, 0x90, 0x3c, 0x64, 0x0a,
+        ]).unwrap();
+
+        assert_eq!(events[1].time, 10);
+        assert_eq!(events[1].data[0], 0x80);
+        assert_eq!(events[2].time, 10);
+        assert_eq!(events[2].data[0], 0x90);
+    }
+
+    #[test]
+    fn zero_duration_notes_end_after_they_start() {
+        let events = read_events(&[
+            // A note ending at t=10, a zero duration note at t=10 and a
+            // program change at the same time
+            0x90, 0x3c, 0x64, 0x0a,
+            0x0a, 0x90, 0x3e, 0x64, 0x00,
+            0xc0, 0x05,
+        ]).unwrap();
+        let events = events
+            .iter()
+            .map(|event| (event.time, event.data.clone()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(events, [
+            (0, vec![0x90, 0x3c, 0x64]),
+            (10, vec![0x80, 0x3c, 0x40]),
+            (10, vec![0x90, 0x3e, 0x64]),
+            (10, vec![0xc0, 0x05]),
+            (10, vec![0x80, 0x3e, 0x40]),
+        ]);
+    }
+
+    #[test]
+    fn smf_output() {
+        let data = xmi_form(&[0x90, 0x3c, 0x64, 0x81, 0x00, 0xff, 0x2f, 0x00]);
+        let sequences = read_sequences_from_data(&data).unwrap();
+
+        assert_eq!(sequences.len(), 1);
+
+        let smf = &sequences[0];
+
+        assert_eq!(&smf[8..14], [0, 0, 0, 1, 0, SMF_DIVISION as u8]);
+        assert_eq!(smf_track(smf), [
+            // Tempo
+            0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+            0x00, 0x90, 0x3c, 0x64,
+            0x81, 0x00, 0x80, 0x3c, 0x40,
+            // End of track
+            0x00, 0xff, 0x2f, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        // Meta event claiming a 0x0fffffff bytes payload
+        assert!(read_events(&[0xff, 0x01, 0xff, 0xff, 0xff, 0x7f]).is_err());
+        assert!(read_sequences_from_data(&xmi_form(&[0xf0, 0x8f, 0xff, 0xff, 0x7f])).is_err());
+    }
+}